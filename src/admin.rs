@@ -0,0 +1,612 @@
+use std::cmp;
+use std::io::timer;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use client::KafkaClient;
+use protocol::*;
+use protocol::metadata::v1::{MetadataRequestV1, MetadataResponseV1};
+use types::*;
+
+#[deriving(Show, Clone)]
+pub struct AdminConfig {
+    /// How long the controller may take to carry out an operation, such as
+    /// waiting for a new topic's partitions to get leaders, before it
+    /// answers with `RequestTimedOut`. The client waits for that answer for
+    /// no longer than the connection's `read_timeout`, so the controller is
+    /// given at most a second less than that.
+    pub operation_timeout: Duration,
+    /// How many times a request is retried against a newly looked up
+    /// controller, after the last one turned out not to be it.
+    pub max_controller_retries: uint,
+    pub retry_backoff: Duration
+}
+
+impl AdminConfig {
+    pub fn new() -> AdminConfig {
+        AdminConfig {
+            operation_timeout: Duration::seconds(20),
+            max_controller_retries: 3,
+            retry_backoff: Duration::milliseconds(100)
+        }
+    }
+}
+
+/// A topic to create. Either give counts with `new`, or give each
+/// partition's replicas with `with_replica_assignment`.
+#[deriving(Show, PartialEq, Eq, Clone)]
+pub struct NewTopic {
+    pub name: String,
+    pub num_partitions: i32,
+    pub replication_factor: i16,
+    /// Each partition with its replicas, the first being the preferred leader.
+    pub replica_assignment: Vec<(i32, Vec<i32>)>,
+    /// Topic-level settings such as `cleanup.policy`, overriding the broker
+    /// defaults.
+    pub configs: Vec<(String, String)>
+}
+
+impl NewTopic {
+    pub fn new(name: &str, num_partitions: i32, replication_factor: i16) -> NewTopic {
+        NewTopic {
+            name: String::from_str(name),
+            num_partitions: num_partitions,
+            replication_factor: replication_factor,
+            replica_assignment: vec![],
+            configs: vec![]
+        }
+    }
+
+    pub fn with_replica_assignment(name: &str, replica_assignment: Vec<(i32, Vec<i32>)>) -> NewTopic {
+        NewTopic {
+            name: String::from_str(name),
+            num_partitions: FROM_ASSIGNMENT,
+            replication_factor: FROM_ASSIGNMENT as i16,
+            replica_assignment: replica_assignment,
+            configs: vec![]
+        }
+    }
+
+    pub fn with_config(mut self, name: &str, value: &str) -> NewTopic {
+        self.configs.push((String::from_str(name), String::from_str(value)));
+        self
+    }
+}
+
+/// Partitions to add to an existing topic.
+#[deriving(Show, PartialEq, Eq, Clone)]
+pub struct NewPartitions {
+    pub topic: String,
+    /// The topic's partition count afterwards, not the number to add.
+    pub total_count: i32,
+    /// The replicas of each added partition, in order, leaving placement to
+    /// the controller when `None`.
+    pub assignment: Option<Vec<Vec<i32>>>
+}
+
+impl NewPartitions {
+    pub fn increase_to(topic: &str, total_count: i32) -> NewPartitions {
+        NewPartitions { topic: String::from_str(topic), total_count: total_count, assignment: None }
+    }
+
+    /// Places the added partitions, so `assignment` needs one replica list
+    /// for each of them.
+    pub fn with_assignment(mut self, assignment: Vec<Vec<i32>>) -> NewPartitions {
+        self.assignment = Some(assignment);
+        self
+    }
+}
+
+/// Something with configuration of its own.
+#[deriving(Show, PartialEq, Eq, Clone)]
+pub enum ConfigResource {
+    TopicConfigResource(String),
+    /// A broker, by node id.
+    BrokerConfigResource(i32)
+}
+
+impl ConfigResource {
+    fn key(&self) -> (i8, String) {
+        match *self {
+            TopicConfigResource(ref topic) => (TOPIC_RESOURCE, topic.clone()),
+            BrokerConfigResource(node_id) => (BROKER_RESOURCE, node_id.to_string())
+        }
+    }
+
+    /// The broker a request about this resource has to go to, since only a
+    /// broker itself knows its configuration; any broker knows a topic's.
+    fn broker(&self) -> Option<i32> {
+        match *self {
+            TopicConfigResource(_) => None,
+            BrokerConfigResource(node_id) => Some(node_id)
+        }
+    }
+}
+
+/// One setting of a topic or broker.
+#[deriving(Show, PartialEq, Eq, Clone)]
+pub struct ConfigEntry {
+    pub name: String,
+    /// `None` for sensitive settings, which brokers don't give out.
+    pub value: Option<String>,
+    /// Whether the setting can only be changed in the broker's config file.
+    pub read_only: bool,
+    /// Whether the setting hasn't been overridden.
+    pub is_default: bool,
+    pub is_sensitive: bool
+}
+
+/// Each topic's outcome, for operations which may succeed for some topics
+/// and fail for others.
+pub type TopicResults = Vec<(String, KafkaResult<()>)>;
+
+/// Each resource's outcome for `AdminClient::describe_configs` and
+/// `alter_configs`.
+pub type ConfigResults<T> = Vec<(ConfigResource, KafkaResult<T>)>;
+
+/// Each partition's low watermark after `AdminClient::delete_records`.
+pub type DeleteRecordsResults = Vec<(TopicPartition, KafkaResult<i64>)>;
+
+/// Creates, changes and deletes topics, on top of a `KafkaClient`. Requests
+/// only the controller can answer are sent to it, found through metadata,
+/// and retried against a new one when the controller moves.
+pub struct AdminClient {
+    client: KafkaClient,
+    config: AdminConfig,
+    controller: Mutex<Option<(String, u16)>>
+}
+
+impl AdminClient {
+    pub fn new(client: KafkaClient) -> AdminClient {
+        AdminClient::with_config(client, AdminConfig::new())
+    }
+
+    pub fn with_config(client: KafkaClient, config: AdminConfig) -> AdminClient {
+        AdminClient { client: client, config: config, controller: Mutex::new(None) }
+    }
+
+    pub fn client(&self) -> &KafkaClient {
+        &self.client
+    }
+
+    /// Creates one topic, failing with the broker's error if it can't be
+    /// created, e.g. `TopicAlreadyExistsCode`.
+    pub fn create_topic(&self, topic: &NewTopic) -> KafkaResult<()> {
+        let results = try!(self.create_topics(&[topic.clone()], false));
+        match results.into_iter().next() {
+            Some((_, result)) => result,
+            None => fail!((MalformedResponseError, "CreateTopics response is missing the requested topic"))
+        }
+    }
+
+    /// Creates the topics, giving each one's outcome, since some may be
+    /// created while others fail. With `validate_only` the controller only
+    /// checks that the topics could be created.
+    pub fn create_topics(&self, topics: &[NewTopic], validate_only: bool) -> KafkaResult<TopicResults> {
+        let request = create_request(topics, self.timeout_ms(), validate_only);
+        self.send_to_controller(request, |response: CreateTopicsResponse| {
+            topic_results(response.topic_errors.into_iter().map(|topic_error| {
+                (topic_error.topic, topic_error.error_code, topic_error.error_message)
+            }).collect())
+        })
+    }
+
+    /// Grows one topic, failing with the broker's error if it can't be
+    /// grown, e.g. `InvalidPartitionsCode` when it already has as many.
+    pub fn create_partitions_for(&self, partitions: &NewPartitions) -> KafkaResult<()> {
+        let results = try!(self.create_partitions(&[partitions.clone()], false));
+        match results.into_iter().next() {
+            Some((_, result)) => result,
+            None => fail!((MalformedResponseError, "CreatePartitions response is missing the requested topic"))
+        }
+    }
+
+    /// Grows the topics, giving each one's outcome. Records keyed to a
+    /// partition by hash will be keyed to a different one afterwards.
+    pub fn create_partitions(&self, partitions: &[NewPartitions], validate_only: bool)
+                             -> KafkaResult<TopicResults> {
+        let request = create_partitions_request(partitions, self.timeout_ms(), validate_only);
+        self.send_to_controller(request, |response: CreatePartitionsResponse| {
+            topic_results(response.topic_errors.into_iter().map(|topic_error| {
+                (topic_error.topic, topic_error.error_code, topic_error.error_message)
+            }).collect())
+        })
+    }
+
+    /// Deletes one topic, failing with the broker's error if it can't be
+    /// deleted.
+    pub fn delete_topic(&self, topic: &str) -> KafkaResult<()> {
+        let results = try!(self.delete_topics(&[topic]));
+        match results.into_iter().next() {
+            Some((_, result)) => result,
+            None => fail!((MalformedResponseError, "DeleteTopics response is missing the requested topic"))
+        }
+    }
+
+    /// Deletes the topics, giving each one's outcome. Brokers only delete
+    /// topics when `delete.topic.enable` is set.
+    pub fn delete_topics(&self, topics: &[&str]) -> KafkaResult<TopicResults> {
+        let request = DeleteTopicsRequest {
+            topics: topics.iter().map(|topic| String::from_str(*topic)).collect(),
+            timeout: self.timeout_ms()
+        };
+        self.send_to_controller(request, |response: DeleteTopicsResponse| {
+            topic_results(response.topic_error_codes.into_iter().map(|topic_error| {
+                (topic_error.topic, topic_error.error_code, None)
+            }).collect())
+        })
+    }
+
+    /// Deletes the records before each offset, or before the high watermark
+    /// for `HIGH_WATERMARK`, giving each partition's new low watermark.
+    /// Each partition's leader is asked in turn.
+    pub fn delete_records(&self, offsets: &[(TopicPartition, i64)]) -> DeleteRecordsResults {
+        offsets.iter().map(|&(ref partition, offset)| {
+            let request = delete_records_request(partition, offset, self.timeout_ms());
+            let result = self.client.send_to_leader(partition, request, |response: DeleteRecordsResponse| {
+                response.low_watermark(partition.topic.as_slice(), partition.partition)
+            });
+            (partition.clone(), result)
+        }).collect()
+    }
+
+    /// Every setting of each resource, giving each one's outcome.
+    pub fn describe_configs(&self, resources: &[ConfigResource]) -> KafkaResult<ConfigResults<Vec<ConfigEntry>>> {
+        let mut described = vec![];
+        for (broker, batch) in config_batches(resources).into_iter() {
+            let request = DescribeConfigsRequest {
+                resources: batch.iter().map(|resource| {
+                    let (resource_type, resource_name) = resource.key();
+                    DescribeConfigsResource { resource_type: resource_type, resource_name: resource_name,
+                                              config_names: None }
+                }).collect()
+            };
+            let response: DescribeConfigsResponse = try!(self.send_for_resource(broker, request));
+            for result in response.resources.into_iter() {
+                let DescribeConfigsResult { error_code, error_message, resource_type, resource_name,
+                                            config_entries } = result;
+                let resource = try!(config_resource(resource_type, resource_name));
+                let entries = resource_result(error_code, error_message).map(|()| {
+                    config_entries.iter().map(config_entry).collect()
+                });
+                described.push((resource, entries));
+            }
+        }
+        Ok(described)
+    }
+
+    /// Sets each resource's configuration, giving each one's outcome. This
+    /// replaces the whole configuration, so settings left out go back to
+    /// their defaults: pass back the overrides from `describe_configs` that
+    /// should stay. With `validate_only` the changes are only checked.
+    pub fn alter_configs(&self, configs: &[(ConfigResource, Vec<(String, String)>)], validate_only: bool)
+                         -> KafkaResult<ConfigResults<()>> {
+        let resources: Vec<ConfigResource> = configs.iter().map(|&(ref resource, _)| resource.clone()).collect();
+        let mut altered = vec![];
+        for (broker, batch) in config_batches(resources.as_slice()).into_iter() {
+            let request = AlterConfigsRequest {
+                resources: batch.iter().map(|resource| {
+                    let (resource_type, resource_name) = resource.key();
+                    let entries = configs.iter().find(|&&(ref configured, _)| configured == resource)
+                        .map(|&(_, ref entries)| entries.as_slice()).unwrap();
+                    AlterConfigsResource {
+                        resource_type: resource_type,
+                        resource_name: resource_name,
+                        config_entries: entries.iter().map(|&(ref name, ref value)| AlterConfigsEntry {
+                            config_name: name.clone(),
+                            config_value: Some(value.clone())
+                        }).collect()
+                    }
+                }).collect(),
+                validate_only: if validate_only { 1 } else { 0 }
+            };
+            let response: AlterConfigsResponse = try!(self.send_for_resource(broker, request));
+            for result in response.resources.into_iter() {
+                let resource = try!(config_resource(result.resource_type, result.resource_name));
+                altered.push((resource, resource_result(result.error_code, result.error_message)));
+            }
+        }
+        Ok(altered)
+    }
+
+    fn timeout_ms(&self) -> i32 {
+        operation_timeout_ms(self.config.operation_timeout, self.client.config().connection.read_timeout)
+    }
+
+    /// The `host:port` of the controller, as last looked up.
+    pub fn controller(&self) -> KafkaResult<(String, u16)> {
+        match *self.controller.lock() {
+            Some(ref controller) => return Ok(controller.clone()),
+            None => ()
+        }
+
+        let request = MetadataRequestV1 { topic_names: Some(vec![]) };
+        let response: MetadataResponseV1 = try!(self.client.send_to_any(request));
+        let controller = match response.controller() {
+            Some(broker) => (broker.host.clone(), broker.port as u16),
+            None => fail!(NotControllerCode)
+        };
+        *self.controller.lock() = Some(controller.clone());
+        Ok(controller)
+    }
+
+    /// Sends a request to the controller. `check` turns the response into
+    /// the result, failing with `NotControllerCode` if the broker said it
+    /// isn't the controller, in which case the controller is looked up again
+    /// and the request retried, up to `max_controller_retries` times. So is
+    /// a failure to connect. A connection failing once the request is on its
+    /// way isn't retried, since the controller may have carried it out: the
+    /// transport error is returned, and whether the topics changed has to be
+    /// checked before trying again.
+    fn send_to_controller<T:Request + Clone, U:Response, V>(&self, request: T,
+                                                           check: |U| -> KafkaResult<V>) -> KafkaResult<V> {
+        let mut attempt = 0;
+        loop {
+            let result = match self.controller() {
+                Ok((host, port)) => match self.client.send_to_host(host.as_slice(), port, request.clone()) {
+                    Ok(response) => check(response),
+                    Err(err) => Err(err)
+                },
+                Err(err) => Err(err)
+            };
+
+            match result {
+                Err(ref err) if is_controller_error(err) && attempt < self.config.max_controller_retries => (),
+                _ => return result
+            }
+
+            attempt += 1;
+            *self.controller.lock() = None;
+            timer::sleep(self.config.retry_backoff);
+        }
+    }
+
+    fn send_for_resource<T:Request + Clone, U:Response>(&self, broker: Option<i32>, request: T) -> KafkaResult<U> {
+        match broker {
+            Some(node_id) => {
+                if self.client.cluster().is_none() {
+                    try!(self.client.load_metadata(vec![]));
+                }
+                self.client.send_to_broker(node_id, request)
+            },
+            None => self.client.send_to_any(request)
+        }
+    }
+}
+
+// The operation timeout to ask the controller for, a second short of the
+// read timeout so its `RequestTimedOut` arrives before the client gives up
+fn operation_timeout_ms(timeout: Duration, read_timeout: Option<Duration>) -> i32 {
+    let timeout = match read_timeout {
+        Some(read_timeout) => cmp::min(timeout, cmp::max(read_timeout - Duration::seconds(1), Duration::zero())),
+        None => timeout
+    };
+    timeout.num_milliseconds() as i32
+}
+
+fn create_request(topics: &[NewTopic], timeout_ms: i32, validate_only: bool) -> CreateTopicsRequest {
+    CreateTopicsRequest {
+        create_topic_requests: topics.iter().map(|topic| CreateTopicsTopic {
+            topic: topic.name.clone(),
+            num_partitions: topic.num_partitions,
+            replication_factor: topic.replication_factor,
+            replica_assignment: topic.replica_assignment.iter().map(|&(partition, ref replicas)| {
+                CreateTopicsReplicaAssignment { partition: partition, replicas: replicas.clone() }
+            }).collect(),
+            config_entries: topic.configs.iter().map(|&(ref name, ref value)| CreateTopicsConfigEntry {
+                config_name: name.clone(),
+                config_value: Some(value.clone())
+            }).collect()
+        }).collect(),
+        timeout: timeout_ms,
+        validate_only: if validate_only { 1 } else { 0 }
+    }
+}
+
+fn create_partitions_request(partitions: &[NewPartitions], timeout_ms: i32, validate_only: bool)
+                             -> CreatePartitionsRequest {
+    CreatePartitionsRequest {
+        topic_partitions: partitions.iter().map(|partitions| CreatePartitionsTopic {
+            topic: partitions.topic.clone(),
+            count: partitions.total_count,
+            assignment: partitions.assignment.clone()
+        }).collect(),
+        timeout: timeout_ms,
+        validate_only: if validate_only { 1 } else { 0 }
+    }
+}
+
+fn delete_records_request(partition: &TopicPartition, offset: i64, timeout_ms: i32) -> DeleteRecordsRequest {
+    DeleteRecordsRequest {
+        topics: vec![DeleteRecordsTopic {
+            topic: partition.topic.clone(),
+            partitions: vec![DeleteRecordsPartition { partition: partition.partition, offset: offset }]
+        }],
+        timeout: timeout_ms
+    }
+}
+
+/// Topics go together to any broker, and each broker's own settings to it.
+fn config_batches(resources: &[ConfigResource]) -> Vec<(Option<i32>, Vec<ConfigResource>)> {
+    let mut batches = vec![];
+    let topics: Vec<ConfigResource> = resources.iter().filter(|resource| resource.broker().is_none())
+        .map(|resource| resource.clone()).collect();
+    if !topics.is_empty() {
+        batches.push((None, topics));
+    }
+    for resource in resources.iter() {
+        match resource.broker() {
+            Some(node_id) => batches.push((Some(node_id), vec![resource.clone()])),
+            None => ()
+        }
+    }
+    batches
+}
+
+fn config_resource(resource_type: i8, resource_name: String) -> KafkaResult<ConfigResource> {
+    match resource_type {
+        TOPIC_RESOURCE => Ok(TopicConfigResource(resource_name)),
+        BROKER_RESOURCE => match from_str(resource_name.as_slice()) {
+            Some(node_id) => Ok(BrokerConfigResource(node_id)),
+            None => fail!((MalformedResponseError, "Config resource names an invalid broker"))
+        },
+        _ => fail!((MalformedResponseError, "Config resource has an unknown type"))
+    }
+}
+
+fn config_entry(entry: &DescribeConfigsEntry) -> ConfigEntry {
+    ConfigEntry {
+        name: entry.config_name.clone(),
+        value: entry.config_value.clone(),
+        read_only: entry.read_only != 0,
+        is_default: entry.is_default != 0,
+        is_sensitive: entry.is_sensitive != 0
+    }
+}
+
+/// Each topic's outcome, unless the broker wasn't the controller, which is
+/// then the error so the request can be sent to the new one.
+fn topic_results(errors: Vec<(String, i16, Option<String>)>) -> KafkaResult<TopicResults> {
+    if errors.iter().any(|&(_, error_code, _)| error_code == NotControllerCode as i16) {
+        fail!(NotControllerCode);
+    }
+    Ok(errors.into_iter().map(|(topic, error_code, error_message)| {
+        (topic, resource_result(error_code, error_message))
+    }).collect())
+}
+
+/// Whether the controller may have moved, so it needs to be looked up again,
+/// and the request certainly wasn't carried out.
+fn is_controller_error(err: &KafkaError) -> bool {
+    match err.kind {
+        BrokerError(NotControllerCode) => true,
+        TransportError(..) | TimeoutError => err.operation == Some("Connect"),
+        _ => false
+    }
+}
+
+/// The outcome for one resource, keeping the broker's explanation.
+fn resource_result(error_code: i16, error_message: Option<String>) -> KafkaResult<()> {
+    match check_error_code(error_code) {
+        Ok(()) => Ok(()),
+        Err(mut err) => {
+            err.detail = error_message;
+            Err(err)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::error::FromError;
+    use std::io;
+    use std::time::Duration;
+    use protocol::*;
+    use types::*;
+    use super::*;
+    use super::{config_batches, config_entry, config_resource, create_request, create_partitions_request,
+                delete_records_request, is_controller_error, operation_timeout_ms, resource_result, topic_results};
+
+    #[test]
+    fn test_operation_timeout_ms() {
+        let timeout = AdminConfig::new().operation_timeout;
+        assert_eq!(operation_timeout_ms(timeout, Some(Duration::seconds(30))), 20000);
+        assert_eq!(operation_timeout_ms(timeout, Some(Duration::seconds(10))), 9000);
+        assert_eq!(operation_timeout_ms(timeout, Some(Duration::milliseconds(500))), 0);
+        assert_eq!(operation_timeout_ms(timeout, None), 20000);
+    }
+
+    #[test]
+    fn test_create_request() {
+        let topics = [NewTopic::new("counts", 3, 2).with_config("cleanup.policy", "compact"),
+                      NewTopic::with_replica_assignment("placed", vec![(0, vec![1, 2])])];
+        let request = create_request(&topics, 5000, true);
+        assert!(request.validate().is_ok());
+        assert_eq!(request.validate_only, 1);
+        assert_eq!(request.create_topic_requests[0].config_entries,
+                   vec![CreateTopicsConfigEntry { config_name: String::from_str("cleanup.policy"),
+                                                  config_value: Some(String::from_str("compact")) }]);
+        assert_eq!(request.create_topic_requests[1].num_partitions, FROM_ASSIGNMENT);
+
+        let invalid = create_request(&[NewTopic::new("empty", 0, 1)], 5000, false);
+        assert!(invalid.validate().is_err());
+    }
+
+    #[test]
+    fn test_create_partitions_request() {
+        let partitions = [NewPartitions::increase_to("a", 6),
+                          NewPartitions::increase_to("b", 4).with_assignment(vec![vec![1, 2]])];
+        let request = create_partitions_request(&partitions, 5000, false);
+        assert!(request.validate().is_ok());
+        assert_eq!(request.topic_partitions[0].assignment, None);
+        assert_eq!(request.topic_partitions[1].assignment, Some(vec![vec![1, 2]]));
+        assert_eq!(request.validate_only, 0);
+    }
+
+    #[test]
+    fn test_delete_records_request() {
+        let request = delete_records_request(&TopicPartition::new("t", 2), HIGH_WATERMARK, 5000);
+        assert!(request.validate().is_ok());
+        assert_eq!(request.topics, vec![DeleteRecordsTopic {
+            topic: String::from_str("t"),
+            partitions: vec![DeleteRecordsPartition { partition: 2, offset: HIGH_WATERMARK }]
+        }]);
+    }
+
+    #[test]
+    fn test_config_batches() {
+        let resources = [TopicConfigResource(String::from_str("a")), BrokerConfigResource(1),
+                         TopicConfigResource(String::from_str("b"))];
+        assert_eq!(config_batches(&resources), vec![
+            (None, vec![TopicConfigResource(String::from_str("a")), TopicConfigResource(String::from_str("b"))]),
+            (Some(1), vec![BrokerConfigResource(1)])
+        ]);
+    }
+
+    #[test]
+    fn test_config_entries() {
+        assert_eq!(config_resource(BROKER_RESOURCE, String::from_str("3")).ok().unwrap(), BrokerConfigResource(3));
+        assert!(config_resource(BROKER_RESOURCE, String::from_str("b")).is_err());
+        assert!(config_resource(0, String::from_str("t")).is_err());
+
+        let entry = DescribeConfigsEntry {
+            config_name: String::from_str("ssl.key.password"),
+            config_value: None,
+            read_only: 1,
+            is_default: 0,
+            is_sensitive: 1
+        };
+        assert_eq!(config_entry(&entry), ConfigEntry {
+            name: String::from_str("ssl.key.password"),
+            value: None,
+            read_only: true,
+            is_default: false,
+            is_sensitive: true
+        });
+    }
+
+    #[test]
+    fn test_topic_results() {
+        let results = topic_results(vec![(String::from_str("a"), 0, None),
+                                         (String::from_str("b"), 36, None)]).ok().unwrap();
+        assert!(results[0].1.is_ok());
+        assert_eq!(results[1].1.clone().unwrap_err().kind, BrokerError(TopicAlreadyExistsCode));
+
+        let moved = topic_results(vec![(String::from_str("a"), 0, None),
+                                       (String::from_str("b"), 41, None)]).unwrap_err();
+        assert!(is_controller_error(&moved));
+        assert!(!is_controller_error(&resource_result(36, None).unwrap_err()));
+
+        let refused: KafkaError = FromError::from_error(io::standard_error(io::ConnectionRefused));
+        assert!(is_controller_error(&refused.clone().with_context("Connect", None)));
+        assert!(!is_controller_error(&refused.with_context("CreateTopics", None)));
+    }
+
+    #[test]
+    fn test_resource_result() {
+        assert!(resource_result(0, None).is_ok());
+        let err = resource_result(36, Some(String::from_str("Topic 'counts' already exists."))).unwrap_err();
+        assert_eq!(err.kind, BrokerError(TopicAlreadyExistsCode));
+        assert_eq!(err.detail, Some(String::from_str("Topic 'counts' already exists.")));
+    }
+}
@@ -0,0 +1,320 @@
+use std::cmp;
+use std::io::extensions::u64_from_be_bytes;
+use std::str;
+
+use types::*;
+
+static MAX_PREALLOCATED_ELEMENTS: uint = 1024;
+
+/// Reads protocol primitives straight out of a byte slice, handing out views
+/// into it for STRING and BYTES fields rather than allocating copies.
+pub struct SliceReader<'a> {
+    buffer: &'a [u8],
+    position: uint
+}
+
+impl <'a> SliceReader<'a> {
+    pub fn new(buffer: &'a [u8]) -> SliceReader<'a> {
+        SliceReader {
+            buffer: buffer,
+            position: 0
+        }
+    }
+
+    pub fn position(&self) -> uint {
+        self.position
+    }
+
+    pub fn remaining(&self) -> uint {
+        self.buffer.len() - self.position
+    }
+
+    pub fn take(&mut self, length: uint) -> KafkaResult<&'a [u8]> {
+        if length > self.remaining() {
+            fail!((MalformedResponseError, "Unexpected end of data"));
+        }
+        let slice = self.buffer.slice(self.position, self.position + length);
+        self.position += length;
+        Ok(slice)
+    }
+
+    fn read_be(&mut self, size: uint) -> KafkaResult<u64> {
+        let bytes = try!(self.take(size));
+        Ok(u64_from_be_bytes(bytes, 0, size))
+    }
+
+    pub fn read_i8(&mut self) -> KafkaResult<i8> {
+        Ok(try!(self.read_be(1)) as i8)
+    }
+
+    pub fn read_i16(&mut self) -> KafkaResult<i16> {
+        Ok(try!(self.read_be(2)) as i16)
+    }
+
+    pub fn read_i32(&mut self) -> KafkaResult<i32> {
+        Ok(try!(self.read_be(4)) as i32)
+    }
+
+    pub fn read_i64(&mut self) -> KafkaResult<i64> {
+        Ok(try!(self.read_be(8)) as i64)
+    }
+
+    pub fn read_str(&mut self) -> KafkaResult<&'a str> {
+        match try!(self.read_nullable_str()) {
+            Some(string) => Ok(string),
+            None => fail!((MalformedResponseError, "Negative string size"))
+        }
+    }
+
+    pub fn read_nullable_str(&mut self) -> KafkaResult<Option<&'a str>> {
+        let size = try!(self.read_i16());
+        if size < -1 {
+            fail!((MalformedResponseError, "Negative string size"));
+        }
+        if size == -1 {
+            return Ok(None);
+        }
+
+        let bytes = try!(self.take(size as uint));
+        match str::from_utf8(bytes) {
+            Some(string) => Ok(Some(string)),
+            None => fail!((MalformedResponseError, "Malformed UTF8 response"))
+        }
+    }
+
+    pub fn read_bytes(&mut self) -> KafkaResult<&'a [u8]> {
+        match try!(self.read_nullable_bytes()) {
+            Some(bytes) => Ok(bytes),
+            None => fail!((MalformedResponseError, "Negative array size"))
+        }
+    }
+
+    pub fn read_nullable_bytes(&mut self) -> KafkaResult<Option<&'a [u8]>> {
+        let size = try!(self.read_i32());
+        if size < -1 {
+            fail!((MalformedResponseError, "Negative array size"));
+        }
+        if size == -1 {
+            return Ok(None);
+        }
+        Ok(Some(try!(self.take(size as uint))))
+    }
+}
+
+/// Counterpart of `KafkaSerializable::decode` for types that borrow from the
+/// buffer they were decoded from.
+pub trait BorrowedDecodable<'a> {
+    fn decode_borrowed(reader: &mut SliceReader<'a>) -> KafkaResult<Self>;
+}
+
+impl <'a> BorrowedDecodable<'a> for i32 {
+    fn decode_borrowed(reader: &mut SliceReader<'a>) -> KafkaResult<i32> {
+        reader.read_i32()
+    }
+}
+
+impl <'a, T:BorrowedDecodable<'a>> BorrowedDecodable<'a> for Vec<T> {
+    fn decode_borrowed(reader: &mut SliceReader<'a>) -> KafkaResult<Vec<T>> {
+        let size = try!(reader.read_i32());
+        if size < 0 {
+            fail!((MalformedResponseError, "Negative array size"));
+        }
+
+        let mut result = Vec::with_capacity(cmp::min(size as uint, MAX_PREALLOCATED_ELEMENTS));
+        for _ in range(0, size) {
+            result.push(try!(BorrowedDecodable::decode_borrowed(reader)));
+        }
+        Ok(result)
+    }
+}
+
+#[deriving(Show, PartialEq, Eq)]
+pub struct MessageView<'a> {
+    pub offset: i64,
+    pub crc: i32,
+    pub magic_byte: i8,
+    pub attributes: i8,
+    pub key: Option<&'a [u8]>,
+    pub value: Option<&'a [u8]>
+}
+
+/// The raw bytes of a MessageSet, decoded lazily by `iter()`.
+#[deriving(Show, PartialEq, Eq)]
+pub struct MessageSetView<'a> {
+    pub bytes: &'a [u8]
+}
+
+impl <'a> MessageSetView<'a> {
+    pub fn iter(&self) -> MessageViews<'a> {
+        MessageViews {
+            reader: SliceReader::new(self.bytes),
+            done: false
+        }
+    }
+}
+
+impl <'a> BorrowedDecodable<'a> for MessageSetView<'a> {
+    fn decode_borrowed(reader: &mut SliceReader<'a>) -> KafkaResult<MessageSetView<'a>> {
+        Ok(MessageSetView { bytes: try!(reader.read_bytes()) })
+    }
+}
+
+pub struct MessageViews<'a> {
+    reader: SliceReader<'a>,
+    done: bool
+}
+
+impl <'a> MessageViews<'a> {
+    fn read_message(&mut self) -> KafkaResult<Option<MessageView<'a>>> {
+        // A message cut short at the end of a fetch is dropped, as with MessageSetIter
+        if self.reader.remaining() < 12 {
+            return Ok(None);
+        }
+
+        let offset = try!(self.reader.read_i64());
+        let message_size = try!(self.reader.read_i32());
+        if message_size < 0 {
+            fail!((MalformedResponseError, "Negative message size"));
+        }
+        if message_size as uint > self.reader.remaining() {
+            return Ok(None);
+        }
+
+        let mut message = SliceReader::new(try!(self.reader.take(message_size as uint)));
+        let view = MessageView {
+            offset: offset,
+            crc: try!(message.read_i32()),
+            magic_byte: try!(message.read_i8()),
+            attributes: try!(message.read_i8()),
+            key: try!(message.read_nullable_bytes()),
+            value: try!(message.read_nullable_bytes())
+        };
+        if message.remaining() != 0 {
+            fail!((MalformedResponseError, "Less data read than specified"));
+        }
+        Ok(Some(view))
+    }
+}
+
+impl <'a> Iterator<KafkaResult<MessageView<'a>>> for MessageViews<'a> {
+    fn next(&mut self) -> Option<KafkaResult<MessageView<'a>>> {
+        if self.done {
+            return None;
+        }
+
+        match self.read_message() {
+            Ok(Some(view)) => Some(Ok(view)),
+            Ok(None) => {
+                self.done = true;
+                None
+            },
+            Err(err) => {
+                self.done = true;
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+#[deriving(Show, PartialEq, Eq)]
+pub struct FetchResponsePartitionView<'a> {
+    pub partition: i32,
+    pub error_code: i16,
+    pub highwater_mark_offset: i64,
+    pub messages: MessageSetView<'a>
+}
+
+impl <'a> BorrowedDecodable<'a> for FetchResponsePartitionView<'a> {
+    fn decode_borrowed(reader: &mut SliceReader<'a>) -> KafkaResult<FetchResponsePartitionView<'a>> {
+        Ok(FetchResponsePartitionView {
+            partition: try!(reader.read_i32()),
+            error_code: try!(reader.read_i16()),
+            highwater_mark_offset: try!(reader.read_i64()),
+            messages: try!(BorrowedDecodable::decode_borrowed(reader))
+        })
+    }
+}
+
+#[deriving(Show, PartialEq, Eq)]
+pub struct FetchResponseTopicView<'a> {
+    pub name: &'a str,
+    pub partitions: Vec<FetchResponsePartitionView<'a>>
+}
+
+impl <'a> BorrowedDecodable<'a> for FetchResponseTopicView<'a> {
+    fn decode_borrowed(reader: &mut SliceReader<'a>) -> KafkaResult<FetchResponseTopicView<'a>> {
+        Ok(FetchResponseTopicView {
+            name: try!(reader.read_str()),
+            partitions: try!(BorrowedDecodable::decode_borrowed(reader))
+        })
+    }
+}
+
+#[deriving(Show, PartialEq, Eq)]
+pub struct FetchResponseView<'a> {
+    pub topics: Vec<FetchResponseTopicView<'a>>
+}
+
+impl <'a> BorrowedDecodable<'a> for FetchResponseView<'a> {
+    fn decode_borrowed(reader: &mut SliceReader<'a>) -> KafkaResult<FetchResponseView<'a>> {
+        Ok(FetchResponseView {
+            topics: try!(BorrowedDecodable::decode_borrowed(reader))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::MemWriter;
+    use protocol::*;
+    use types::*;
+    use super::*;
+
+    #[test]
+    fn test_fetch_response_view() {
+        let message = Message {
+            crc: 0,
+            magic_byte: 0,
+            attributes: 0,
+            key: Some(vec![1, 2, 3]),
+            value: Some(vec![4, 5, 6])
+        };
+        let response = FetchResponse {
+            topics: vec![FetchResponseTopic {
+                name: String::from_str("test"),
+                partitions: vec![FetchResponsePartition {
+                    partition: 2,
+                    error_code: 0,
+                    highwater_mark_offset: 10,
+                    messages: WithSize(MessageSet {
+                        messages: vec![MessageSetElement { offset: 9, message: WithSize(message) }]
+                    })
+                }]
+            }]
+        };
+
+        let mut writer = MemWriter::new();
+        response.encode(&mut writer).ok().unwrap();
+        let buffer = writer.unwrap();
+
+        let mut reader = SliceReader::new(buffer.as_slice());
+        let view: FetchResponseView = BorrowedDecodable::decode_borrowed(&mut reader).ok().unwrap();
+        assert_eq!(reader.remaining(), 0);
+        assert_eq!(view.topics[0].name, "test");
+
+        let partition = &view.topics[0].partitions[0];
+        assert_eq!(partition.highwater_mark_offset, 10);
+
+        let messages: Vec<MessageView> = partition.messages.iter().map(|m| m.ok().unwrap()).collect();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].offset, 9);
+        assert_eq!(messages[0].key, Some([1u8, 2, 3].as_slice()));
+        assert_eq!(messages[0].value, Some([4u8, 5, 6].as_slice()));
+    }
+
+    #[test]
+    fn test_truncated_string() {
+        let buffer = [0x00u8, 0x05, 'a' as u8];
+        assert!(SliceReader::new(buffer.as_slice()).read_str().is_err());
+    }
+}
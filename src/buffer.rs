@@ -0,0 +1,68 @@
+/// Keeps cleared byte buffers around so that framing requests and responses
+/// doesn't need a fresh allocation every time.
+pub struct BufferPool {
+    buffers: Vec<Vec<u8>>,
+    max_buffers: uint,
+    max_retained_size: uint
+}
+
+impl BufferPool {
+    /// Retains at most `max_buffers` buffers; buffers which have grown beyond
+    /// `max_retained_size` bytes are freed on checkin rather than kept.
+    pub fn new(max_buffers: uint, max_retained_size: uint) -> BufferPool {
+        BufferPool {
+            buffers: Vec::with_capacity(max_buffers),
+            max_buffers: max_buffers,
+            max_retained_size: max_retained_size
+        }
+    }
+
+    pub fn checkout(&mut self) -> Vec<u8> {
+        match self.buffers.pop() {
+            Some(buffer) => buffer,
+            None => Vec::new()
+        }
+    }
+
+    pub fn checkin(&mut self, mut buffer: Vec<u8>) {
+        if buffer.capacity() > self.max_retained_size || self.buffers.len() >= self.max_buffers {
+            return;
+        }
+        buffer.clear();
+        self.buffers.push(buffer);
+    }
+
+    pub fn len(&self) -> uint {
+        self.buffers.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reuse() {
+        let mut pool = BufferPool::new(2, 1024);
+        let mut buffer = pool.checkout();
+        buffer.push_all(&[1, 2, 3]);
+        pool.checkin(buffer);
+        assert_eq!(pool.len(), 1);
+
+        let buffer = pool.checkout();
+        assert!(buffer.is_empty());
+        assert!(buffer.capacity() >= 3);
+        assert_eq!(pool.len(), 0);
+    }
+
+    #[test]
+    fn test_limits() {
+        let mut pool = BufferPool::new(1, 16);
+        pool.checkin(Vec::with_capacity(1024));
+        assert_eq!(pool.len(), 0);
+
+        pool.checkin(Vec::with_capacity(8));
+        pool.checkin(Vec::with_capacity(8));
+        assert_eq!(pool.len(), 1);
+    }
+}
@@ -0,0 +1,174 @@
+use std::collections::{HashMap, TreeMap};
+
+use consumer::ConsumerRecord;
+use types::TopicPartition;
+
+// Bytes counted for each cached record on top of its topic, key and value
+const RECORD_OVERHEAD: uint = 64;
+
+#[deriving(Show, PartialEq, Eq, Clone)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64
+}
+
+/// Recently read records by partition and offset, for components that read
+/// the same offsets again. Once the records held take up more than
+/// `max_bytes`, the least recently used are evicted.
+pub struct RecordCache {
+    max_bytes: uint,
+    bytes: uint,
+    next_use: u64,
+    // Each record with when it was last used
+    entries: HashMap<(TopicPartition, i64), (u64, ConsumerRecord)>,
+    by_use: TreeMap<u64, (TopicPartition, i64)>,
+    stats: CacheStats
+}
+
+impl RecordCache {
+    pub fn new(max_bytes: uint) -> RecordCache {
+        RecordCache {
+            max_bytes: max_bytes,
+            bytes: 0,
+            next_use: 0,
+            entries: HashMap::new(),
+            by_use: TreeMap::new(),
+            stats: CacheStats { hits: 0, misses: 0, evictions: 0 }
+        }
+    }
+
+    pub fn get(&mut self, partition: &TopicPartition, offset: i64) -> Option<ConsumerRecord> {
+        let key = (partition.clone(), offset);
+        let last_use = match self.entries.get(&key) {
+            Some(&(last_use, _)) => last_use,
+            None => {
+                self.stats.misses += 1;
+                return None;
+            }
+        };
+
+        self.stats.hits += 1;
+        self.by_use.remove(&last_use);
+        let now = self.tick();
+        self.by_use.insert(now, key.clone());
+        let entry = self.entries.get_mut(&key).unwrap();
+        entry.0 = now;
+        Some(entry.1.clone())
+    }
+
+    /// Caches a record, unless it alone is larger than the cache.
+    pub fn insert(&mut self, record: ConsumerRecord) {
+        let size = record_size(&record);
+        if size > self.max_bytes {
+            return;
+        }
+
+        let key = (TopicPartition::new(record.topic.as_slice(), record.partition), record.offset);
+        self.remove(&key);
+        while self.bytes + size > self.max_bytes {
+            self.evict();
+        }
+
+        let now = self.tick();
+        self.by_use.insert(now, key.clone());
+        self.entries.insert(key, (now, record));
+        self.bytes += size;
+    }
+
+    /// Records cached.
+    pub fn len(&self) -> uint {
+        self.entries.len()
+    }
+
+    /// Bytes counted against `max_bytes`.
+    pub fn bytes(&self) -> uint {
+        self.bytes
+    }
+
+    pub fn stats(&self) -> &CacheStats {
+        &self.stats
+    }
+
+    fn tick(&mut self) -> u64 {
+        self.next_use += 1;
+        self.next_use
+    }
+
+    fn remove(&mut self, key: &(TopicPartition, i64)) {
+        match self.entries.remove(key) {
+            Some((last_use, record)) => {
+                self.by_use.remove(&last_use);
+                self.bytes -= record_size(&record);
+            },
+            None => ()
+        }
+    }
+
+    fn evict(&mut self) {
+        let oldest = match self.by_use.iter().next() {
+            Some((_, key)) => key.clone(),
+            None => return
+        };
+        self.remove(&oldest);
+        self.stats.evictions += 1;
+    }
+}
+
+fn record_size(record: &ConsumerRecord) -> uint {
+    RECORD_OVERHEAD + record.topic.len() +
+        record.key.as_ref().map_or(0, |key| key.len()) +
+        record.value.as_ref().map_or(0, |value| value.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use consumer::ConsumerRecord;
+    use types::TopicPartition;
+    use super::*;
+
+    fn record(offset: i64, value_size: uint) -> ConsumerRecord {
+        ConsumerRecord {
+            topic: String::from_str("t"),
+            partition: 0,
+            offset: offset,
+            key: None,
+            value: Some(Vec::from_elem(value_size, 0u8))
+        }
+    }
+
+    #[test]
+    fn test_lru_eviction() {
+        let partition = TopicPartition::new("t", 0);
+        // Room for two records of 65 + 35 bytes
+        let mut cache = RecordCache::new(200);
+        cache.insert(record(1, 35));
+        cache.insert(record(2, 35));
+        assert!(cache.get(&partition, 1).is_some());
+
+        cache.insert(record(3, 35));
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.bytes(), 200);
+        assert!(cache.get(&partition, 2).is_none());
+        assert_eq!(cache.get(&partition, 1), Some(record(1, 35)));
+        assert!(cache.get(&partition, 3).is_some());
+        assert_eq!(*cache.stats(), CacheStats { hits: 3, misses: 1, evictions: 1 });
+    }
+
+    #[test]
+    fn test_oversized_record() {
+        let mut cache = RecordCache::new(100);
+        cache.insert(record(1, 100));
+        assert_eq!(cache.len(), 0);
+        assert_eq!(cache.bytes(), 0);
+    }
+
+    #[test]
+    fn test_replace() {
+        let mut cache = RecordCache::new(1000);
+        cache.insert(record(1, 10));
+        cache.insert(record(1, 20));
+        assert_eq!(cache.len(), 1);
+        assert_eq!(cache.bytes(), 85);
+    }
+}
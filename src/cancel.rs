@@ -0,0 +1,54 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, SeqCst};
+
+use types::*;
+
+/// Asks long-running operations to stop, for shutting a client down from
+/// another thread. Clones share the same state, so one can be kept by the
+/// application and another passed to the operation. Operations check it
+/// between steps and return a `CancelledError`.
+#[deriving(Clone)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>
+}
+
+impl CancellationToken {
+    pub fn new() -> CancellationToken {
+        CancellationToken { cancelled: Arc::new(AtomicBool::new(false)) }
+    }
+
+    /// Cancels every operation using this token or a clone of it, now and
+    /// from now on.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(SeqCst)
+    }
+
+    /// Fails with a `CancelledError` once cancelled.
+    pub fn check(&self) -> KafkaResult<()> {
+        if self.is_cancelled() {
+            fail!((CancelledError, "The operation was cancelled"));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use types::*;
+    use super::*;
+
+    #[test]
+    fn test_cancel() {
+        let token = CancellationToken::new();
+        let shared = token.clone();
+        assert!(token.check().is_ok());
+
+        shared.cancel();
+        assert!(token.is_cancelled());
+        assert_eq!(token.check().err().unwrap().kind, CancelledError);
+    }
+}
@@ -0,0 +1,1138 @@
+use std::cmp;
+use std::collections::HashMap;
+use std::error;
+use std::i32;
+use std::io::{timer, IoError, EndOfFile};
+use std::rand;
+use std::rand::{Rng, SeedableRng, XorShiftRng};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use time;
+
+use cluster::Cluster;
+use scoring::{BrokerScores, BrokerHealth};
+use spawn::{Spawner, NativeSpawner};
+use trace::{Tracer, NoopTracer, SpanInfo};
+use connection::{KafkaConnection, ConnectionConfig, ConnectionPool, parse_host_port};
+use group::GroupMember;
+use protocol;
+use protocol::*;
+use protocol::offset::v1::{OffsetRequestPartitionV1, OffsetRequestTopicV1, OffsetRequestV1, OffsetResponseV1};
+use types::*;
+use versions;
+use versions::{ApiVersionRange, VersionProbe, ProbeVersions, AssumeVersions};
+
+#[deriving(Show, Clone)]
+pub struct ClientConfig {
+    /// Idle connections kept open to each broker.
+    pub max_idle_connections: uint,
+    /// How long an unused connection is kept before being closed.
+    pub idle_timeout: Duration,
+    /// Socket settings for new broker connections.
+    pub connection: ConnectionConfig,
+    /// How many times a request is retried after the broker reports that
+    /// leadership has moved, refreshing metadata in between.
+    pub max_routing_retries: uint,
+    /// Pause before each such retry, giving leader election time to finish.
+    pub retry_backoff: Duration,
+    /// How long topic configuration is cached before being described again.
+    pub topic_config_ttl: Duration,
+    /// Seeds every random choice the client makes, such as which broker to
+    /// ask for metadata and how much to jitter backoffs, so runs can be
+    /// reproduced. Seeded from the OS when `None`.
+    pub rng_seed: Option<u64>,
+    /// How `broker_versions` finds out what a broker supports.
+    pub version_probe: VersionProbe
+}
+
+impl ClientConfig {
+    pub fn new() -> ClientConfig {
+        ClientConfig {
+            max_idle_connections: 2,
+            idle_timeout: Duration::minutes(5),
+            connection: ConnectionConfig::new(),
+            max_routing_retries: 3,
+            retry_backoff: Duration::milliseconds(100),
+            topic_config_ttl: Duration::minutes(5),
+            rng_seed: None,
+            version_probe: ProbeVersions(versions::KAFKA_0_9)
+        }
+    }
+}
+
+#[deriving(Show, PartialEq, Eq, Clone)]
+pub struct TopicListing {
+    pub name: String,
+    pub partitions: uint
+}
+
+/// The topic settings requests are checked against before being sent.
+#[deriving(Show, PartialEq, Eq, Clone)]
+pub struct TopicConfig {
+    /// "delete", "compact" or both, comma separated.
+    pub cleanup_policy: String,
+    pub max_message_bytes: Option<i32>,
+    pub retention_ms: Option<i64>
+}
+
+impl TopicConfig {
+    pub fn is_compacted(&self) -> bool {
+        self.cleanup_policy.as_slice().split(',').any(|policy| policy.trim() == "compact")
+    }
+}
+
+const TOPIC_CONFIG_NAMES: &'static [&'static str] = &["cleanup.policy", "max.message.bytes", "retention.ms"];
+
+fn topic_config(entries: &[DescribeConfigsEntry]) -> TopicConfig {
+    let mut config = TopicConfig {
+        cleanup_policy: String::from_str("delete"),
+        max_message_bytes: None,
+        retention_ms: None
+    };
+    for entry in entries.iter() {
+        let value = match entry.config_value {
+            Some(ref value) => value.as_slice(),
+            None => continue
+        };
+        match entry.config_name.as_slice() {
+            "cleanup.policy" => config.cleanup_policy = String::from_str(value),
+            "max.message.bytes" => config.max_message_bytes = from_str(value),
+            "retention.ms" => config.retention_ms = from_str(value),
+            _ => ()
+        }
+    }
+    config
+}
+
+/// An offset committed for a group, with a string of the application's
+/// own, such as a checkpoint of its processing, stored alongside.
+#[deriving(Show, PartialEq, Eq, Clone)]
+pub struct OffsetAndMetadata {
+    pub offset: i64,
+    pub metadata: String
+}
+
+impl OffsetAndMetadata {
+    pub fn new(offset: i64) -> OffsetAndMetadata {
+        OffsetAndMetadata { offset: offset, metadata: String::new() }
+    }
+}
+
+/// Whether each partition's offset was committed.
+pub type CommitResults = Vec<(TopicPartition, KafkaResult<()>)>;
+
+/// Asks `fetch_offset` for the offset the next message will be written at.
+pub const LATEST_OFFSET: i64 = -1;
+/// Asks `fetch_offset` for the oldest offset the broker still has.
+pub const EARLIEST_OFFSET: i64 = -2;
+
+/// Where brokers keep committed offsets and group metadata.
+pub const CONSUMER_OFFSETS_TOPIC: &'static str = "__consumer_offsets";
+/// Where brokers keep transaction coordinator state.
+pub const TRANSACTION_STATE_TOPIC: &'static str = "__transaction_state";
+
+/// Whether a topic is one Kafka uses for its own bookkeeping.
+pub fn is_internal_topic(name: &str) -> bool {
+    name == CONSUMER_OFFSETS_TOPIC || name == TRANSACTION_STATE_TOPIC
+}
+
+/// Whether internal topics should show up in listings, subscriptions or
+/// mirrored topic sets. They're rarely wanted, and consuming or mirroring
+/// them by accident is easy when matching topics wholesale.
+#[deriving(Show, PartialEq, Eq, Clone)]
+pub enum InternalTopics {
+    IncludeInternalTopics,
+    ExcludeInternalTopics
+}
+
+impl InternalTopics {
+    pub fn allows(&self, topic: &str) -> bool {
+        *self == IncludeInternalTopics || !is_internal_topic(topic)
+    }
+
+    pub fn filter(&self, topics: Vec<String>) -> Vec<String> {
+        topics.into_iter().filter(|topic| self.allows(topic.as_slice())).collect()
+    }
+}
+
+fn topic_listings(metadata: &MetadataResponse, internal: InternalTopics) -> Vec<TopicListing> {
+    let mut topics: Vec<TopicListing> = metadata.topics.iter()
+        .filter(|topic| topic.error_code == 0)
+        .filter(|topic| internal.allows(topic.name.as_slice()))
+        .map(|topic| TopicListing { name: topic.name.clone(), partitions: topic.partitions.len() })
+        .collect();
+    topics.sort_by(|a, b| a.name.cmp(&b.name));
+    topics
+}
+
+// The cached metadata updated with a response for some of its topics. The
+// brokers are always all of them, so they're replaced; of the topics only
+// those in the response are.
+fn merge_metadata(cached: &MetadataResponse, response: &MetadataResponse) -> MetadataResponse {
+    let mut topics: Vec<TopicMetadata> = cached.topics.iter()
+        .filter(|topic| !response.topics.iter().any(|refreshed| refreshed.name == topic.name))
+        .map(|topic| topic.clone())
+        .collect();
+    topics.extend(response.topics.iter().map(|topic| topic.clone()));
+    MetadataResponse { brokers: response.brokers.clone(), topics: topics }
+}
+
+// Commits for `member_id` of a group's `generation_id`, or for a group
+// without a generation as when partitions are assigned explicitly, with
+// partitions grouped by topic
+fn commit_request(group: &str, generation_id: i32, member_id: &str,
+                  offsets: &HashMap<TopicPartition, OffsetAndMetadata>) -> OffsetCommitRequest {
+    let mut sorted: Vec<(&TopicPartition, &OffsetAndMetadata)> = offsets.iter().collect();
+    sorted.sort_by(|&(a, _), &(b, _)| a.cmp(b));
+
+    let mut topics: Vec<OffsetCommitRequestTopic> = vec![];
+    for (partition, offset) in sorted.into_iter() {
+        if topics.last().map_or(true, |topic| topic.name != partition.topic) {
+            topics.push(OffsetCommitRequestTopic { name: partition.topic.clone(), partitions: vec![] });
+        }
+        let last = topics.len() - 1;
+        topics.as_mut_slice()[last].partitions.push(OffsetCommitRequestPartition {
+            partition: partition.partition,
+            offset: offset.offset,
+            timestamp: BROKER_TIMESTAMP,
+            metadata: offset.metadata.clone()
+        });
+    }
+
+    OffsetCommitRequest {
+        consumer_group: String::from_str(group),
+        consumer_group_generation_id: generation_id,
+        consumer_id: String::from_str(member_id),
+        topics: topics
+    }
+}
+
+// Fails if the response answers for a partition that wasn't committed
+fn commit_results(response: OffsetCommitResponse,
+                  offsets: &HashMap<TopicPartition, OffsetAndMetadata>) -> KafkaResult<CommitResults> {
+    let mut results = vec![];
+    for topic in response.topics.into_iter() {
+        for partition in topic.partitions.into_iter() {
+            let topic_partition = TopicPartition::new(topic.name.as_slice(), partition.partition);
+            if !offsets.contains_key(&topic_partition) {
+                fail!((MalformedResponseError, "OffsetCommit response has a partition that wasn't committed"));
+            }
+            results.push((topic_partition, check_error_code(partition.error_code)));
+        }
+    }
+    Ok(results)
+}
+
+// Fails if the response answers for a partition that wasn't asked about
+fn committed_results(response: OffsetFetchResponse, partitions: &[TopicPartition])
+                     -> KafkaResult<HashMap<TopicPartition, Option<OffsetAndMetadata>>> {
+    let mut committed = HashMap::new();
+    for topic in response.topics.into_iter() {
+        for partition in topic.partitions.into_iter() {
+            let topic_partition = TopicPartition::new(topic.name.as_slice(), partition.partition);
+            if !partitions.contains(&topic_partition) {
+                fail!((MalformedResponseError, "OffsetFetch response has a partition that wasn't requested"));
+            }
+            try!(check_error_code(partition.error_code));
+            // No offset is committed as -1
+            let offset = if partition.offset < 0 {
+                None
+            } else {
+                Some(OffsetAndMetadata { offset: partition.offset, metadata: partition.metadata })
+            };
+            committed.insert(topic_partition, offset);
+        }
+    }
+    Ok(committed)
+}
+
+fn span_info<T:Request>(host: &str, port: u16, request: &T) -> SpanInfo {
+    SpanInfo {
+        api_key: Request::api_key(None::<T>),
+        broker: format!("{}:{}", host, port),
+        topic_count: request.topic_count(),
+        request_bytes: request.size().unwrap_or(0) as uint
+    }
+}
+
+// Splits a produce request into one per leader, by node id. Partitions without
+// a known leader are returned separately.
+fn split_by_leader(request: ProduceRequest, leader_for: |&TopicPartition| -> Option<i32>)
+                   -> (HashMap<i32, ProduceRequest>, Vec<TopicPartition>) {
+    let mut requests: HashMap<i32, ProduceRequest> = HashMap::new();
+    let mut unrouted = vec![];
+    for topic in request.topics.into_iter() {
+        for partition in topic.partitions.into_iter() {
+            let topic_partition = TopicPartition::new(topic.name.as_slice(), partition.partition);
+            let leader = match leader_for(&topic_partition) {
+                Some(leader) => leader,
+                None => {
+                    unrouted.push(topic_partition);
+                    continue;
+                }
+            };
+
+            if !requests.contains_key(&leader) {
+                requests.insert(leader, ProduceRequest {
+                    required_acks: request.required_acks,
+                    timeout: request.timeout,
+                    topics: vec![]
+                });
+            }
+            let split = requests.get_mut(&leader).unwrap();
+            if split.topics.last().map_or(true, |last| last.name != topic.name) {
+                split.topics.push(ProduceRequestTopic { name: topic.name.clone(), partitions: vec![] });
+            }
+            split.topics.last_mut().unwrap().partitions.push(partition);
+        }
+    }
+    (requests, unrouted)
+}
+
+// Combines the responses from each leader, answering unrouted partitions with
+// LeaderNotAvailable
+fn merge_responses(responses: Vec<ProduceResponse>, unrouted: Vec<TopicPartition>) -> ProduceResponse {
+    let mut merged: Vec<ProduceResponseTopic> = vec![];
+    let unrouted = unrouted.into_iter().map(|partition| ProduceResponseTopic {
+        name: partition.topic,
+        partitions: vec![ProduceResponsePartition {
+            partition: partition.partition,
+            error_code: protocol::LeaderNotAvailable as i16,
+            offset: -1
+        }]
+    });
+
+    for topic in responses.into_iter().flat_map(|response| response.topics.into_iter()).chain(unrouted) {
+        match merged.iter().position(|merged_topic| merged_topic.name == topic.name) {
+            Some(i) => merged.as_mut_slice()[i].partitions.extend(topic.partitions.into_iter()),
+            None => merged.push(topic)
+        }
+    }
+    ProduceResponse { topics: merged }
+}
+
+// Whether an ApiVersions probe failing with `err` falls back, and if so
+// whether that's remembered. Brokers before 0.10 close the connection on
+// requests they don't know, while a timeout or reset may be transient, and
+// failing to connect says nothing about the broker's versions.
+fn probe_fallback(err: &KafkaError) -> Option<bool> {
+    match *err {
+        KafkaError { operation: Some(operation), .. } if operation == "Connect" => None,
+        KafkaError { kind: TransportError(IoError { kind: EndOfFile, .. }), .. } => Some(true),
+        KafkaError { kind: TransportError(..), .. } | KafkaError { kind: TimeoutError, .. } => Some(false),
+        _ => None
+    }
+}
+
+fn seeded_rng(seed: Option<u64>) -> XorShiftRng {
+    match seed {
+        // The constant words keep the seed from ever being all zeroes
+        Some(seed) => SeedableRng::from_seed([seed as u32, (seed >> 32) as u32, 0x9e3779b9, 0x7f4a7c15]),
+        None => rand::task_rng().gen()
+    }
+}
+
+/// Entry point for talking to a cluster. Clones share one metadata cache and
+/// one set of idle broker connections, so a client can be handed to as many
+/// tasks as need it.
+#[deriving(Clone)]
+pub struct KafkaClient {
+    state: Arc<Mutex<ClientState>>
+}
+
+struct ClientState {
+    client_id: String,
+    hosts: Vec<(String, u16)>,
+    config: ClientConfig,
+    // Busy connections are checked out, so the pool only holds idle ones
+    connections: ConnectionPool<KafkaConnection>,
+    metadata: Option<MetadataResponse>,
+    cluster: Option<Cluster>,
+    // Each with the time it was described at
+    topic_configs: HashMap<String, (TopicConfig, u64)>,
+    // By "host:port", including those assumed after a failed probe
+    broker_versions: HashMap<String, Vec<ApiVersionRange>>,
+    broker_scores: BrokerScores,
+    spawner: Arc<Box<Spawner + Send + Sync>>,
+    tracer: Arc<Box<Tracer + Send + Sync>>,
+    rng: XorShiftRng
+}
+
+impl KafkaClient {
+    /// `hosts` are the bootstrap brokers used to discover the rest of the cluster.
+    pub fn new(client_id: &str, hosts: Vec<(String, u16)>) -> KafkaClient {
+        KafkaClient::with_config(client_id, hosts, ClientConfig::new())
+    }
+
+    /// Like `new`, taking hosts as "host:port" strings.
+    pub fn from_hosts(client_id: &str, hosts: &[&str]) -> KafkaResult<KafkaClient> {
+        let mut parsed = Vec::with_capacity(hosts.len());
+        for host in hosts.iter() {
+            parsed.push(try!(parse_host_port(*host)));
+        }
+        Ok(KafkaClient::new(client_id, parsed))
+    }
+
+    pub fn with_config(client_id: &str, hosts: Vec<(String, u16)>, config: ClientConfig) -> KafkaClient {
+        let rng = seeded_rng(config.rng_seed);
+        KafkaClient {
+            state: Arc::new(Mutex::new(ClientState {
+                client_id: String::from_str(client_id),
+                hosts: hosts,
+                connections: ConnectionPool::new(config.max_idle_connections, config.idle_timeout),
+                config: config,
+                metadata: None,
+                cluster: None,
+                topic_configs: HashMap::new(),
+                broker_versions: HashMap::new(),
+                broker_scores: BrokerScores::new(),
+                spawner: Arc::new(box NativeSpawner as Box<Spawner + Send + Sync>),
+                tracer: Arc::new(box NoopTracer as Box<Tracer + Send + Sync>),
+                rng: rng
+            }))
+        }
+    }
+
+    /// Changes how background work started by this client and the producers
+    /// and consumers built on it is run.
+    pub fn set_spawner(&self, spawner: Box<Spawner + Send + Sync>) {
+        self.state.lock().spawner = Arc::new(spawner);
+    }
+
+    /// Reports a span for every request sent by this client and the
+    /// producers and consumers built on it.
+    pub fn set_tracer(&self, tracer: Box<Tracer + Send + Sync>) {
+        self.state.lock().tracer = Arc::new(tracer);
+    }
+
+    /// Starts background work with the configured `Spawner`.
+    pub fn spawn(&self, task: proc(): Send) {
+        let spawner = self.state.lock().spawner.clone();
+        spawner.spawn(task);
+    }
+
+    /// A generator for components built on this client that make random
+    /// choices, derived from the client's own so `rng_seed` covers them too.
+    pub fn new_rng(&self) -> XorShiftRng {
+        self.state.lock().rng.gen()
+    }
+
+    pub fn config(&self) -> ClientConfig {
+        self.state.lock().config.clone()
+    }
+
+    /// The metadata from the last successful `load_metadata`.
+    pub fn metadata(&self) -> Option<MetadataResponse> {
+        self.state.lock().metadata.clone()
+    }
+
+    /// The cluster layout from the last successful `load_metadata`.
+    pub fn cluster(&self) -> Option<Cluster> {
+        self.state.lock().cluster.clone()
+    }
+
+    /// Fetches metadata for `topics` (all topics when empty) from the first
+    /// reachable bootstrap host or known broker, and caches it. Refreshing
+    /// some topics keeps what is cached for the others.
+    pub fn load_metadata(&self, topics: Vec<String>) -> KafkaResult<MetadataResponse> {
+        let all_topics = topics.is_empty();
+        let response: MetadataResponse = try!(self.send_to_any(MetadataRequest { topic_names: topics }));
+        let mut state = self.state.lock();
+        let metadata = match state.metadata {
+            Some(ref cached) if !all_topics => merge_metadata(cached, &response),
+            _ => response.clone()
+        };
+        state.cluster = Some(Cluster::new(&metadata));
+        state.metadata = Some(metadata);
+        Ok(response)
+    }
+
+    /// The configuration of `topic`, described by the cluster at most
+    /// `topic_config_ttl` ago.
+    pub fn topic_config(&self, topic: &str) -> KafkaResult<TopicConfig> {
+        let now = time::precise_time_ns();
+        let (cached, ttl) = {
+            let state = self.state.lock();
+            (state.topic_configs.get(&String::from_str(topic)).map(|cached| cached.clone()),
+             state.config.topic_config_ttl)
+        };
+        match cached {
+            Some((config, described_at)) if now - described_at < ttl.num_milliseconds() as u64 * 1000000 => {
+                return Ok(config);
+            },
+            _ => ()
+        }
+
+        let request = DescribeConfigsRequest {
+            resources: vec![DescribeConfigsResource {
+                resource_type: TOPIC_RESOURCE,
+                resource_name: String::from_str(topic),
+                config_names: Some(TOPIC_CONFIG_NAMES.iter().map(|name| String::from_str(*name)).collect())
+            }]
+        };
+        let response: DescribeConfigsResponse = try!(self.send_to_any(request));
+        let config = match response.resources.iter().find(|resource| resource.resource_name.as_slice() == topic) {
+            Some(resource) => {
+                try!(check_error_code(resource.error_code));
+                topic_config(resource.config_entries.as_slice())
+            },
+            None => fail!((MalformedResponseError, "DescribeConfigs response is missing the requested topic"))
+        };
+
+        self.state.lock().topic_configs.insert(String::from_str(topic), (config.clone(), now));
+        Ok(config)
+    }
+
+    /// Lists every topic in the cluster, sorted by name.
+    pub fn list_topics(&self, internal: InternalTopics) -> KafkaResult<Vec<TopicListing>> {
+        // An empty topic list asks the broker for all topics
+        let metadata = try!(self.load_metadata(vec![]));
+        Ok(topic_listings(&metadata, internal))
+    }
+
+    /// The node id of the leader for a partition, as known from the cached
+    /// metadata.
+    pub fn leader_for(&self, partition: &TopicPartition) -> Option<i32> {
+        let state = self.state.lock();
+        match state.cluster {
+            Some(ref cluster) => cluster.leader_for(partition).map(|broker| broker.node_id),
+            None => None
+        }
+    }
+
+    /// Sends a request to the leader of a partition. `check` pulls the
+    /// partition's result out of the response; if it fails with a routing
+    /// error the metadata for its topic is refreshed and the request retried
+    /// against the new leader, up to `max_routing_retries` times.
+    pub fn send_to_leader<T:Request + Clone, U:Response, V>(&self, partition: &TopicPartition, request: T,
+                                                           check: |U| -> KafkaResult<V>) -> KafkaResult<V> {
+        let (max_retries, backoff) = {
+            let state = self.state.lock();
+            (state.config.max_routing_retries, state.config.retry_backoff)
+        };
+
+        let mut attempt = 0;
+        loop {
+            let result = match self.leader_for(partition) {
+                Some(leader) => match self.send_to_broker(leader, request.clone()) {
+                    Ok(response) => check(response),
+                    Err(err) => Err(err)
+                },
+                None => Err(error::FromError::from_error(protocol::LeaderNotAvailable))
+            };
+
+            match result {
+                Err(ref err) if err.is_routing_error() && attempt < max_retries => (),
+                _ => return result
+            }
+
+            attempt += 1;
+            // Metadata that is simply missing doesn't need a backoff
+            if self.state.lock().cluster.is_some() {
+                timer::sleep(self.jitter(backoff));
+            }
+            try!(self.load_metadata(vec![partition.topic.clone()]));
+        }
+    }
+
+    /// Looks up an offset in a partition: `LATEST_OFFSET`, `EARLIEST_OFFSET`,
+    /// or otherwise the first offset written at or after `time` in ms.
+    pub fn fetch_offset(&self, partition: &TopicPartition, time: i64) -> KafkaResult<i64> {
+        let request = OffsetRequest {
+            replica_id: -1,
+            requests: vec![OffsetRequestTopic {
+                name: partition.topic.clone(),
+                partitions: vec![OffsetRequestPartition {
+                    partition: partition.partition,
+                    time: time,
+                    max_number_of_offsets: 1
+                }]
+            }]
+        };
+
+        self.send_to_leader(partition, request, |response: OffsetResponse| {
+            for response_topic in response.responses.iter().filter(|t| t.name == partition.topic) {
+                for result in response_topic.partitions.iter().filter(|p| p.partition == partition.partition) {
+                    try!(check_error_code(result.error_code));
+                    match result.offsets.as_slice().head() {
+                        Some(offset) => return Ok(*offset),
+                        None => fail!((MalformedResponseError, "Offset response has no offsets"))
+                    }
+                }
+            }
+            fail!((MalformedResponseError, "Offset response is missing the requested partition"))
+        })
+    }
+
+    /// Looks up the first offset in a partition written at or after
+    /// `timestamp` in ms, returning it with the timestamp of its record, or
+    /// `None` if every record is older. Unlike `fetch_offset` this finds the
+    /// exact record, but needs brokers of 0.10.1 or later.
+    pub fn offset_for_time(&self, partition: &TopicPartition, timestamp: i64) -> KafkaResult<Option<(i64, i64)>> {
+        let request = OffsetRequestV1 {
+            replica_id: -1,
+            requests: vec![OffsetRequestTopicV1 {
+                name: partition.topic.clone(),
+                partitions: vec![OffsetRequestPartitionV1 {
+                    partition: partition.partition,
+                    time: timestamp
+                }]
+            }]
+        };
+
+        self.send_to_leader(partition, request, |response: OffsetResponseV1| {
+            for response_topic in response.responses.iter().filter(|t| t.name == partition.topic) {
+                for result in response_topic.partitions.iter().filter(|p| p.partition == partition.partition) {
+                    try!(check_error_code(result.error_code));
+                    return Ok(if result.offset < 0 { None } else { Some((result.timestamp, result.offset)) });
+                }
+            }
+            fail!((MalformedResponseError, "Offset response is missing the requested partition"))
+        })
+    }
+
+    /// Finds the broker coordinating `group`, which stores its offsets.
+    pub fn group_coordinator(&self, group: &str) -> KafkaResult<(String, u16)> {
+        let request = ConsumerMetadataRequest { group: String::from_str(group) };
+        let response: ConsumerMetadataResponse = try!(self.send_to_any(request));
+        try!(check_error_code(response.error_code));
+        Ok((response.coordinator_host, response.coordinator_port as u16))
+    }
+
+    /// Stores `offsets`, the offsets of the next records to read, as
+    /// `group`'s positions in their partitions, returning whether each
+    /// partition's offset was stored. Fails if the request as a whole does.
+    pub fn commit_offsets(&self, group: &str,
+                          offsets: &HashMap<TopicPartition, OffsetAndMetadata>) -> KafkaResult<CommitResults> {
+        self.send_commit(commit_request(group, NO_GENERATION_ID, "", offsets), offsets)
+    }
+
+    /// Like `commit_offsets`, as a member of the group's current generation,
+    /// which the coordinator requires while the group has members.
+    pub fn commit_member_offsets(&self, member: &GroupMember,
+                                 offsets: &HashMap<TopicPartition, OffsetAndMetadata>) -> KafkaResult<CommitResults> {
+        let request = commit_request(member.group_id.as_slice(), member.generation_id, member.member_id.as_slice(),
+                                     offsets);
+        self.send_commit(request, offsets)
+    }
+
+    fn send_commit(&self, request: OffsetCommitRequest,
+                   offsets: &HashMap<TopicPartition, OffsetAndMetadata>) -> KafkaResult<CommitResults> {
+        if offsets.is_empty() {
+            return Ok(vec![]);
+        }
+        let (host, port) = try!(self.group_coordinator(request.consumer_group.as_slice()));
+        let response: OffsetCommitResponse = try!(self.send_to_host(host.as_slice(), port, request));
+        commit_results(response, offsets)
+    }
+
+    /// The offsets `group` last committed for `partitions`, with their
+    /// metadata, or `None` for partitions it hasn't committed.
+    pub fn committed_offsets(&self, group: &str, partitions: &[TopicPartition])
+                             -> KafkaResult<HashMap<TopicPartition, Option<OffsetAndMetadata>>> {
+        if partitions.is_empty() {
+            return Ok(HashMap::new());
+        }
+        let mut topics: Vec<OffsetFetchRequestTopic> = vec![];
+        for partition in partitions.iter() {
+            match topics.iter().position(|topic| topic.name == partition.topic) {
+                Some(i) => topics.as_mut_slice()[i].partitions.push(partition.partition),
+                None => topics.push(OffsetFetchRequestTopic {
+                    name: partition.topic.clone(),
+                    partitions: vec![partition.partition]
+                })
+            }
+        }
+        let request = OffsetFetchRequest { consumer_group: String::from_str(group), topics: topics };
+
+        let (host, port) = try!(self.group_coordinator(group));
+        let response: OffsetFetchResponse = try!(self.send_to_host(host.as_slice(), port, request));
+        committed_results(response, partitions)
+    }
+
+    /// Fetches up to `max_bytes` of messages from a partition, starting at
+    /// `offset`, without waiting for new ones to arrive.
+    pub fn fetch_partition(&self, partition: &TopicPartition, offset: i64,
+                           max_bytes: i32) -> KafkaResult<FetchResponsePartition> {
+        self.fetch_partition_waiting(partition, offset, max_bytes, Duration::zero(), 0)
+    }
+
+    /// Like `fetch_partition`, but the broker holds the request for up to
+    /// `max_wait` until at least `min_bytes` of messages are available. The
+    /// connection's read timeout has to be longer than `max_wait`.
+    pub fn fetch_partition_waiting(&self, partition: &TopicPartition, offset: i64, max_bytes: i32,
+                                   max_wait: Duration, min_bytes: i32) -> KafkaResult<FetchResponsePartition> {
+        let request = FetchRequest {
+            replica_id: -1,
+            max_wait_time: cmp::min(max_wait.num_milliseconds(), i32::MAX as i64) as i32,
+            min_bytes: min_bytes,
+            elements: vec![FetchRequestTopic {
+                name: partition.topic.clone(),
+                partitions: vec![FetchRequestPartition {
+                    partition: partition.partition,
+                    fetch_offset: offset,
+                    max_bytes: max_bytes
+                }]
+            }]
+        };
+
+        self.send_to_leader(partition, request, |response: FetchResponse| {
+            for response_topic in response.topics.into_iter().filter(|t| t.name == partition.topic) {
+                for result in response_topic.partitions.into_iter().filter(|p| p.partition == partition.partition) {
+                    try!(check_error_code(result.error_code));
+                    return Ok(result);
+                }
+            }
+            fail!((MalformedResponseError, "Fetch response is missing the requested partition"))
+        })
+    }
+
+    /// Sends a produce request covering partitions with different leaders,
+    /// splitting it into one request per leader, sending those in parallel
+    /// with the client's `Spawner` and merging the responses. Partitions
+    /// without a leader get `LeaderNotAvailable` in the response.
+    ///
+    /// If any of the requests fails outright the first such error is
+    /// returned, though those sent to other leaders may have been written.
+    pub fn produce(&self, request: ProduceRequest) -> KafkaResult<ProduceResponse> {
+        let unknown: Vec<String> = request.topics.iter()
+            .map(|topic| topic.name.clone())
+            .filter(|name| self.cluster().map_or(true, |cluster| cluster.partitions_for(name.as_slice()).is_none()))
+            .collect();
+        if !unknown.is_empty() {
+            try!(self.load_metadata(unknown));
+        }
+
+        let (requests, unrouted) = split_by_leader(request, |partition| self.leader_for(partition));
+        let count = requests.len();
+        let (tx, rx) = channel();
+        for (leader, request) in requests.into_iter() {
+            let (client, tx) = (self.clone(), tx.clone());
+            self.spawn(proc() {
+                let _ = tx.send_opt(client.send_to_broker::<ProduceRequest, ProduceResponse>(leader, request));
+            });
+        }
+
+        let mut responses = vec![];
+        for _ in range(0, count) {
+            match rx.recv_opt() {
+                Ok(result) => responses.push(try!(result)),
+                Err(()) => fail!((SpawnError, "The spawner dropped a produce request without running it"))
+            }
+        }
+        Ok(merge_responses(responses, unrouted))
+    }
+
+    /// The API versions a broker supports, found according to the
+    /// `version_probe` setting and remembered from then on. A broker that
+    /// closes the connection when probed is only probed once. Other failures
+    /// of the probe's request fall back for just this call, and failing to
+    /// connect at all is returned as an error.
+    pub fn broker_versions(&self, host: &str, port: u16) -> KafkaResult<Vec<ApiVersionRange>> {
+        let key = format!("{}:{}", host, port);
+        let probe = {
+            let state = self.state.lock();
+            match state.broker_versions.get(&key) {
+                Some(versions) => return Ok(versions.clone()),
+                None => state.config.version_probe.clone()
+            }
+        };
+
+        let (versions, remember) = match probe {
+            AssumeVersions(assumed) => (assumed.to_vec(), true),
+            ProbeVersions(fallback) => match self.send_to_host(host, port, ApiVersionsRequest) {
+                Ok(response) => {
+                    let response: ApiVersionsResponse = response;
+                    try!(check_error_code(response.error_code));
+                    (response.version_ranges(), true)
+                },
+                Err(err) => match probe_fallback(&err) {
+                    Some(remember) => (fallback.to_vec(), remember),
+                    None => return Err(err)
+                }
+            }
+        };
+        if remember {
+            self.state.lock().broker_versions.insert(key, versions.clone());
+        }
+        Ok(versions)
+    }
+
+    /// Sends a request to the first reachable bootstrap host or known broker,
+    /// for requests any broker can answer.
+    /// Brokers that have been answering well are preferred, so one slow or
+    /// failing broker doesn't hold these requests up.
+    pub fn send_to_any<T:Request + Clone, U:Response>(&self, request: T) -> KafkaResult<U> {
+        let hosts = {
+            let mut hosts = self.metadata_hosts();
+            let mut state = self.state.lock();
+            // Spread requests over the brokers not yet tried rather than always asking the first
+            state.rng.shuffle(hosts.as_mut_slice());
+            state.broker_scores.order(hosts)
+        };
+        let mut last_error = None;
+        for &(ref host, port) in hosts.iter() {
+            match self.send_to_host(host.as_slice(), port, request.clone()) {
+                Ok(response) => return Ok(response),
+                Err(err) => last_error = Some(err)
+            }
+        }
+
+        match last_error {
+            Some(err) => Err(err),
+            None => fail!((NoHosts, "No hosts to send the request to"))
+        }
+    }
+
+    /// Sends a request to the broker with the given node id, as known from the
+    /// cached metadata.
+    pub fn send_to_broker<T:Request, U:Response>(&self, node_id: i32, request: T) -> KafkaResult<U> {
+        let broker = {
+            let state = self.state.lock();
+            match state.cluster {
+                Some(ref cluster) => cluster.broker_by_id(node_id).map(|broker| broker.clone()),
+                None => None
+            }
+        };
+
+        match broker {
+            Some(broker) => self.send_to_host(broker.host.as_slice(), broker.port as u16, request),
+            None => fail!(protocol::BrokerNotAvailable)
+        }
+    }
+
+    pub fn send_to_host<T:Request, U:Response>(&self, host: &str, port: u16, request: T) -> KafkaResult<U> {
+        let info = span_info(host, port, &request);
+        let tracer = self.state.lock().tracer.clone();
+        let span = tracer.start_span(&info);
+        let started = time::precise_time_ns();
+
+        let result = match self.checkout_connection(host, port) {
+            Ok(mut connection) => {
+                let result = connection.request(request);
+                match result {
+                    // A failed connection may be out of step with the broker, so don't reuse it
+                    Ok(_) => self.checkin_connection(connection),
+                    Err(_) => ()
+                }
+                result.map_err(|err| err.with_context(versions::api_name(info.api_key), Some(info.broker.clone())))
+            },
+            Err(err) => Err(err)
+        };
+
+        tracer.end_span(span, &info, result.as_ref().err());
+        let latency_ms = (time::precise_time_ns() - started) as f64 / 1e6;
+        self.state.lock().broker_scores.record(info.broker.as_slice(), latency_ms, result.is_err());
+        result
+    }
+
+    /// How a broker has been answering, if the client has sent it anything.
+    pub fn broker_health(&self, host: &str, port: u16) -> Option<BrokerHealth> {
+        self.state.lock().broker_scores.get(format!("{}:{}", host, port).as_slice()).map(|health| health.clone())
+    }
+
+    // Up to a fifth longer, so clients retrying together don't stay in step
+    fn jitter(&self, backoff: Duration) -> Duration {
+        let millis = backoff.num_milliseconds();
+        let extra = self.state.lock().rng.gen_range(0, millis / 5 + 1);
+        Duration::milliseconds(millis + extra)
+    }
+
+    fn metadata_hosts(&self) -> Vec<(String, u16)> {
+        let state = self.state.lock();
+        let mut hosts = state.hosts.clone();
+        match state.cluster {
+            Some(ref cluster) => {
+                for broker in cluster.brokers().into_iter() {
+                    hosts.push((broker.host.clone(), broker.port as u16));
+                }
+            },
+            None => ()
+        }
+        hosts
+    }
+
+    fn checkout_connection(&self, host: &str, port: u16) -> KafkaResult<KafkaConnection> {
+        let (existing, client_id, config) = {
+            let mut state = self.state.lock();
+            (state.connections.checkout(&format!("{}:{}", host, port), time::precise_time_ns()),
+             state.client_id.clone(),
+             state.config.connection.clone())
+        };
+
+        match existing {
+            Some(connection) => Ok(connection),
+            None => KafkaConnection::connect(host, port, client_id.as_slice(), &config)
+        }
+    }
+
+    fn checkin_connection(&self, connection: KafkaConnection) {
+        let key = format!("{}:{}", connection.host(), connection.port());
+        self.state.lock().connections.checkin(key, connection, time::precise_time_ns());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::rand::Rng;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUint, SeqCst};
+    use cluster::Cluster;
+    use protocol::*;
+    use spawn::Spawner;
+    use types::*;
+    use super::*;
+    use super::{topic_listings, topic_config, span_info, split_by_leader, merge_responses, commit_request,
+                commit_results, committed_results, merge_metadata, probe_fallback};
+
+    fn topic(name: &str, error_code: i16, partitions: i32) -> TopicMetadata {
+        TopicMetadata {
+            error_code: error_code,
+            name: String::from_str(name),
+            partitions: range(0, partitions).map(|partition| PartitionMetadata {
+                error_code: 0,
+                partition: partition,
+                leader: 1,
+                replicas: vec![1],
+                isr: vec![1]
+            }).collect()
+        }
+    }
+
+    #[test]
+    fn test_merge_metadata() {
+        fn broker(node_id: i32) -> Broker {
+            Broker { node_id: node_id, host: String::from_str("h"), port: 9092 }
+        }
+        let cached = MetadataResponse { brokers: vec![broker(1)], topics: vec![topic("a", 0, 1), topic("b", 0, 2)] };
+        let mut moved = topic("a", 0, 1);
+        moved.partitions[0].leader = 2;
+        let response = MetadataResponse { brokers: vec![broker(1), broker(2)], topics: vec![moved.clone()] };
+
+        let merged = merge_metadata(&cached, &response);
+        assert_eq!(merged.brokers, response.brokers);
+        assert_eq!(merged.topics, vec![topic("b", 0, 2), moved]);
+
+        let cluster = Cluster::new(&merged);
+        assert_eq!(cluster.leader_for(&TopicPartition::new("a", 0)).map(|broker| broker.node_id), Some(2));
+        assert_eq!(cluster.leader_for(&TopicPartition::new("b", 1)).map(|broker| broker.node_id), Some(1));
+    }
+
+    #[test]
+    fn test_topic_listings() {
+        let metadata = MetadataResponse {
+            brokers: vec![],
+            topics: vec![topic("b", 0, 2), topic("__consumer_offsets", 0, 50), topic("a", 0, 1), topic("c", 3, 0)]
+        };
+
+        let listings = topic_listings(&metadata, ExcludeInternalTopics);
+        assert_eq!(listings, vec![
+            TopicListing { name: String::from_str("a"), partitions: 1 },
+            TopicListing { name: String::from_str("b"), partitions: 2 }
+        ]);
+        assert_eq!(topic_listings(&metadata, IncludeInternalTopics).len(), 3);
+    }
+
+    #[test]
+    fn test_internal_topic_filter() {
+        let topics = vec![String::from_str("__consumer_offsets"), String::from_str("__private"), String::from_str("events")];
+        assert_eq!(ExcludeInternalTopics.filter(topics.clone()),
+                   vec![String::from_str("__private"), String::from_str("events")]);
+        assert_eq!(IncludeInternalTopics.filter(topics.clone()), topics);
+    }
+
+    #[test]
+    fn test_topic_config() {
+        let entry = |name: &str, value: Option<&str>| DescribeConfigsEntry {
+            config_name: String::from_str(name),
+            config_value: value.map(|value| String::from_str(value)),
+            read_only: 0,
+            is_default: 0,
+            is_sensitive: 0
+        };
+
+        let config = topic_config(&[entry("cleanup.policy", Some("compact,delete")),
+                                    entry("max.message.bytes", Some("1000012")),
+                                    entry("retention.ms", None)]);
+        assert!(config.is_compacted());
+        assert_eq!(config.max_message_bytes, Some(1000012));
+        assert_eq!(config.retention_ms, None);
+        assert!(!topic_config(&[]).is_compacted());
+    }
+
+    struct InlineSpawner {
+        spawned: Arc<AtomicUint>
+    }
+
+    impl Spawner for InlineSpawner {
+        fn spawn(&self, task: proc(): Send) {
+            self.spawned.fetch_add(1, SeqCst);
+            task();
+        }
+    }
+
+    #[test]
+    fn test_spawner() {
+        let client = KafkaClient::new("test", vec![]);
+        let spawned = Arc::new(AtomicUint::new(0));
+        client.set_spawner(box InlineSpawner { spawned: spawned.clone() });
+
+        let (tx, rx) = channel();
+        client.spawn(proc() tx.send(42u));
+        assert_eq!(rx.recv(), 42u);
+        assert_eq!(spawned.load(SeqCst), 1);
+    }
+
+    #[test]
+    fn test_seeded_rng() {
+        let seeded = |seed| {
+            let mut config = ClientConfig::new();
+            config.rng_seed = Some(seed);
+            KafkaClient::with_config("test", vec![], config).new_rng()
+        };
+
+        let first: Vec<u32> = seeded(7).gen_iter().take(4).collect();
+        let second: Vec<u32> = seeded(7).gen_iter().take(4).collect();
+        let other: Vec<u32> = seeded(8).gen_iter().take(4).collect();
+        assert_eq!(first, second);
+        assert!(first != other);
+    }
+
+    #[test]
+    fn test_span_info() {
+        let request = MetadataRequest { topic_names: vec![String::from_str("a"), String::from_str("b")] };
+        let info = span_info("broker1", 9092, &request);
+        assert_eq!(info.api_key, 3);
+        assert_eq!(info.broker.as_slice(), "broker1:9092");
+        assert_eq!(info.topic_count, 2);
+        // Array length, then two strings of length prefix and one byte
+        assert_eq!(info.request_bytes, 10);
+    }
+
+    #[test]
+    fn test_probe_fallback() {
+        let io_error = |kind| -> KafkaError {
+            ::std::error::FromError::from_error(::std::io::IoError { kind: kind, desc: "io", detail: None })
+        };
+        let closed = io_error(::std::io::EndOfFile);
+        assert_eq!(probe_fallback(&closed.clone().with_context("ApiVersions", None)), Some(true));
+        assert_eq!(probe_fallback(&io_error(::std::io::ConnectionReset).with_context("ApiVersions", None)), Some(false));
+        assert_eq!(probe_fallback(&closed.with_context("Connect", None)), None);
+        assert_eq!(probe_fallback(&io_error(::std::io::ConnectionRefused).with_context("Connect", None)), None);
+        let error: KafkaError = ::std::error::FromError::from_error(OffsetOutOfRange);
+        assert_eq!(probe_fallback(&error), None);
+    }
+
+    fn assert_shareable<T:Send + Sync>(_: &T) {}
+
+    #[test]
+    fn test_shareable() {
+        let client = KafkaClient::new("test", vec![(String::from_str("localhost"), 9092)]);
+        assert_shareable(&client);
+        assert!(client.clone().metadata().is_none());
+    }
+
+    fn produce_partition(partition: i32) -> ProduceRequestPartition {
+        ProduceRequestPartition { partition: partition, message_set: WithSize(MessageSet { messages: vec![] }) }
+    }
+
+    #[test]
+    fn test_split_by_leader() {
+        let request = ProduceRequest {
+            required_acks: -1,
+            timeout: 1000,
+            topics: vec![
+                ProduceRequestTopic { name: String::from_str("a"), partitions: vec![produce_partition(0), produce_partition(1)] },
+                ProduceRequestTopic { name: String::from_str("b"), partitions: vec![produce_partition(0), produce_partition(7)] }
+            ]
+        };
+
+        // Partition 0 of each topic is led by broker 1, partition 1 by broker 2
+        let (requests, unrouted) = split_by_leader(request, |partition| match partition.partition {
+            0 => Some(1),
+            1 => Some(2),
+            _ => None
+        });
+        assert_eq!(unrouted, vec![TopicPartition::new("b", 7)]);
+        assert_eq!(requests.len(), 2);
+
+        let first = requests.get(&1).unwrap();
+        assert_eq!(first.required_acks, -1);
+        assert_eq!(first.topics.iter().map(|topic| topic.name.as_slice()).collect::<Vec<&str>>(), vec!["a", "b"]);
+        assert_eq!(requests.get(&2).unwrap().topics[0].partitions[0].partition, 1);
+    }
+
+    #[test]
+    fn test_commit_request() {
+        let mut offsets = HashMap::new();
+        offsets.insert(TopicPartition::new("users", 0), OffsetAndMetadata::new(7));
+        offsets.insert(TopicPartition::new("events", 1), OffsetAndMetadata::new(42));
+        offsets.insert(TopicPartition::new("events", 0),
+                       OffsetAndMetadata { offset: 40, metadata: String::from_str("checkpoint") });
+
+        let request = commit_request("group", NO_GENERATION_ID, "", &offsets);
+        assert_eq!(request.consumer_group_generation_id, NO_GENERATION_ID);
+        let layout: Vec<(String, Vec<(i32, i64)>)> = request.topics.iter()
+            .map(|topic| (topic.name.clone(), topic.partitions.iter().map(|p| (p.partition, p.offset)).collect()))
+            .collect();
+        assert_eq!(layout, vec![(String::from_str("events"), vec![(0, 40), (1, 42)]),
+                                (String::from_str("users"), vec![(0, 7)])]);
+        assert_eq!(request.topics[0].partitions[0].metadata.as_slice(), "checkpoint");
+
+        let request = commit_request("group", 4, "member-1", &offsets);
+        assert_eq!((request.consumer_group_generation_id, request.consumer_id.as_slice()), (4, "member-1"));
+    }
+
+    #[test]
+    fn test_commit_results() {
+        let mut offsets = HashMap::new();
+        offsets.insert(TopicPartition::new("events", 0), OffsetAndMetadata::new(40));
+        let response = |partition: i32| OffsetCommitResponse { topics: vec![OffsetCommitResponseTopic {
+            name: String::from_str("events"),
+            partitions: vec![OffsetCommitResponsePartition { partition: partition, error_code: 0 }]
+        }]};
+
+        assert_eq!(commit_results(response(0), &offsets).ok().unwrap(),
+                   vec![(TopicPartition::new("events", 0), Ok(()))]);
+        assert_eq!(commit_results(response(1), &offsets).unwrap_err().kind, ProtocolError(MalformedResponseError));
+    }
+
+    #[test]
+    fn test_committed_results() {
+        let response = |partition: i32, offset: i64| OffsetFetchResponse { topics: vec![OffsetFetchResponseTopic {
+            name: String::from_str("events"),
+            partitions: vec![OffsetFetchResponsePartition {
+                partition: partition,
+                offset: offset,
+                metadata: String::from_str("checkpoint"),
+                error_code: 0
+            }]
+        }]};
+        let partitions = [TopicPartition::new("events", 0)];
+
+        let committed = committed_results(response(0, 40), &partitions).ok().unwrap();
+        assert_eq!(committed.get(&partitions[0]),
+                   Some(&Some(OffsetAndMetadata { offset: 40, metadata: String::from_str("checkpoint") })));
+        assert_eq!(committed_results(response(0, -1), &partitions).ok().unwrap().get(&partitions[0]), Some(&None));
+        assert_eq!(committed_results(response(1, 40), &partitions).unwrap_err().kind,
+                   ProtocolError(MalformedResponseError));
+    }
+
+    #[test]
+    fn test_merge_responses() {
+        let response = |topic: &str, partition: i32, offset: i64| ProduceResponse {
+            topics: vec![ProduceResponseTopic {
+                name: String::from_str(topic),
+                partitions: vec![ProduceResponsePartition { partition: partition, error_code: 0, offset: offset }]
+            }]
+        };
+
+        let merged = merge_responses(vec![response("a", 0, 10), response("a", 1, 20)], vec![TopicPartition::new("b", 3)]);
+        assert_eq!(merged.topics.len(), 2);
+        assert_eq!(merged.topics[0].partitions.iter().map(|p| p.offset).collect::<Vec<i64>>(), vec![10, 20]);
+        assert_eq!(merged.topics[1].name.as_slice(), "b");
+        assert!(check_error_code(merged.topics[1].partitions[0].error_code).is_err());
+    }
+}
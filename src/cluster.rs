@@ -0,0 +1,123 @@
+use std::collections::HashMap;
+
+use protocol::{MetadataResponse, Broker, PartitionMetadata};
+use types::TopicPartition;
+
+/// An indexed view of a `MetadataResponse`, for looking up brokers and
+/// partition leaders without walking the raw metadata on every request.
+#[deriving(Show, Clone)]
+pub struct Cluster {
+    brokers: HashMap<i32, Broker>,
+    // Sorted by partition id. Topics the broker reported an error for are left out.
+    partitions: HashMap<String, Vec<PartitionMetadata>>
+}
+
+impl Cluster {
+    pub fn new(metadata: &MetadataResponse) -> Cluster {
+        let mut brokers = HashMap::new();
+        for broker in metadata.brokers.iter() {
+            brokers.insert(broker.node_id, broker.clone());
+        }
+
+        let mut partitions = HashMap::new();
+        for topic in metadata.topics.iter().filter(|topic| topic.error_code == 0) {
+            let mut topic_partitions = topic.partitions.clone();
+            topic_partitions.sort_by(|a, b| a.partition.cmp(&b.partition));
+            partitions.insert(topic.name.clone(), topic_partitions);
+        }
+
+        Cluster { brokers: brokers, partitions: partitions }
+    }
+
+    /// The broker leading a partition, or `None` if the partition is unknown
+    /// or a leader election is in progress.
+    pub fn leader_for(&self, partition: &TopicPartition) -> Option<&Broker> {
+        self.partition(partition).and_then(|metadata| self.broker_by_id(metadata.leader))
+    }
+
+    pub fn partition(&self, partition: &TopicPartition) -> Option<&PartitionMetadata> {
+        self.partitions_for(partition.topic.as_slice()).and_then(|partitions| {
+            partitions.binary_search(|metadata| metadata.partition.cmp(&partition.partition)).found()
+                .map(|index| &partitions[index])
+        })
+    }
+
+    /// A topic's partitions, ordered by partition id.
+    pub fn partitions_for(&self, topic: &str) -> Option<&[PartitionMetadata]> {
+        self.partitions.get(&String::from_str(topic)).map(|partitions| partitions.as_slice())
+    }
+
+    pub fn topics(&self) -> Vec<&String> {
+        let mut topics: Vec<&String> = self.partitions.keys().collect();
+        topics.sort();
+        topics
+    }
+
+    /// Every broker, ordered by node id.
+    pub fn brokers(&self) -> Vec<&Broker> {
+        let mut brokers: Vec<&Broker> = self.brokers.values().collect();
+        brokers.sort_by(|a, b| a.node_id.cmp(&b.node_id));
+        brokers
+    }
+
+    pub fn broker_by_id(&self, node_id: i32) -> Option<&Broker> {
+        self.brokers.get(&node_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use protocol::*;
+    use types::TopicPartition;
+    use super::*;
+
+    fn broker(node_id: i32) -> Broker {
+        Broker { node_id: node_id, host: format!("broker{}", node_id), port: 9092 }
+    }
+
+    fn partition(partition: i32, leader: i32) -> PartitionMetadata {
+        PartitionMetadata { error_code: 0, partition: partition, leader: leader, replicas: vec![1, 2], isr: vec![1, 2] }
+    }
+
+    fn cluster() -> Cluster {
+        Cluster::new(&MetadataResponse {
+            brokers: vec![broker(2), broker(1)],
+            topics: vec![
+                TopicMetadata {
+                    error_code: 0,
+                    name: String::from_str("events"),
+                    partitions: vec![partition(1, 2), partition(0, 1), partition(2, -1)]
+                },
+                TopicMetadata { error_code: 5, name: String::from_str("creating"), partitions: vec![] }
+            ]
+        })
+    }
+
+    #[test]
+    fn test_leader_for() {
+        let cluster = cluster();
+        assert_eq!(cluster.leader_for(&TopicPartition::new("events", 0)), Some(&broker(1)));
+        assert_eq!(cluster.leader_for(&TopicPartition::new("events", 1)), Some(&broker(2)));
+        // No leader while an election is in progress
+        assert_eq!(cluster.leader_for(&TopicPartition::new("events", 2)), None);
+        assert_eq!(cluster.leader_for(&TopicPartition::new("events", 3)), None);
+        assert_eq!(cluster.leader_for(&TopicPartition::new("other", 0)), None);
+    }
+
+    #[test]
+    fn test_partitions_for() {
+        let cluster = cluster();
+        let partitions: Vec<i32> = cluster.partitions_for("events").unwrap().iter().map(|p| p.partition).collect();
+        assert_eq!(partitions, vec![0, 1, 2]);
+        assert!(cluster.partitions_for("creating").is_none());
+        assert_eq!(cluster.topics(), vec![&String::from_str("events")]);
+    }
+
+    #[test]
+    fn test_brokers() {
+        let cluster = cluster();
+        assert_eq!(cluster.brokers(), vec![&broker(1), &broker(2)]);
+        assert_eq!(cluster.broker_by_id(2), Some(&broker(2)));
+        assert_eq!(cluster.broker_by_id(3), None);
+    }
+}
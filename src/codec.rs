@@ -0,0 +1,253 @@
+use std::io;
+use std::io::extensions::u64_from_be_bytes;
+use std::mem;
+
+use buffer::BufferPool;
+use protocol::*;
+use types::*;
+
+/// Limits applied when encoding requests and decoding responses.
+#[deriving(Show, Clone)]
+pub struct CodecConfig {
+    pub max_request_size: i32,
+    pub max_response_size: i32,
+    /// Rejects responses with bytes left over after decoding. Lenient decoding
+    /// ignores them, as a newer broker may append fields this client doesn't know.
+    pub strict: bool
+}
+
+impl CodecConfig {
+    pub fn new() -> CodecConfig {
+        CodecConfig {
+            max_request_size: 100 * 1024 * 1024,
+            max_response_size: 100 * 1024 * 1024,
+            strict: true
+        }
+    }
+}
+
+/// Running totals kept by an `Encoder` or `Decoder`.
+#[deriving(Show, Clone, PartialEq, Eq)]
+pub struct CodecStats {
+    pub messages: u64,
+    pub bytes: u64,
+    pub errors: u64
+}
+
+impl CodecStats {
+    fn new() -> CodecStats {
+        CodecStats { messages: 0, bytes: 0, errors: 0 }
+    }
+
+    fn record<T>(&mut self, result: &KafkaResult<T>, bytes: uint) {
+        match *result {
+            Ok(_) => {
+                self.messages += 1;
+                self.bytes += bytes as u64;
+            },
+            Err(_) => self.errors += 1
+        }
+    }
+}
+
+/// Frames requests for the wire, reusing one buffer between them.
+pub struct Encoder {
+    buffer: Vec<u8>,
+    config: CodecConfig,
+    stats: CodecStats
+}
+
+impl Encoder {
+    pub fn new(config: CodecConfig) -> Encoder {
+        Encoder { buffer: Vec::new(), config: config, stats: CodecStats::new() }
+    }
+
+    /// Encodes a request with its header and size prefix. The bytes returned
+    /// are only valid until the next call.
+    pub fn encode_request<T:Request>(&mut self, correlation_id: i32, client_id: &str, request: T) -> KafkaResult<&[u8]> {
+        let message = RequestOrResponse(RequestMessage {
+            correlation_id: correlation_id,
+            client_id: String::from_str(client_id),
+            request_message: request
+        });
+
+        let mut encoder = BufferEncoder::from_buffer(mem::replace(&mut self.buffer, Vec::new()));
+        let result = message.encode_buffered(&mut encoder);
+        self.buffer = encoder.unwrap();
+        let result = result.and_then(|()| {
+            if self.buffer.len() - 4 > self.config.max_request_size as uint {
+                fail!((MalformedRequestError, "Request larger than max_request_size"));
+            }
+            Ok(())
+        });
+
+        self.stats.record(&result, self.buffer.len());
+        try!(result);
+        Ok(self.buffer.as_slice())
+    }
+
+    pub fn stats(&self) -> &CodecStats {
+        &self.stats
+    }
+}
+
+/// Reads and decodes response frames, with buffers taken from a pool.
+pub struct Decoder {
+    buffers: BufferPool,
+    config: CodecConfig,
+    stats: CodecStats
+}
+
+impl Decoder {
+    pub fn new(config: CodecConfig) -> Decoder {
+        Decoder { buffers: BufferPool::new(4, 1024 * 1024), config: config, stats: CodecStats::new() }
+    }
+
+    /// Reads the body of one size-prefixed frame.
+    pub fn read_frame(&mut self, reader: &mut io::Reader) -> KafkaResult<Vec<u8>> {
+        let size = try!(reader.read_be_i32());
+        if size < 0 || size > self.config.max_response_size {
+            self.stats.errors += 1;
+            fail!((MalformedResponseError, "Invalid response size"));
+        }
+
+        let mut frame = self.buffers.checkout();
+        try!(reader.push_at_least(size as uint, size as uint, &mut frame));
+        Ok(frame)
+    }
+
+    /// Decodes a frame read by `read_frame`, returning its buffer to the pool.
+    pub fn decode_response<T:Response>(&mut self, correlation_id: i32, frame: Vec<u8>) -> KafkaResult<T> {
+        let result = if self.config.strict {
+            decode_from_slice::<ResponseMessage<T>>(frame.as_slice())
+        } else {
+            KafkaSerializable::decode(&mut io::BufReader::new(frame.as_slice()))
+        };
+        let result = result.and_then(|message: ResponseMessage<T>| {
+            try!(message.check_correlation_id(correlation_id));
+            Ok(message.response)
+        });
+
+        self.stats.record(&result, frame.len());
+        self.buffers.checkin(frame);
+        result
+    }
+
+    pub fn stats(&self) -> &CodecStats {
+        &self.stats
+    }
+}
+
+/// Reassembles length-prefixed frames from chunks of bytes as they arrive, for
+/// transports which can't hand over a blocking `Reader`.
+pub struct FrameCodec {
+    buffer: Vec<u8>,
+    start: uint,
+    max_frame_size: i32
+}
+
+impl FrameCodec {
+    pub fn new(max_frame_size: i32) -> FrameCodec {
+        FrameCodec {
+            buffer: Vec::new(),
+            start: 0,
+            max_frame_size: max_frame_size
+        }
+    }
+
+    pub fn feed(&mut self, bytes: &[u8]) {
+        // Drop frames which have already been handed out before growing further
+        if self.start > 0 {
+            let remaining = self.buffer.slice_from(self.start).to_vec();
+            self.buffer = remaining;
+            self.start = 0;
+        }
+        self.buffer.push_all(bytes);
+    }
+
+    /// Number of bytes received which don't belong to a returned frame yet.
+    pub fn buffered(&self) -> uint {
+        self.buffer.len() - self.start
+    }
+
+    /// Returns the body of the next frame, without its size prefix, once all of
+    /// it has been fed.
+    pub fn next_frame(&mut self) -> KafkaResult<Option<Vec<u8>>> {
+        if self.buffered() < 4 {
+            return Ok(None);
+        }
+
+        let size = u64_from_be_bytes(self.buffer.as_slice(), self.start, 4) as i32;
+        if size < 0 || size > self.max_frame_size {
+            fail!((MalformedResponseError, "Invalid frame size"));
+        }
+        if self.buffered() < 4 + size as uint {
+            return Ok(None);
+        }
+
+        let body_start = self.start + 4;
+        let body_end = body_start + size as uint;
+        let frame = self.buffer.slice(body_start, body_end).to_vec();
+        self.start = body_end;
+        Ok(Some(frame))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::BufReader;
+    use protocol::*;
+    use super::*;
+
+    #[test]
+    fn test_partial_frames() {
+        let mut codec = FrameCodec::new(1024);
+        codec.feed(&[0x00, 0x00]);
+        assert_eq!(codec.next_frame().ok().unwrap(), None);
+
+        codec.feed(&[0x00, 0x03, 0x01, 0x02]);
+        assert_eq!(codec.next_frame().ok().unwrap(), None);
+
+        codec.feed(&[0x03, 0x00, 0x00, 0x00, 0x01, 0x04, 0x00]);
+        assert_eq!(codec.next_frame().ok().unwrap(), Some(vec![0x01, 0x02, 0x03]));
+        assert_eq!(codec.next_frame().ok().unwrap(), Some(vec![0x04]));
+        assert_eq!(codec.next_frame().ok().unwrap(), None);
+        assert_eq!(codec.buffered(), 1);
+    }
+
+    #[test]
+    fn test_oversized_frame() {
+        let mut codec = FrameCodec::new(16);
+        codec.feed(&[0x00, 0x00, 0x01, 0x00]);
+        assert!(codec.next_frame().is_err());
+    }
+
+    #[test]
+    fn test_decoder_strictness() {
+        // Correlation id 7, an empty topic array and one unknown trailing byte
+        let frame = vec![0x00, 0x00, 0x00, 0x07, 0x00, 0x00, 0x00, 0x00, 0xff];
+
+        let mut strict = Decoder::new(CodecConfig::new());
+        assert!(strict.decode_response::<ProduceResponse>(7, frame.clone()).is_err());
+        assert_eq!(strict.stats().errors, 1);
+
+        let mut lenient = Decoder::new(CodecConfig { strict: false, ..CodecConfig::new() });
+        let response = lenient.decode_response::<ProduceResponse>(7, frame).ok().unwrap();
+        assert!(response.topics.is_empty());
+        assert_eq!(lenient.stats().messages, 1);
+        assert_eq!(lenient.stats().bytes, 9);
+    }
+
+    #[test]
+    fn test_size_limits() {
+        let config = CodecConfig { max_request_size: 16, max_response_size: 16, ..CodecConfig::new() };
+        let mut encoder = Encoder::new(config.clone());
+        let request = MetadataRequest { topic_names: vec![String::from_str("a-rather-long-topic")] };
+        assert!(encoder.encode_request(0, "client", request).is_err());
+        assert_eq!(encoder.stats().errors, 1);
+
+        let mut decoder = Decoder::new(config);
+        let mut reader = BufReader::new(&[0x00, 0x00, 0x01, 0x00]);
+        assert!(decoder.read_frame(&mut reader).is_err());
+    }
+}
@@ -0,0 +1,350 @@
+use std::cmp;
+use std::collections::HashMap;
+use std::i32;
+use std::io;
+use std::io::{IoResult, TcpStream};
+use std::io::extensions::u64_from_be_bytes;
+use std::io::net::addrinfo::get_host_addresses;
+use std::io::net::ip::SocketAddr;
+use std::time::Duration;
+
+use codec::{CodecConfig, CodecStats, Encoder, Decoder};
+use protocol::*;
+use types::*;
+
+#[deriving(Show, Clone)]
+pub struct ConnectionConfig {
+    /// Disables Nagle's algorithm, so small requests aren't held back.
+    pub nodelay: bool,
+    /// Seconds of idleness before TCP keepalive probes are sent, if enabled.
+    pub keepalive: Option<uint>,
+    pub connect_timeout: Option<Duration>,
+    /// Limit on waiting for a response; should exceed any fetch max_wait_time.
+    pub read_timeout: Option<Duration>,
+    pub write_timeout: Option<Duration>,
+    /// Requests which may be awaiting a response at once. Sending another
+    /// first reads responses until one is collected.
+    pub max_in_flight: uint,
+    pub codec: CodecConfig
+}
+
+impl ConnectionConfig {
+    pub fn new() -> ConnectionConfig {
+        ConnectionConfig {
+            nodelay: true,
+            keepalive: None,
+            connect_timeout: Some(Duration::seconds(10)),
+            read_timeout: Some(Duration::seconds(30)),
+            write_timeout: Some(Duration::seconds(30)),
+            max_in_flight: 5,
+            codec: CodecConfig::new()
+        }
+    }
+}
+
+/// A connection to a single broker. Several requests may be outstanding at
+/// once; each gets its own correlation id, and responses are matched back to
+/// their requests regardless of the order in which they're collected.
+pub struct KafkaConnection {
+    host: String,
+    port: u16,
+    client_id: String,
+    stream: TcpStream,
+    read_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+    next_correlation_id: i32,
+    max_in_flight: uint,
+    // Correlation ids of requests whose response hasn't been read yet
+    in_flight: HashMap<i32, i16>,
+    // Responses that were read while waiting for a different one
+    completed: HashMap<i32, Vec<u8>>,
+    encoder: Encoder,
+    decoder: Decoder
+}
+
+impl KafkaConnection {
+    pub fn connect(host: &str, port: u16, client_id: &str, config: &ConnectionConfig) -> KafkaResult<KafkaConnection> {
+        let stream = match KafkaConnection::open_stream(host, port, config) {
+            Ok(stream) => stream,
+            Err(err) => {
+                let err: KafkaError = ::std::error::FromError::from_error(err);
+                return Err(err.with_context("Connect", Some(format!("{}:{}", host, port))));
+            }
+        };
+
+        Ok(KafkaConnection {
+            host: String::from_str(host),
+            port: port,
+            client_id: String::from_str(client_id),
+            stream: stream,
+            read_timeout: config.read_timeout,
+            write_timeout: config.write_timeout,
+            next_correlation_id: 0,
+            max_in_flight: cmp::max(config.max_in_flight, 1),
+            in_flight: HashMap::new(),
+            completed: HashMap::new(),
+            encoder: Encoder::new(config.codec.clone()),
+            decoder: Decoder::new(config.codec.clone())
+        })
+    }
+
+    fn open_stream(host: &str, port: u16, config: &ConnectionConfig) -> IoResult<TcpStream> {
+        let mut stream = try!(KafkaConnection::connect_any(host, port, config));
+        try!(stream.set_nodelay(config.nodelay));
+        try!(stream.set_keepalive(config.keepalive));
+        Ok(stream)
+    }
+
+    // Tries each address the host resolves to in turn, returning the last
+    // failure if none of them accept the connection.
+    fn connect_any(host: &str, port: u16, config: &ConnectionConfig) -> IoResult<TcpStream> {
+        let addresses = try!(get_host_addresses(host));
+        let mut last_error = io::standard_error(io::InvalidInput);
+        for &ip in addresses.iter() {
+            let result = match config.connect_timeout {
+                Some(timeout) => TcpStream::connect_timeout(SocketAddr { ip: ip, port: port }, timeout),
+                None => TcpStream::connect(ip.to_string().as_slice(), port)
+            };
+            match result {
+                Ok(stream) => return Ok(stream),
+                Err(err) => last_error = err
+            }
+        }
+        Err(last_error)
+    }
+
+    pub fn host(&self) -> &str {
+        self.host.as_slice()
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// Number of requests sent whose response hasn't been received.
+    pub fn in_flight(&self) -> uint {
+        self.in_flight.len() + self.completed.len()
+    }
+
+    /// Writes a request without waiting for the response, returning the
+    /// correlation id to pass to `receive`. Requests which get no response
+    /// aren't counted as in flight.
+    pub fn send<T:Request>(&mut self, request: T) -> KafkaResult<i32> {
+        while self.in_flight.len() >= self.max_in_flight {
+            let oldest = *self.in_flight.keys().min().unwrap();
+            try!(self.read_response(oldest));
+        }
+
+        let correlation_id = self.next_correlation_id;
+        self.next_correlation_id = if correlation_id == i32::MAX { 0 } else { correlation_id + 1 };
+
+        let api_key = Request::api_key(None::<T>);
+        let expects_response = request.expects_response();
+        let bytes = try!(self.encoder.encode_request(correlation_id, self.client_id.as_slice(), request));
+        self.stream.set_write_timeout(to_millis(self.write_timeout));
+        try!(self.stream.write(bytes));
+        try!(self.stream.flush());
+
+        if expects_response {
+            self.in_flight.insert(correlation_id, api_key);
+        }
+        Ok(correlation_id)
+    }
+
+    /// Waits for the response to the request sent as `correlation_id`.
+    pub fn receive<T:Response>(&mut self, correlation_id: i32) -> KafkaResult<T> {
+        if !self.in_flight.contains_key(&correlation_id) && !self.completed.contains_key(&correlation_id) {
+            fail!((MalformedRequestError, "No request in flight with this correlation ID"));
+        }
+
+        loop {
+            match self.completed.remove(&correlation_id) {
+                Some(frame) => return self.decoder.decode_response(correlation_id, frame),
+                None => ()
+            }
+
+            try!(self.read_response(correlation_id));
+        }
+    }
+
+    /// Sends a request and waits for its response, if the broker sends one.
+    pub fn request<T:Request, U:Response>(&mut self, request: T) -> KafkaResult<U> {
+        let expects_response = request.expects_response();
+        let correlation_id = try!(self.send(request));
+        if !expects_response {
+            return Response::unanswered(None::<U>);
+        }
+        self.receive(correlation_id)
+    }
+
+    /// Totals for the requests written on this connection.
+    pub fn encoder_stats(&self) -> &CodecStats {
+        self.encoder.stats()
+    }
+
+    /// Totals for the responses read on this connection.
+    pub fn decoder_stats(&self) -> &CodecStats {
+        self.decoder.stats()
+    }
+
+    // Reads whichever response arrives next and sets it aside in `completed`.
+    // `expected` is only used to report a response nobody is waiting for.
+    fn read_response(&mut self, expected: i32) -> KafkaResult<()> {
+        let frame = try!(self.read_frame());
+        if frame.len() < 4 {
+            fail!((MalformedResponseError, "Response too short for a correlation ID"));
+        }
+
+        let received = u64_from_be_bytes(frame.as_slice(), 0, 4) as i32;
+        if self.in_flight.remove(&received).is_none() {
+            fail!((ProtocolMismatchError(expected, received), "Response for unknown correlation ID"));
+        }
+        self.completed.insert(received, frame);
+        Ok(())
+    }
+
+    fn read_frame(&mut self) -> KafkaResult<Vec<u8>> {
+        // Stream timeouts are deadlines fixed when set, so renew them per frame
+        self.stream.set_read_timeout(to_millis(self.read_timeout));
+        self.decoder.read_frame(&mut self.stream)
+    }
+}
+
+/// Splits a broker address like "host:port" or "[::1]:port", defaulting to
+/// port 9092 when none is given.
+pub fn parse_host_port(address: &str) -> KafkaResult<(String, u16)> {
+    let (host, port) = if address.starts_with("[") {
+        let end = match address.find(']') {
+            Some(end) => end,
+            None => fail!((InvalidHost, "Unterminated IPv6 address"))
+        };
+        let rest = address.slice_from(end + 1);
+        if rest.is_empty() {
+            (address.slice(1, end), None)
+        } else if rest.starts_with(":") {
+            (address.slice(1, end), Some(rest.slice_from(1)))
+        } else {
+            fail!((InvalidHost, "Unexpected characters after IPv6 address"));
+        }
+    } else if address.chars().filter(|&c| c == ':').count() > 1 {
+        // An IPv6 literal without brackets can't carry a port
+        (address, None)
+    } else {
+        match address.rfind(':') {
+            Some(colon) => (address.slice_to(colon), Some(address.slice_from(colon + 1))),
+            None => (address, None)
+        }
+    };
+
+    if host.is_empty() {
+        fail!((InvalidHost, "Missing broker host"));
+    }
+    let port = match port {
+        Some(port) => match from_str::<u16>(port) {
+            Some(port) => port,
+            None => fail!((InvalidHost, "Invalid broker port"))
+        },
+        None => 9092
+    };
+    Ok((String::from_str(host), port))
+}
+
+fn to_millis(duration: Option<Duration>) -> Option<u64> {
+    duration.map(|duration| duration.num_milliseconds() as u64)
+}
+
+/// Idle connections per broker, keyed by "host:port". Connections are reused
+/// most recently returned first, and dropped once idle for too long.
+pub struct ConnectionPool<C> {
+    // Each connection with the time it was checked in, in nanoseconds
+    idle: HashMap<String, Vec<(u64, C)>>,
+    max_idle_per_broker: uint,
+    idle_timeout_ns: u64
+}
+
+impl <C> ConnectionPool<C> {
+    pub fn new(max_idle_per_broker: uint, idle_timeout: Duration) -> ConnectionPool<C> {
+        ConnectionPool {
+            idle: HashMap::new(),
+            max_idle_per_broker: max_idle_per_broker,
+            idle_timeout_ns: idle_timeout.num_milliseconds() as u64 * 1000000
+        }
+    }
+
+    pub fn checkout(&mut self, key: &String, now: u64) -> Option<C> {
+        self.reap(now);
+        match self.idle.get_mut(key) {
+            Some(connections) => connections.pop().map(|(_, connection)| connection),
+            None => None
+        }
+    }
+
+    pub fn checkin(&mut self, key: String, connection: C, now: u64) {
+        self.reap(now);
+        if !self.idle.contains_key(&key) {
+            self.idle.insert(key.clone(), Vec::new());
+        }
+
+        let connections = self.idle.get_mut(&key).unwrap();
+        if connections.len() < self.max_idle_per_broker {
+            connections.push((now, connection));
+        }
+    }
+
+    pub fn idle(&self, key: &String) -> uint {
+        self.idle.get(key).map_or(0, |connections| connections.len())
+    }
+
+    fn reap(&mut self, now: u64) {
+        let timeout = self.idle_timeout_ns;
+        for (_, connections) in self.idle.iter_mut() {
+            connections.retain(|&(since, _)| now - since < timeout);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use super::*;
+
+    fn host(address: &str) -> (String, u16) {
+        parse_host_port(address).ok().unwrap()
+    }
+
+    #[test]
+    fn test_parse_host_port() {
+        assert_eq!(host("localhost:9093"), (String::from_str("localhost"), 9093));
+        assert_eq!(host("localhost"), (String::from_str("localhost"), 9092));
+        assert_eq!(host("[::1]:9093"), (String::from_str("::1"), 9093));
+        assert_eq!(host("[fe80::1]"), (String::from_str("fe80::1"), 9092));
+        assert_eq!(host("fe80::1"), (String::from_str("fe80::1"), 9092));
+        assert!(parse_host_port("localhost:http").is_err());
+        assert!(parse_host_port("[::1").is_err());
+        assert!(parse_host_port(":9092").is_err());
+    }
+
+    #[test]
+    fn test_pool_limits() {
+        let key = String::from_str("localhost:9092");
+        let mut pool = ConnectionPool::new(2, Duration::seconds(1));
+        pool.checkin(key.clone(), 1i, 0);
+        pool.checkin(key.clone(), 2i, 0);
+        pool.checkin(key.clone(), 3i, 0);
+        assert_eq!(pool.idle(&key), 2);
+
+        assert_eq!(pool.checkout(&key, 0), Some(2));
+        assert_eq!(pool.checkout(&key, 0), Some(1));
+        assert_eq!(pool.checkout(&key, 0), None);
+    }
+
+    #[test]
+    fn test_pool_idle_timeout() {
+        let key = String::from_str("localhost:9092");
+        let mut pool = ConnectionPool::new(2, Duration::milliseconds(10));
+        pool.checkin(key.clone(), 1i, 0);
+        pool.checkin(key.clone(), 2i, 5000000);
+        assert_eq!(pool.checkout(&key, 12000000), Some(2));
+        assert_eq!(pool.checkout(&key, 12000000), None);
+    }
+}
@@ -0,0 +1,1372 @@
+use std::cmp;
+use std::collections::{HashMap, RingBuf};
+use std::i32;
+use std::io::timer;
+use std::time::Duration;
+
+use regex::Regex;
+use serialize::json;
+use time;
+
+use cache::{RecordCache, CacheStats};
+use cancel::CancellationToken;
+use client::{KafkaClient, CommitResults, OffsetAndMetadata, InternalTopics, EARLIEST_OFFSET, LATEST_OFFSET};
+use cluster::Cluster;
+use group::{GroupMember, Heartbeater, HeartbeatOk, RejoinNeeded, Fenced};
+use protocol::*;
+use types::*;
+
+/// A message read from a partition, with where it was read from.
+#[deriving(Show, PartialEq, Eq, Clone)]
+pub struct ConsumerRecord {
+    pub topic: String,
+    pub partition: i32,
+    pub offset: i64,
+    pub key: Option<Vec<u8>>,
+    pub value: Option<Vec<u8>>
+}
+
+/// What the consumer does when its position in a partition is out of the
+/// range of offsets the broker has, as after retention deleted the records.
+#[deriving(Show, PartialEq, Eq, Clone)]
+pub enum OffsetReset {
+    /// Return the `OffsetOutOfRange` error.
+    ResetNone,
+    /// Carry on from the oldest record the broker still has.
+    ResetEarliest,
+    /// Skip to the end, reading only records written from now on.
+    ResetLatest
+}
+
+impl OffsetReset {
+    // The time to ask `fetch_offset` for, if the position is to be reset
+    fn offset_time(&self) -> Option<i64> {
+        match *self {
+            ResetNone => None,
+            ResetEarliest => Some(EARLIEST_OFFSET),
+            ResetLatest => Some(LATEST_OFFSET)
+        }
+    }
+}
+
+#[deriving(Show, Clone)]
+pub struct ConsumerConfig {
+    /// Bytes requested from each partition per fetch. A message larger than
+    /// this can't be read, unless `fetch_max_bytes_ceiling` allows more.
+    pub fetch_max_bytes: i32,
+    /// When a fetch returns no whole message though the partition has more,
+    /// the next message is too large for the fetch size. The partition's
+    /// fetch size is then doubled, up to this ceiling, until the message is
+    /// read, and goes back to normal after. Stalls there when `None`.
+    pub fetch_max_bytes_ceiling: Option<i32>,
+    /// Records per second read from any one partition. A partition over its
+    /// limit is left out of fetches until it's back under.
+    pub max_records_per_sec: Option<u64>,
+    /// Bytes of keys and values per second read from any one partition.
+    pub max_bytes_per_sec: Option<u64>,
+    /// Size of a cache of recently read records, so `read_at` can often
+    /// avoid going to the broker. No records are cached when `None`.
+    pub record_cache_bytes: Option<uint>,
+    /// Defaults to `ResetNone`.
+    pub auto_offset_reset: OffsetReset,
+    /// Records returned by one `fetch` or `poll` at most, taken in turn from
+    /// each partition with records so one deep backlog can't fill every
+    /// batch. The rest are buffered, and their partitions aren't fetched
+    /// from until the buffer empties. Unlimited when `None`.
+    pub max_poll_records: Option<uint>,
+    /// The group whose offsets are committed, and which `subscribe` joins.
+    /// Nothing is committed without one.
+    pub group_id: Option<String>,
+    /// Makes the consumer a static member of its group, which keeps its
+    /// partitions when restarted with the same id within `session_timeout`,
+    /// without the rest of the group rebalancing. Each instance needs its
+    /// own, e.g. its host name. Needs a group coordinator from Kafka 2.3 on;
+    /// older ones are joined as a dynamic member, without the id.
+    pub group_instance_id: Option<String>,
+    /// How long the group coordinator waits for a heartbeat before dropping
+    /// a member and rebalancing.
+    pub session_timeout: Duration,
+    /// How long a rebalance waits for every member to rejoin. Joining blocks
+    /// for up to this long, so it has to stay below the connection's
+    /// `read_timeout`.
+    pub rebalance_timeout: Duration,
+    /// Heartbeats are sent this often while a member of a group, usually a
+    /// third of `session_timeout`.
+    pub heartbeat_interval: Duration,
+    /// Commit the positions reached by earlier fetches and polls every
+    /// `auto_commit_interval`. Turn off to commit by hand. Failed commits
+    /// don't stop fetching and polling, and are retried at the next
+    /// interval; an `AutoCommitListener` hears about them.
+    pub enable_auto_commit: bool,
+    pub auto_commit_interval: Duration,
+    /// How often a pattern subscription looks for topics created or deleted.
+    pub subscription_refresh_interval: Duration
+}
+
+impl ConsumerConfig {
+    pub fn new() -> ConsumerConfig {
+        ConsumerConfig {
+            fetch_max_bytes: FETCH_BYTES,
+            fetch_max_bytes_ceiling: None,
+            max_records_per_sec: None,
+            max_bytes_per_sec: None,
+            record_cache_bytes: None,
+            auto_offset_reset: ResetNone,
+            max_poll_records: None,
+            group_id: None,
+            group_instance_id: None,
+            session_timeout: Duration::seconds(10),
+            rebalance_timeout: Duration::seconds(20),
+            heartbeat_interval: Duration::seconds(3),
+            enable_auto_commit: true,
+            auto_commit_interval: Duration::seconds(5),
+            subscription_refresh_interval: Duration::minutes(5)
+        }
+    }
+}
+
+// How long a cancellable poll may wait before checking its token again
+const CANCEL_CHECK_MS: u64 = 100;
+
+// How much sooner than the connection's read timeout a fetch's max_wait_time
+// must end, leaving the broker time to respond
+const READ_TIMEOUT_MARGIN_MS: u64 = 1000;
+
+/// A partition assigned to a consumer, and the next offset it will read.
+#[deriving(Show, PartialEq, Eq, Clone, Encodable)]
+pub struct AssignedPartition {
+    pub topic: String,
+    pub partition: i32,
+    pub position: i64
+}
+
+/// What a consumer is reading, for external schedulers such as autoscalers
+/// to gather from each instance and judge how partitions are spread.
+#[deriving(Show, PartialEq, Eq, Clone, Encodable)]
+pub struct AssignmentSnapshot {
+    pub partitions: Vec<AssignedPartition>
+}
+
+impl AssignmentSnapshot {
+    /// E.g. `{"partitions":[{"topic":"events","partition":0,"position":42}]}`
+    pub fn to_json(&self) -> String {
+        json::encode(self)
+    }
+}
+
+/// Told about offsets missing between consecutive records of a partition,
+/// which may mean records were lost.
+pub trait GapListener {
+    /// Offsets `from` up to but excluding `until` were skipped.
+    fn on_gap(&mut self, partition: &TopicPartition, from: i64, until: i64);
+}
+
+/// Told the outcome of each automatic commit.
+pub trait AutoCommitListener {
+    /// `results` says whether each partition's offset was stored, or why
+    /// the commit failed as a whole.
+    fn on_auto_commit(&mut self, results: &KafkaResult<CommitResults>);
+}
+
+// What `subscribe_pattern` keeps the assignment in line with
+struct Subscription {
+    pattern: Regex,
+    internal: InternalTopics,
+    initial_offset: i64,
+    last_refresh: u64
+}
+
+// The consumer's place in the group `subscribe` joined
+struct Membership {
+    member: GroupMember,
+    topics: Vec<String>,
+    initial_offset: i64,
+    heartbeater: Heartbeater,
+    // Who to rejoin as on the next fetch or poll, after a join failed
+    rejoin: Option<GroupMember>
+}
+
+/// Told when `Consumer::reassign` moves partitions to or from the
+/// consumer, as a group rebalance does, so the application can commit
+/// offsets and flush or load per-partition state at the right moments.
+pub trait RebalanceListener {
+    /// Called before `partitions` are taken away, while their positions can
+    /// still be committed.
+    fn on_partitions_revoked(&mut self, consumer: &mut Consumer, partitions: &[TopicPartition]);
+
+    /// Called once `partitions` have been added and positioned, before any
+    /// of their records are fetched.
+    fn on_partitions_assigned(&mut self, consumer: &mut Consumer, partitions: &[TopicPartition]);
+}
+
+/// Reads records from the partitions assigned to it, keeping track of the
+/// next offset to read from each.
+pub struct Consumer {
+    client: KafkaClient,
+    config: ConsumerConfig,
+    // The next offset to fetch from each assigned partition
+    positions: HashMap<TopicPartition, i64>,
+    // Records fetched but not yet returned, over `max_poll_records`
+    buffered: HashMap<TopicPartition, RingBuf<ConsumerRecord>>,
+    // Where the next batch starts taking buffered records from
+    next_partition: uint,
+    throttles: HashMap<TopicPartition, Throttle>,
+    // Partitions weighted other than 1
+    weights: HashMap<TopicPartition, uint>,
+    // Fetch sizes of partitions stalled on a message larger than usual
+    grown_max_bytes: HashMap<TopicPartition, i32>,
+    // The high watermark in each partition's last fetch response
+    highwater_marks: HashMap<TopicPartition, i64>,
+    // Heap bytes held by each partition's last decoded fetch response
+    decoded_bytes: HashMap<TopicPartition, uint>,
+    cache: Option<RecordCache>,
+    // Offsets last committed, so unchanged ones aren't committed again
+    committed: HashMap<TopicPartition, i64>,
+    last_auto_commit: u64,
+    gap_listener: Option<Box<GapListener + Send>>,
+    auto_commit_listener: Option<Box<AutoCommitListener + Send>>,
+    rebalance_listener: Option<Box<RebalanceListener + Send>>,
+    subscription: Option<Subscription>,
+    membership: Option<Membership>
+}
+
+impl Consumer {
+    pub fn new(client: KafkaClient) -> Consumer {
+        Consumer::with_config(client, ConsumerConfig::new())
+    }
+
+    pub fn with_config(client: KafkaClient, config: ConsumerConfig) -> Consumer {
+        let cache = config.record_cache_bytes.map(|max_bytes| RecordCache::new(max_bytes));
+        Consumer {
+            client: client,
+            config: config,
+            positions: HashMap::new(),
+            buffered: HashMap::new(),
+            next_partition: 0,
+            throttles: HashMap::new(),
+            weights: HashMap::new(),
+            grown_max_bytes: HashMap::new(),
+            highwater_marks: HashMap::new(),
+            decoded_bytes: HashMap::new(),
+            cache: cache,
+            committed: HashMap::new(),
+            last_auto_commit: time::precise_time_ns(),
+            gap_listener: None,
+            auto_commit_listener: None,
+            rebalance_listener: None,
+            subscription: None,
+            membership: None
+        }
+    }
+
+    /// Starts reading a partition from `offset`, which may also be
+    /// `EARLIEST_OFFSET` or `LATEST_OFFSET`.
+    pub fn assign(&mut self, partition: TopicPartition, offset: i64) -> KafkaResult<()> {
+        let offset = if offset < 0 { try!(self.client.fetch_offset(&partition, offset)) } else { offset };
+        let throttle = Throttle::new(&self.config, time::precise_time_ns());
+        self.throttles.insert(partition.clone(), throttle);
+        self.positions.insert(partition, offset);
+        Ok(())
+    }
+
+    pub fn unassign(&mut self, partition: &TopicPartition) {
+        self.positions.remove(partition);
+        self.buffered.remove(partition);
+        self.throttles.remove(partition);
+        self.weights.remove(partition);
+        self.grown_max_bytes.remove(partition);
+        self.highwater_marks.remove(partition);
+        self.decoded_bytes.remove(partition);
+        self.committed.remove(partition);
+    }
+
+    /// Replaces the whole assignment with `assignment`, each partition
+    /// starting from its offset as with `assign`. Partitions kept keep their
+    /// positions. The rebalance listener, if set, is told which partitions
+    /// were revoked before they go and which were added once they're in.
+    pub fn reassign(&mut self, assignment: Vec<(TopicPartition, i64)>) -> KafkaResult<()> {
+        let current = self.assignment();
+        let revoked: Vec<TopicPartition> = current.iter()
+            .filter(|&partition| !assignment.iter().any(|&(ref kept, _)| kept == partition))
+            .map(|partition| partition.clone())
+            .collect();
+        let added: Vec<(TopicPartition, i64)> = assignment.into_iter()
+            .filter(|&(ref partition, _)| !current.contains(partition))
+            .collect();
+
+        self.notify_rebalance(revoked.as_slice(), |listener, consumer, partitions| {
+            listener.on_partitions_revoked(consumer, partitions)
+        });
+        for partition in revoked.iter() {
+            self.unassign(partition);
+        }
+
+        let mut assigned = vec![];
+        for (partition, offset) in added.into_iter() {
+            try!(self.assign(partition.clone(), offset));
+            assigned.push(partition);
+        }
+        self.notify_rebalance(assigned.as_slice(), |listener, consumer, partitions| {
+            listener.on_partitions_assigned(consumer, partitions)
+        });
+        Ok(())
+    }
+
+    /// Calls `listener` around changes made by `reassign`.
+    pub fn set_rebalance_listener(&mut self, listener: Box<RebalanceListener + Send>) {
+        self.rebalance_listener = Some(listener);
+    }
+
+    // The listener is taken out while it runs, so it can be handed the consumer
+    fn notify_rebalance(&mut self, partitions: &[TopicPartition],
+                        notify: |&mut Box<RebalanceListener + Send>, &mut Consumer, &[TopicPartition]|) {
+        match self.rebalance_listener.take() {
+            Some(mut listener) => {
+                notify(&mut listener, self, partitions);
+                // Unless the listener set a new one
+                if self.rebalance_listener.is_none() {
+                    self.rebalance_listener = Some(listener);
+                }
+            },
+            None => ()
+        }
+    }
+
+    /// Assigns every partition of the topics whose names match `pattern`,
+    /// and every `subscription_refresh_interval` after, during a fetch or
+    /// poll, reloads metadata to take on topics created since and drop
+    /// those deleted. Partitions start from the group's committed offset,
+    /// or `initial_offset` without one. The pattern matches anywhere in a
+    /// name unless anchored with `^` and `$`.
+    ///
+    /// This doesn't join a group, so every consumer subscribed with the
+    /// same pattern reads every matching partition; `subscribe` shares
+    /// topics' partitions among a group instead. Changes go through
+    /// `reassign`, so the rebalance listener hears about them.
+    pub fn subscribe_pattern(&mut self, pattern: Regex, internal: InternalTopics,
+                             initial_offset: i64) -> KafkaResult<()> {
+        self.membership = None;
+        self.subscription = Some(Subscription {
+            pattern: pattern,
+            internal: internal,
+            initial_offset: initial_offset,
+            last_refresh: 0
+        });
+        self.refresh_subscription()
+    }
+
+    /// Joins the consumer's `group_id` as a consumer of `topics`, which the
+    /// group's members share out between them, and assigns those given to
+    /// this one. Partitions start from the group's committed offset, or
+    /// `initial_offset` without one.
+    ///
+    /// Heartbeats are then sent in the background. When the group
+    /// rebalances, or the member is dropped from it, the next fetch or poll
+    /// rejoins, retrying on later ones if that fails. The rebalance listener
+    /// hears about partitions revoked before rejoining, while it can still
+    /// commit as the member of the old generation, and about those assigned
+    /// once the group has handed them out. A static member fenced by another
+    /// consumer with its `group_instance_id` loses its partitions and stops
+    /// rejoining.
+    pub fn subscribe(&mut self, topics: Vec<String>, initial_offset: i64) -> KafkaResult<()> {
+        let group = try!(self.group());
+        self.subscription = None;
+        let member = GroupMember::new(group.as_slice(), self.config.group_instance_id.clone());
+        self.join(member, topics, initial_offset)
+    }
+
+    /// Drops the subscription along with every assigned partition. A group
+    /// member just stops heartbeating, so the group rebalances without it
+    /// once its session times out, or for a static member, unless it comes
+    /// back by then.
+    pub fn unsubscribe(&mut self) -> KafkaResult<()> {
+        self.subscription = None;
+        self.membership = None;
+        self.reassign(vec![])
+    }
+
+    // Partitions are revoked first, while offsets can still be committed as
+    // the old member, and assigned once SyncGroup has handed them out. The
+    // old membership stays until the join succeeds; if it fails, the next
+    // fetch or poll tries again.
+    fn join(&mut self, member: GroupMember, topics: Vec<String>, initial_offset: i64) -> KafkaResult<()> {
+        if self.config.enable_auto_commit {
+            self.auto_commit();
+        }
+        try!(self.reassign(vec![]));
+
+        let (joined, partitions) = match self.client.join_consumer_group(&member, topics.as_slice(),
+                                                                         self.config.session_timeout,
+                                                                         self.config.rebalance_timeout) {
+            Ok(joined) => joined,
+            Err(err @ KafkaError { kind: BrokerError(FencedInstanceIdCode), .. }) => {
+                self.membership = None;
+                return Err(err);
+            },
+            Err(err) => {
+                match self.membership {
+                    Some(ref mut membership) => {
+                        membership.topics = topics;
+                        membership.initial_offset = initial_offset;
+                        membership.rejoin = Some(member);
+                    },
+                    None => ()
+                }
+                return Err(err);
+            }
+        };
+
+        // Dropping the old heartbeater stops it
+        let heartbeater = Heartbeater::start(self.client.clone(), joined.clone(), self.config.heartbeat_interval,
+                                             self.config.session_timeout);
+        self.membership = Some(Membership {
+            member: joined.clone(),
+            topics: topics,
+            initial_offset: initial_offset,
+            heartbeater: heartbeater,
+            rejoin: None
+        });
+
+        let committed = if partitions.is_empty() {
+            Ok(HashMap::new())
+        } else {
+            self.client.committed_offsets(joined.group_id.as_slice(), partitions.as_slice())
+        };
+        let committed = match committed {
+            Ok(committed) => committed,
+            Err(err) => {
+                // Rejoined on the next fetch or poll, to get the partitions again
+                self.membership.as_mut().unwrap().rejoin = Some(joined);
+                return Err(err);
+            }
+        };
+        let assignment = partitions.into_iter().map(|partition| {
+            let offset = match committed.get(&partition) {
+                Some(&Some(ref committed)) => committed.offset,
+                _ => initial_offset
+            };
+            (partition, offset)
+        }).collect();
+        self.reassign(assignment)
+    }
+
+    fn maybe_rejoin(&mut self) -> KafkaResult<()> {
+        let (member, topics, initial_offset) = match self.membership {
+            Some(ref membership) => {
+                let member = match (&membership.rejoin, membership.heartbeater.status()) {
+                    (&Some(ref member), _) => Some(member.clone()),
+                    (&None, HeartbeatOk) => return Ok(()),
+                    (&None, Fenced) => None,
+                    // Still in the group, which is rebalancing
+                    (&None, RejoinNeeded(RebalanceInProgressCode)) => Some(membership.member.clone()),
+                    // Dropped from the group, so a new member id is needed. A
+                    // static member still gets its partitions back.
+                    _ => Some(GroupMember::new(membership.member.group_id.as_slice(),
+                                               membership.member.group_instance_id.clone()))
+                };
+                (member, membership.topics.clone(), membership.initial_offset)
+            },
+            None => return Ok(())
+        };
+        match member {
+            Some(member) => self.join(member, topics, initial_offset),
+            None => self.leave_fenced()
+        }
+    }
+
+    // Another consumer has the member's instance id, and its partitions
+    fn leave_fenced(&mut self) -> KafkaResult<()> {
+        self.membership = None;
+        try!(self.reassign(vec![]));
+        fail!(FencedInstanceIdCode)
+    }
+
+    fn maybe_refresh_subscription(&mut self) -> KafkaResult<()> {
+        let interval = self.config.subscription_refresh_interval.num_milliseconds() as u64 * 1000000;
+        match self.subscription {
+            Some(ref subscription) if time::precise_time_ns() - subscription.last_refresh >= interval => (),
+            _ => return Ok(())
+        }
+        self.refresh_subscription()
+    }
+
+    fn refresh_subscription(&mut self) -> KafkaResult<()> {
+        try!(self.client.load_metadata(vec![]));
+        let cluster = match self.client.cluster() {
+            Some(cluster) => cluster,
+            None => fail!((NoMetadata, "No metadata loaded"))
+        };
+        let (matched, initial_offset) = match self.subscription {
+            Some(ref mut subscription) => {
+                subscription.last_refresh = time::precise_time_ns();
+                (matching_partitions(&cluster, &subscription.pattern, &subscription.internal),
+                 subscription.initial_offset)
+            },
+            None => return Ok(())
+        };
+
+        let current = self.assignment();
+        let added: Vec<TopicPartition> = matched.iter().filter(|&p| !current.contains(p)).map(|p| p.clone()).collect();
+        let committed = if self.config.group_id.is_some() && !added.is_empty() {
+            try!(self.committed(added.as_slice()))
+        } else {
+            HashMap::new()
+        };
+
+        let assignment = matched.into_iter().map(|partition| {
+            let offset = match committed.get(&partition) {
+                Some(&Some(ref committed)) => committed.offset,
+                // Kept partitions keep their positions whatever is given here
+                _ => initial_offset
+            };
+            (partition, offset)
+        }).collect();
+        self.reassign(assignment)
+    }
+
+    /// Prioritizes a partition, for topics carrying latency-critical data.
+    /// Partitions are fetched heaviest first, each asking for up to
+    /// `weight * fetch_max_bytes`, so when the consumer is behind the
+    /// heavier ones catch up sooner. The default weight is 1; 0 is taken as 1.
+    pub fn set_weight(&mut self, partition: &TopicPartition, weight: uint) {
+        if weight > 1 {
+            self.weights.insert(partition.clone(), weight);
+        } else {
+            self.weights.remove(partition);
+        }
+    }
+
+    /// The assigned partitions, in order.
+    pub fn assignment(&self) -> Vec<TopicPartition> {
+        let mut partitions: Vec<TopicPartition> = self.positions.keys().map(|partition| partition.clone()).collect();
+        partitions.sort();
+        partitions
+    }
+
+    /// The assignment and positions, in partition order. Whether partitions
+    /// were assigned explicitly or by the group, this is the whole of what
+    /// this consumer has been given.
+    pub fn assignment_snapshot(&self) -> AssignmentSnapshot {
+        AssignmentSnapshot {
+            partitions: self.assignment().into_iter().map(|partition| AssignedPartition {
+                position: self.position(&partition).unwrap(),
+                topic: partition.topic,
+                partition: partition.partition
+            }).collect()
+        }
+    }
+
+    /// The offset of the next record to be read from a partition.
+    pub fn position(&self, partition: &TopicPartition) -> Option<i64> {
+        match self.buffered.get(partition).and_then(|buffer| buffer.front()) {
+            Some(record) => Some(record.offset),
+            None => self.positions.get(partition).map(|&offset| offset)
+        }
+    }
+
+    /// How many records each assigned partition has past the consumer's
+    /// position, as of the high watermark in its last fetch. Partitions not
+    /// fetched from yet are left out.
+    pub fn lag(&self) -> HashMap<TopicPartition, i64> {
+        let mut lag = HashMap::new();
+        for partition in self.assignment().into_iter() {
+            match (self.highwater_marks.get(&partition), self.position(&partition)) {
+                (Some(&highwater_mark), Some(position)) => {
+                    lag.insert(partition, cmp::max(highwater_mark - position, 0));
+                },
+                _ => ()
+            }
+        }
+        lag
+    }
+
+    /// Heap bytes held by the latest fetch response decoded for each topic's
+    /// partitions, to see which topics a consumer's memory goes to and tune
+    /// their fetch sizes.
+    pub fn memory_by_topic(&self) -> HashMap<String, uint> {
+        let mut memory = HashMap::new();
+        for (partition, &bytes) in self.decoded_bytes.iter() {
+            let total = memory.get(&partition.topic).map_or(0, |&total| total) + bytes;
+            memory.insert(partition.topic.clone(), total);
+        }
+        memory
+    }
+
+    /// Finds, for each partition, the first record written at or after the
+    /// given time in ms, as `(timestamp, offset)`, or `None` where every record
+    /// is older. Pass the offsets to `assign` to start reading from that time.
+    pub fn offsets_for_times(&self, times: &[(TopicPartition, i64)])
+                             -> KafkaResult<HashMap<TopicPartition, Option<(i64, i64)>>> {
+        let mut offsets = HashMap::new();
+        for &(ref partition, timestamp) in times.iter() {
+            let found = try!(self.client.offset_for_time(partition, timestamp));
+            offsets.insert(partition.clone(), found);
+        }
+        Ok(offsets)
+    }
+
+    /// Moves an assigned partition's position, so the next fetch reads from
+    /// `offset`, which may also be `EARLIEST_OFFSET` or `LATEST_OFFSET`. Records
+    /// buffered from the old position are dropped, so none are returned
+    /// afterwards.
+    pub fn seek(&mut self, partition: &TopicPartition, offset: i64) -> KafkaResult<()> {
+        if !self.positions.contains_key(partition) {
+            fail!((UnassignedPartition, "Can't seek a partition that isn't assigned"));
+        }
+        let offset = if offset < 0 { try!(self.client.fetch_offset(partition, offset)) } else { offset };
+        self.positions.insert(partition.clone(), offset);
+        self.buffered.remove(partition);
+        Ok(())
+    }
+
+    /// Seeks each of `partitions` to the oldest record the broker still has,
+    /// or every assigned partition when `partitions` is empty.
+    pub fn seek_to_beginning(&mut self, partitions: &[TopicPartition]) -> KafkaResult<()> {
+        self.seek_all(partitions, EARLIEST_OFFSET)
+    }
+
+    /// Seeks each of `partitions` past the last record written, or every
+    /// assigned partition when `partitions` is empty.
+    pub fn seek_to_end(&mut self, partitions: &[TopicPartition]) -> KafkaResult<()> {
+        self.seek_all(partitions, LATEST_OFFSET)
+    }
+
+    fn seek_all(&mut self, partitions: &[TopicPartition], time: i64) -> KafkaResult<()> {
+        let partitions = if partitions.is_empty() { self.assignment() } else { partitions.to_vec() };
+        for partition in partitions.iter() {
+            try!(self.seek(partition, time));
+        }
+        Ok(())
+    }
+
+    /// Reports gaps in the offsets read from topics which aren't compacted,
+    /// where there should be none. The protocol versions spoken here predate
+    /// transactions, so no gaps are left by transaction markers either.
+    pub fn set_gap_listener(&mut self, listener: Box<GapListener + Send>) {
+        self.gap_listener = Some(listener);
+    }
+
+    /// Tells `listener` how each automatic commit went, as failures don't
+    /// make fetching or polling fail.
+    pub fn set_auto_commit_listener(&mut self, listener: Box<AutoCommitListener + Send>) {
+        self.auto_commit_listener = Some(listener);
+    }
+
+    /// Fetches once from each assigned partition that isn't over its rate
+    /// limit, returning the records read, heaviest partitions first.
+    pub fn fetch(&mut self) -> KafkaResult<Vec<ConsumerRecord>> {
+        try!(self.maybe_rejoin());
+        try!(self.maybe_refresh_subscription());
+        self.maybe_auto_commit();
+        let records = try!(self.fetch_round(Duration::zero()));
+        Ok(self.deliver(records))
+    }
+
+    /// Waits up to `timeout` for records, returning as soon as any have been
+    /// read. If none arrive in time an empty batch is returned.
+    ///
+    /// Each round of fetches gives every partition an equal share of the time
+    /// left as its `max_wait_time`, so the broker holds the request open until
+    /// a message arrives rather than the consumer asking again and again. The
+    /// share is kept a second short of the connection's `read_timeout`, so a
+    /// long poll isn't mistaken for a broker that stopped responding.
+    pub fn poll(&mut self, timeout: Duration) -> KafkaResult<Vec<ConsumerRecord>> {
+        self.poll_until(timeout, None)
+    }
+
+    /// Like `poll`, but fails with a `CancelledError` soon after `token` is
+    /// cancelled. The broker is asked to wait at most 100ms at a time, so
+    /// the token is checked that often while no records arrive.
+    pub fn poll_cancellable(&mut self, timeout: Duration,
+                            token: &CancellationToken) -> KafkaResult<Vec<ConsumerRecord>> {
+        self.poll_until(timeout, Some(token))
+    }
+
+    fn poll_until(&mut self, timeout: Duration,
+                  token: Option<&CancellationToken>) -> KafkaResult<Vec<ConsumerRecord>> {
+        try!(self.maybe_rejoin());
+        try!(self.maybe_refresh_subscription());
+        self.maybe_auto_commit();
+        let deadline = time::precise_time_ns() + timeout.num_milliseconds() as u64 * 1000000;
+        let max_share = max_fetch_wait_ms(self.client.config().connection.read_timeout);
+        loop {
+            match token {
+                Some(token) => try!(token.check()),
+                None => ()
+            }
+            let started = time::precise_time_ns();
+            if started >= deadline {
+                return Ok(vec![]);
+            }
+            let remaining_ms = (deadline - started) / 1000000;
+            let share = remaining_ms / cmp::max(self.positions.len(), 1) as u64;
+            let share = max_share.map_or(share, |max_share| cmp::min(share, max_share));
+            let share = if token.is_some() { cmp::min(share, CANCEL_CHECK_MS) } else { share };
+            // Buffered records are ready to return, so don't wait for more
+            let share = if self.buffered.is_empty() { share } else { 0 };
+
+            let records = try!(self.fetch_round(Duration::milliseconds(share as i64)));
+            let records = self.deliver(records);
+            if !records.is_empty() {
+                return Ok(records);
+            }
+            // Nothing was waited on, as when every partition is over its rate limit
+            if time::precise_time_ns() - started < 1000000 {
+                timer::sleep(Duration::milliseconds(cmp::min(remaining_ms, 1) as i64));
+            }
+        }
+    }
+
+    fn fetch_round(&mut self, max_wait: Duration) -> KafkaResult<Vec<ConsumerRecord>> {
+        let min_bytes = if max_wait == Duration::zero() { 0 } else { 1 };
+        let mut records = vec![];
+        for partition in schedule(self.assignment(), &self.weights).into_iter() {
+            let now = time::precise_time_ns();
+            if self.buffered.contains_key(&partition) || !self.throttles.get_mut(&partition).unwrap().ready(now) {
+                continue;
+            }
+
+            let position = *self.positions.get(&partition).unwrap();
+            let weight = self.weights.get(&partition).map_or(1, |&weight| weight);
+            let base_max_bytes = cmp::min(self.config.fetch_max_bytes as i64 * weight as i64, i32::MAX as i64) as i32;
+            let max_bytes = self.grown_max_bytes.get(&partition).map_or(base_max_bytes, |&grown| grown);
+            // Once there are records to return, don't hold them back waiting for more
+            let (max_wait, min_bytes) = if records.is_empty() { (max_wait, min_bytes) } else { (Duration::zero(), 0) };
+            let fetched = match self.client.fetch_partition_waiting(&partition, position, max_bytes,
+                                                                    max_wait, min_bytes) {
+                Ok(fetched) => fetched,
+                Err(KafkaError { kind: BrokerError(OffsetOutOfRange), .. })
+                        if self.config.auto_offset_reset != ResetNone => {
+                    let time = self.config.auto_offset_reset.offset_time().unwrap();
+                    let offset = try!(self.client.fetch_offset(&partition, time));
+                    self.positions.insert(partition, offset);
+                    continue;
+                }
+                Err(e) => return Err(e)
+            };
+            let highwater_mark = fetched.highwater_mark_offset;
+            self.highwater_marks.insert(partition.clone(), highwater_mark);
+            self.decoded_bytes.insert(partition.clone(), fetched.heap_size());
+            let WithSize(message_set) = fetched.messages;
+
+            let start = records.len();
+            let next = try!(append_records(&mut records, &partition, &message_set, position));
+            let stalled = next == position && highwater_mark > position;
+            match next_max_bytes(max_bytes, base_max_bytes, self.config.fetch_max_bytes_ceiling, stalled) {
+                size if size == base_max_bytes => { self.grown_max_bytes.remove(&partition); },
+                size => { self.grown_max_bytes.insert(partition.clone(), size); }
+            }
+            self.check_gaps(&partition, position, records.slice_from(start));
+            self.throttles.get_mut(&partition).unwrap().take(records.slice_from(start), now);
+            self.positions.insert(partition, next);
+        }
+
+        match self.cache {
+            Some(ref mut cache) => for record in records.iter() { cache.insert(record.clone()) },
+            None => ()
+        }
+        Ok(records)
+    }
+
+    // Commits the positions reached so far if auto-commit is due. Done before
+    // fetching, so a failed commit doesn't lose records already fetched.
+    // Partitions that failed aren't marked committed, so they're tried again
+    // next time.
+    fn maybe_auto_commit(&mut self) {
+        let interval = self.config.auto_commit_interval.num_milliseconds() as u64 * 1000000;
+        if !self.config.enable_auto_commit || time::precise_time_ns() - self.last_auto_commit < interval {
+            return;
+        }
+        self.auto_commit();
+    }
+
+    // Commits positions that moved since they were last committed, telling
+    // the auto-commit listener how it went
+    fn auto_commit(&mut self) {
+        if self.config.group_id.is_none() {
+            return;
+        }
+        self.last_auto_commit = time::precise_time_ns();
+
+        let offsets: HashMap<TopicPartition, OffsetAndMetadata> = self.positions_to_commit().into_iter()
+            .filter(|&(ref partition, ref position)| self.committed.get(partition) != Some(&position.offset))
+            .collect();
+        let results = self.commit_sync_offsets(offsets);
+        match self.auto_commit_listener {
+            Some(ref mut listener) => listener.on_auto_commit(&results),
+            None => ()
+        }
+    }
+
+    // The position of every assigned partition, without metadata
+    fn positions_to_commit(&self) -> HashMap<TopicPartition, OffsetAndMetadata> {
+        self.assignment().into_iter().map(|partition| {
+            let position = self.position(&partition).unwrap();
+            (partition, OffsetAndMetadata::new(position))
+        }).collect()
+    }
+
+    fn group(&self) -> KafkaResult<String> {
+        match self.config.group_id {
+            Some(ref group) => Ok(group.clone()),
+            None => fail!((NoGroupId, "Offsets can't be committed without a group_id"))
+        }
+    }
+
+    /// Commits the position of every assigned partition for the consumer's
+    /// `group_id`, waiting for the coordinator's answer. Returns whether
+    /// each partition's offset was stored.
+    pub fn commit_sync(&mut self) -> KafkaResult<CommitResults> {
+        let offsets = self.positions_to_commit();
+        self.commit_sync_offsets(offsets)
+    }
+
+    /// Like `commit_sync`, committing the given offsets: those of the next
+    /// records to read, as positions are. Each may carry metadata of the
+    /// application's, such as a checkpoint, which `committed` returns when
+    /// the application resumes.
+    pub fn commit_sync_offsets(&mut self,
+                               offsets: HashMap<TopicPartition, OffsetAndMetadata>) -> KafkaResult<CommitResults> {
+        let results = match self.membership {
+            Some(ref membership) => try!(self.client.commit_member_offsets(&membership.member, &offsets)),
+            None => try!(self.client.commit_offsets(try!(self.group()).as_slice(), &offsets))
+        };
+        for &(ref partition, ref result) in results.iter() {
+            match offsets.get(partition) {
+                Some(offset) if result.is_ok() => { self.committed.insert(partition.clone(), offset.offset); },
+                _ => ()
+            }
+        }
+        Ok(results)
+    }
+
+    /// The offsets last committed for the consumer's `group_id`, with their
+    /// metadata, or `None` for partitions with nothing committed. Pass the
+    /// offsets to `assign` to resume where the group left off.
+    pub fn committed(&self, partitions: &[TopicPartition])
+                     -> KafkaResult<HashMap<TopicPartition, Option<OffsetAndMetadata>>> {
+        let group = try!(self.group());
+        self.client.committed_offsets(group.as_slice(), partitions)
+    }
+
+    /// Commits the position of every assigned partition without waiting,
+    /// passing the outcome to `callback` on a task of the client's `Spawner`.
+    pub fn commit_async(&mut self, callback: proc(KafkaResult<CommitResults>): Send) {
+        let offsets = self.positions_to_commit();
+        self.commit_async_offsets(offsets, callback);
+    }
+
+    /// Like `commit_async`, committing the given offsets.
+    pub fn commit_async_offsets(&mut self, offsets: HashMap<TopicPartition, OffsetAndMetadata>,
+                                callback: proc(KafkaResult<CommitResults>): Send) {
+        let group = match self.group() {
+            Ok(group) => group,
+            Err(err) => return callback(Err(err))
+        };
+        let member = self.membership.as_ref().map(|membership| membership.member.clone());
+        let client = self.client.clone();
+        self.client.spawn(proc() {
+            callback(match member {
+                Some(member) => client.commit_member_offsets(&member, &offsets),
+                None => client.commit_offsets(group.as_slice(), &offsets)
+            })
+        });
+    }
+
+    // Limits freshly fetched records to `max_poll_records`, buffering the rest
+    fn deliver(&mut self, records: Vec<ConsumerRecord>) -> Vec<ConsumerRecord> {
+        let max = match self.config.max_poll_records {
+            Some(max) => max,
+            None => return records
+        };
+        for record in records.into_iter() {
+            let partition = TopicPartition::new(record.topic.as_slice(), record.partition);
+            if !self.buffered.contains_key(&partition) {
+                self.buffered.insert(partition.clone(), RingBuf::new());
+            }
+            self.buffered.get_mut(&partition).unwrap().push_back(record);
+        }
+
+        let order = schedule(self.assignment(), &self.weights);
+        let start = self.next_partition;
+        self.next_partition += 1;
+        interleave(&mut self.buffered, order.as_slice(), start, max)
+    }
+
+    /// Reads the record at an offset, from the record cache if it's there,
+    /// without changing the partition's position. Returns `None` if there's
+    /// no record at that offset, as after compaction.
+    pub fn read_at(&mut self, partition: &TopicPartition, offset: i64) -> KafkaResult<Option<ConsumerRecord>> {
+        match self.cache.as_mut().and_then(|cache| cache.get(partition, offset)) {
+            Some(record) => return Ok(Some(record)),
+            None => ()
+        }
+
+        let fetched = try!(self.client.fetch_partition(partition, offset, self.config.fetch_max_bytes));
+        let WithSize(message_set) = fetched.messages;
+        let mut records = vec![];
+        try!(append_records(&mut records, partition, &message_set, offset));
+
+        let mut found = None;
+        for record in records.into_iter() {
+            if record.offset == offset {
+                found = Some(record.clone());
+            }
+            match self.cache {
+                Some(ref mut cache) => cache.insert(record),
+                None => ()
+            }
+        }
+        Ok(found)
+    }
+
+    /// Hits and misses of the record cache, if enabled.
+    pub fn cache_stats(&self) -> Option<CacheStats> {
+        self.cache.as_ref().map(|cache| cache.stats().clone())
+    }
+
+    fn check_gaps(&mut self, partition: &TopicPartition, position: i64, records: &[ConsumerRecord]) {
+        if self.gap_listener.is_none() {
+            return;
+        }
+        // Compaction leaves gaps as a matter of course. Brokers too old to
+        // describe configs are taken not to be compacting.
+        match self.client.topic_config(partition.topic.as_slice()) {
+            Ok(ref config) if config.is_compacted() => return,
+            _ => ()
+        }
+
+        let offsets: Vec<i64> = records.iter().map(|record| record.offset).collect();
+        let listener = self.gap_listener.as_mut().unwrap();
+        for (from, until) in find_gaps(position, offsets.as_slice()).into_iter() {
+            listener.on_gap(partition, from, until);
+        }
+    }
+}
+
+// The fetch size after a fetch of `current` bytes: doubled up to the ceiling
+// while the partition is stalled, otherwise back to `base`
+fn next_max_bytes(current: i32, base: i32, ceiling: Option<i32>, stalled: bool) -> i32 {
+    match ceiling {
+        Some(ceiling) if stalled => cmp::max(cmp::min(current as i64 * 2, ceiling as i64) as i32, current),
+        _ => base
+    }
+}
+
+// The longest max_wait_time a fetch can ask for without its response
+// outlasting the read timeout
+fn max_fetch_wait_ms(read_timeout: Option<Duration>) -> Option<u64> {
+    read_timeout.map(|timeout| cmp::max(timeout.num_milliseconds(), 0) as u64)
+                .map(|timeout| if timeout > READ_TIMEOUT_MARGIN_MS { timeout - READ_TIMEOUT_MARGIN_MS } else { 0 })
+}
+
+// Every partition of the topics matching `pattern`, in order
+fn matching_partitions(cluster: &Cluster, pattern: &Regex, internal: &InternalTopics) -> Vec<TopicPartition> {
+    let mut partitions = vec![];
+    for topic in cluster.topics().into_iter() {
+        if !pattern.is_match(topic.as_slice()) || !internal.allows(topic.as_slice()) {
+            continue;
+        }
+        for metadata in cluster.partitions_for(topic.as_slice()).unwrap().iter() {
+            partitions.push(TopicPartition::new(topic.as_slice(), metadata.partition));
+        }
+    }
+    partitions
+}
+
+// Takes up to `max` records, one from each partition's buffer in turn,
+// starting `start` partitions into `order`. Emptied buffers are removed.
+fn interleave(buffered: &mut HashMap<TopicPartition, RingBuf<ConsumerRecord>>, order: &[TopicPartition],
+              start: uint, max: uint) -> Vec<ConsumerRecord> {
+    let mut records = vec![];
+    if order.is_empty() {
+        return records;
+    }
+    let mut exhausted = false;
+    while records.len() < max && !exhausted {
+        exhausted = true;
+        for i in range(0, order.len()) {
+            if records.len() == max {
+                break;
+            }
+            let partition = &order[(start + i) % order.len()];
+            match buffered.get_mut(partition).and_then(|buffer| buffer.pop_front()) {
+                Some(record) => {
+                    records.push(record);
+                    exhausted = false;
+                },
+                None => ()
+            }
+        }
+    }
+
+    let emptied: Vec<TopicPartition> = buffered.iter()
+        .filter(|&(_, buffer)| buffer.is_empty())
+        .map(|(partition, _)| partition.clone())
+        .collect();
+    for partition in emptied.iter() {
+        buffered.remove(partition);
+    }
+    records
+}
+
+// Orders partitions heaviest first, keeping the given order among equals
+fn schedule(mut partitions: Vec<TopicPartition>, weights: &HashMap<TopicPartition, uint>) -> Vec<TopicPartition> {
+    let weight = |partition: &TopicPartition| weights.get(partition).map_or(1, |&weight| weight);
+    partitions.sort_by(|a, b| weight(b).cmp(&weight(a)));
+    partitions
+}
+
+// Refills at `rate` per second, holding at most a second's worth. Taking more
+// than is there leaves the bucket in debt until it has refilled.
+struct TokenBucket {
+    rate: f64,
+    tokens: f64,
+    updated_at: u64
+}
+
+impl TokenBucket {
+    fn new(rate: u64, now: u64) -> TokenBucket {
+        TokenBucket { rate: rate as f64, tokens: rate as f64, updated_at: now }
+    }
+
+    fn refill(&mut self, now: u64) {
+        let elapsed = (now - self.updated_at) as f64 / 1e9;
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.rate);
+        self.updated_at = now;
+    }
+
+    fn ready(&mut self, now: u64) -> bool {
+        self.refill(now);
+        self.tokens > 0.0
+    }
+
+    fn take(&mut self, amount: uint, now: u64) {
+        self.refill(now);
+        self.tokens -= amount as f64;
+    }
+}
+
+// A partition's record and byte rate limits, where configured
+struct Throttle {
+    records: Option<TokenBucket>,
+    bytes: Option<TokenBucket>
+}
+
+impl Throttle {
+    fn new(config: &ConsumerConfig, now: u64) -> Throttle {
+        Throttle {
+            records: config.max_records_per_sec.map(|rate| TokenBucket::new(rate, now)),
+            bytes: config.max_bytes_per_sec.map(|rate| TokenBucket::new(rate, now))
+        }
+    }
+
+    fn ready(&mut self, now: u64) -> bool {
+        self.records.as_mut().map_or(true, |bucket| bucket.ready(now)) &&
+            self.bytes.as_mut().map_or(true, |bucket| bucket.ready(now))
+    }
+
+    fn take(&mut self, records: &[ConsumerRecord], now: u64) {
+        let bytes = records.iter().fold(0, |total, record| {
+            total + record.key.as_ref().map_or(0, |key| key.len()) + record.value.as_ref().map_or(0, |value| value.len())
+        });
+        self.records.as_mut().map(|bucket| bucket.take(records.len(), now));
+        self.bytes.as_mut().map(|bucket| bucket.take(bytes, now));
+    }
+}
+
+/// Appends the messages at or after `from` as records, decompressing those
+/// that are compressed, returning the offset to fetch next.
+fn append_records(records: &mut Vec<ConsumerRecord>, partition: &TopicPartition, message_set: &MessageSet,
+                  from: i64) -> KafkaResult<i64> {
+    let mut next = from;
+    for (_, offset, key, value) in try!(fetched_messages(partition, message_set)) {
+        // Brokers may return messages from before the requested offset, as
+        // from the start of a compressed set
+        if offset < from {
+            continue;
+        }
+        records.push(ConsumerRecord {
+            topic: partition.topic.clone(),
+            partition: partition.partition,
+            offset: offset,
+            key: key,
+            value: value
+        });
+        next = offset + 1;
+    }
+    Ok(next)
+}
+
+/// The ranges of offsets skipped by `offsets`, which should carry on from
+/// `expected` one after another.
+fn find_gaps(expected: i64, offsets: &[i64]) -> Vec<(i64, i64)> {
+    let mut gaps = vec![];
+    let mut expected = expected;
+    for &offset in offsets.iter() {
+        if offset > expected {
+            gaps.push((expected, offset));
+        }
+        expected = offset + 1;
+    }
+    gaps
+}
+
+/// Bytes fetched per request while reading a topic. Doubled whenever a
+/// single message doesn't fit, up to `MAX_FETCH_BYTES`.
+const FETCH_BYTES: i32 = 1024 * 1024;
+const MAX_FETCH_BYTES: i32 = 64 * 1024 * 1024;
+
+/// Reads a compacted topic from its oldest retained offset up to the high
+/// watermark at the time of the call, returning the latest value for each key.
+/// Tombstones (messages with no value) remove their key, and messages without
+/// a key are ignored, as compaction would discard them.
+pub fn snapshot_topic(client: &KafkaClient, topic: &str) -> KafkaResult<HashMap<Vec<u8>, Vec<u8>>> {
+    let metadata = try!(client.load_metadata(vec![String::from_str(topic)]));
+    let partitions: Vec<TopicPartition> = match metadata.topics.iter().find(|t| t.name.as_slice() == topic) {
+        Some(topic_metadata) => {
+            try!(check_error_code(topic_metadata.error_code));
+            topic_metadata.partitions.iter().map(|p| TopicPartition::new(topic, p.partition)).collect()
+        },
+        None => fail!(UnknownTopicOrPartition)
+    };
+
+    let mut snapshot = HashMap::new();
+    for partition in partitions.iter() {
+        let mut offset = try!(client.fetch_offset(partition, EARLIEST_OFFSET));
+        let until = try!(client.fetch_offset(partition, LATEST_OFFSET));
+        let mut max_bytes = FETCH_BYTES;
+
+        while offset < until {
+            let fetched = try!(client.fetch_partition(partition, offset, max_bytes));
+            let WithSize(message_set) = fetched.messages;
+            let next = try!(apply_messages(&mut snapshot, partition, &message_set, offset, until));
+            if next == offset {
+                // Nothing complete came back, so the next message is larger than a fetch
+                if max_bytes >= MAX_FETCH_BYTES {
+                    fail!((FetchSizeLimit, "A message is too large to fetch"));
+                }
+                max_bytes *= 2;
+            }
+            offset = next;
+        }
+    }
+    Ok(snapshot)
+}
+
+/// Applies the messages in `[from, until)` to a snapshot, decompressing those
+/// that are compressed, returning the offset to fetch next.
+fn apply_messages(snapshot: &mut HashMap<Vec<u8>, Vec<u8>>, partition: &TopicPartition, message_set: &MessageSet,
+                  from: i64, until: i64) -> KafkaResult<i64> {
+    let mut next = from;
+    for (_, offset, key, value) in try!(fetched_messages(partition, message_set)) {
+        // Brokers may return messages from before the requested offset
+        if offset < from {
+            continue;
+        }
+        if offset >= until {
+            return Ok(until);
+        }
+        match (key, value) {
+            (Some(key), Some(value)) => { snapshot.insert(key, value); },
+            (Some(key), None) => { snapshot.remove(&key); },
+            (None, _) => ()
+        }
+        next = offset + 1;
+    }
+    Ok(next)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashMap, RingBuf};
+    use std::time::Duration;
+    use protocol::*;
+    use client::{EARLIEST_OFFSET, LATEST_OFFSET, ExcludeInternalTopics, IncludeInternalTopics};
+    use cluster::Cluster;
+    use regex::Regex;
+    use types::*;
+    use super::{apply_messages, append_records, find_gaps, interleave, matching_partitions, max_fetch_wait_ms,
+                next_max_bytes, schedule, TokenBucket};
+    use super::{ResetNone, ResetEarliest, ResetLatest, AssignmentSnapshot, AssignedPartition};
+    use super::ConsumerRecord;
+
+    fn element(offset: i64, key: Option<&str>, value: Option<&str>) -> MessageSetElement {
+        MessageSetElement {
+            offset: offset,
+            message: WithSize(Message {
+                crc: 0,
+                magic_byte: 0,
+                attributes: 0,
+                key: key.map(|key| key.as_bytes().to_vec()),
+                value: value.map(|value| value.as_bytes().to_vec())
+            })
+        }
+    }
+
+    #[test]
+    fn test_apply_messages() {
+        let mut snapshot = HashMap::new();
+        let message_set = MessageSet { messages: vec![
+            element(3, Some("a"), Some("stale")),
+            element(5, Some("a"), Some("1")),
+            element(6, Some("b"), Some("2")),
+            element(7, None, Some("unkeyed")),
+            element(8, Some("a"), None),
+            element(9, Some("c"), Some("3")),
+            element(10, Some("d"), Some("written after the snapshot started"))
+        ]};
+
+        assert_eq!(apply_messages(&mut snapshot, &TopicPartition::new("t", 0), &message_set, 5, 10).ok().unwrap(), 10);
+        assert_eq!(snapshot.len(), 2);
+        assert_eq!(snapshot.get(&b"b".to_vec()), Some(&b"2".to_vec()));
+        assert_eq!(snapshot.get(&b"c".to_vec()), Some(&b"3".to_vec()));
+    }
+
+    #[test]
+    fn test_apply_nothing() {
+        let mut snapshot = HashMap::new();
+        let partition = TopicPartition::new("t", 0);
+        assert_eq!(apply_messages(&mut snapshot, &partition, &MessageSet { messages: vec![] }, 4, 10).ok().unwrap(), 4);
+        assert!(snapshot.is_empty());
+    }
+
+    #[test]
+    fn test_append_records() {
+        let partition = TopicPartition::new("events", 1);
+        let message_set = MessageSet { messages: vec![
+            element(3, Some("a"), Some("before")),
+            element(4, Some("b"), Some("1")),
+            element(6, None, None)
+        ]};
+
+        let mut records = vec![];
+        assert_eq!(append_records(&mut records, &partition, &message_set, 4).ok().unwrap(), 7);
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].offset, 4);
+        assert_eq!(records[0].key, Some(b"b".to_vec()));
+        assert_eq!(records[1].partition, 1);
+        assert!(records[1].value.is_none());
+
+        // Compressed messages are decompressed rather than read as records
+        let mut compressed = element(7, None, Some("not gzip"));
+        match compressed.message {
+            WithSize(ref mut message) => message.attributes = GZIP_COMPRESSION
+        }
+        let message_set = MessageSet { messages: vec![compressed] };
+        assert!(append_records(&mut records, &partition, &message_set, 7).is_err());
+        assert!(apply_messages(&mut HashMap::new(), &partition, &message_set, 7, 10).is_err());
+    }
+
+    #[test]
+    fn test_schedule() {
+        let partitions = vec![TopicPartition::new("bulk", 0), TopicPartition::new("bulk", 1),
+                              TopicPartition::new("alerts", 0), TopicPartition::new("orders", 0)];
+        let mut weights = HashMap::new();
+        weights.insert(TopicPartition::new("alerts", 0), 10u);
+        weights.insert(TopicPartition::new("orders", 0), 3u);
+
+        assert_eq!(schedule(partitions, &weights), vec![
+            TopicPartition::new("alerts", 0), TopicPartition::new("orders", 0),
+            TopicPartition::new("bulk", 0), TopicPartition::new("bulk", 1)]);
+    }
+
+    #[test]
+    fn test_token_bucket() {
+        let mut bucket = TokenBucket::new(10, 0);
+        assert!(bucket.ready(0));
+        bucket.take(25, 0);
+        assert!(!bucket.ready(1000000000));
+        assert!(bucket.ready(1600000000));
+
+        // Idle time only refills up to a second's worth
+        bucket.take(1, 1600000000);
+        assert!(bucket.ready(100000000000));
+        bucket.take(10, 100000000000);
+        assert!(!bucket.ready(100000000000));
+    }
+
+    #[test]
+    fn test_assignment_json() {
+        let snapshot = AssignmentSnapshot {
+            partitions: vec![AssignedPartition { topic: String::from_str("events"), partition: 0, position: 42 }]
+        };
+        assert_eq!(snapshot.to_json().as_slice(), r#"{"partitions":[{"topic":"events","partition":0,"position":42}]}"#);
+    }
+
+    fn record(partition: i32, offset: i64) -> ConsumerRecord {
+        ConsumerRecord { topic: String::from_str("events"), partition: partition, offset: offset, key: None, value: None }
+    }
+
+    fn offsets(records: Vec<ConsumerRecord>) -> Vec<(i32, i64)> {
+        records.iter().map(|record| (record.partition, record.offset)).collect()
+    }
+
+    #[test]
+    fn test_matching_partitions() {
+        let topic = |name: &str, count: i32| TopicMetadata {
+            error_code: 0,
+            name: String::from_str(name),
+            partitions: range(0, count).map(|partition| PartitionMetadata {
+                error_code: 0, partition: partition, leader: 1, replicas: vec![1], isr: vec![1]
+            }).collect()
+        };
+        let cluster = Cluster::new(&MetadataResponse {
+            brokers: vec![],
+            topics: vec![topic("events.eu", 2), topic("events.us", 1), topic("users", 1), topic("__consumer_offsets", 1)]
+        });
+
+        let pattern = Regex::new(r"^events\.").unwrap();
+        assert_eq!(matching_partitions(&cluster, &pattern, &ExcludeInternalTopics),
+                   vec![TopicPartition::new("events.eu", 0), TopicPartition::new("events.eu", 1),
+                        TopicPartition::new("events.us", 0)]);
+
+        let everything = Regex::new(".*").unwrap();
+        assert_eq!(matching_partitions(&cluster, &everything, &ExcludeInternalTopics).len(), 4);
+        assert_eq!(matching_partitions(&cluster, &everything, &IncludeInternalTopics).len(), 5);
+    }
+
+    #[test]
+    fn test_next_max_bytes() {
+        assert_eq!(next_max_bytes(1024, 1024, Some(3000), true), 2048);
+        assert_eq!(next_max_bytes(2048, 1024, Some(3000), true), 3000);
+        assert_eq!(next_max_bytes(3000, 1024, Some(3000), true), 3000);
+        assert_eq!(next_max_bytes(3000, 1024, Some(3000), false), 1024);
+        assert_eq!(next_max_bytes(1024, 1024, None, true), 1024);
+    }
+
+    #[test]
+    fn test_max_fetch_wait_ms() {
+        assert_eq!(max_fetch_wait_ms(Some(Duration::seconds(30))), Some(29000));
+        assert_eq!(max_fetch_wait_ms(Some(Duration::milliseconds(500))), Some(0));
+        assert_eq!(max_fetch_wait_ms(None), None);
+    }
+
+    #[test]
+    fn test_interleave() {
+        let (p0, p1) = (TopicPartition::new("events", 0), TopicPartition::new("events", 1));
+        let mut buffered = HashMap::new();
+        buffered.insert(p0.clone(), range(0, 5).map(|offset| record(0, offset)).collect::<RingBuf<ConsumerRecord>>());
+        buffered.insert(p1.clone(), range(0, 2).map(|offset| record(1, offset)).collect::<RingBuf<ConsumerRecord>>());
+        let order = [p0.clone(), p1.clone()];
+
+        assert_eq!(offsets(interleave(&mut buffered, &order, 1, 3)), vec![(1, 0), (0, 0), (1, 1)]);
+        assert!(!buffered.contains_key(&p1));
+        assert_eq!(offsets(interleave(&mut buffered, &order, 0, 10)), vec![(0, 1), (0, 2), (0, 3), (0, 4)]);
+        assert!(buffered.is_empty());
+    }
+
+    #[test]
+    fn test_offset_reset() {
+        assert_eq!(ResetNone.offset_time(), None);
+        assert_eq!(ResetEarliest.offset_time(), Some(EARLIEST_OFFSET));
+        assert_eq!(ResetLatest.offset_time(), Some(LATEST_OFFSET));
+    }
+
+    #[test]
+    fn test_find_gaps() {
+        assert_eq!(find_gaps(4, &[4, 5, 6]), vec![]);
+        assert_eq!(find_gaps(4, &[6, 7, 10]), vec![(4, 6), (8, 10)]);
+        assert_eq!(find_gaps(4, &[]), vec![]);
+    }
+}
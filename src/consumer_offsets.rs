@@ -0,0 +1,237 @@
+//! Decoders for the records brokers write to the `__consumer_offsets` topic,
+//! so that committed offsets and group membership can be audited from it.
+
+use std::io;
+
+use protocol::KafkaSerializable;
+use types::*;
+
+#[deriving(Show, PartialEq, Eq, Clone)]
+pub struct OffsetCommitKey {
+    pub group: String,
+    pub topic: String,
+    pub partition: i32
+}
+
+#[deriving(Show, PartialEq, Eq, Clone)]
+pub struct OffsetCommitValue {
+    pub offset: i64,
+    /// Only present from value version 3.
+    pub leader_epoch: Option<i32>,
+    pub metadata: String,
+    pub commit_timestamp: i64,
+    /// Only present in value version 1.
+    pub expire_timestamp: Option<i64>
+}
+
+#[deriving(Show, PartialEq, Eq, Clone)]
+pub struct MemberMetadata {
+    pub member_id: String,
+    /// Only present from value version 3.
+    pub group_instance_id: Option<String>,
+    pub client_id: String,
+    pub client_host: String,
+    /// Only present from value version 1.
+    pub rebalance_timeout: Option<i32>,
+    pub session_timeout: i32,
+    pub subscription: Vec<u8>,
+    pub assignment: Vec<u8>
+}
+
+#[deriving(Show, PartialEq, Eq, Clone)]
+pub struct GroupMetadataValue {
+    pub protocol_type: String,
+    pub generation: i32,
+    pub protocol: Option<String>,
+    pub leader: Option<String>,
+    /// Only present from value version 2.
+    pub current_state_timestamp: Option<i64>,
+    pub members: Vec<MemberMetadata>
+}
+
+/// A decoded record. A `None` value is a tombstone: the offset expired or
+/// the group was removed.
+#[deriving(Show, PartialEq, Eq, Clone)]
+pub enum OffsetsTopicRecord {
+    OffsetCommit(OffsetCommitKey, Option<OffsetCommitValue>),
+    GroupMetadata(String, Option<GroupMetadataValue>)
+}
+
+fn read<T:KafkaSerializable>(reader: &mut io::Reader) -> KafkaResult<T> {
+    KafkaSerializable::decode(reader)
+}
+
+/// Decodes a record's key and value as found in `__consumer_offsets`.
+pub fn decode_record(key: &[u8], value: Option<&[u8]>) -> KafkaResult<OffsetsTopicRecord> {
+    let mut key_reader = io::BufReader::new(key);
+    let key_version: i16 = try!(read(&mut key_reader));
+    match key_version {
+        0 | 1 => {
+            let key = OffsetCommitKey {
+                group: try!(read(&mut key_reader)),
+                topic: try!(read(&mut key_reader)),
+                partition: try!(read(&mut key_reader))
+            };
+            let value = match value {
+                Some(value) => Some(try!(decode_offset_commit_value(value))),
+                None => None
+            };
+            Ok(OffsetCommit(key, value))
+        },
+        2 => {
+            let group = try!(read(&mut key_reader));
+            let value = match value {
+                Some(value) => Some(try!(decode_group_metadata_value(value))),
+                None => None
+            };
+            Ok(GroupMetadata(group, value))
+        },
+        _ => fail!((MalformedResponseError, "Unsupported offsets topic key version"))
+    }
+}
+
+fn decode_offset_commit_value(value: &[u8]) -> KafkaResult<OffsetCommitValue> {
+    let mut reader = io::BufReader::new(value);
+    let version: i16 = try!(read(&mut reader));
+    if version < 0 || version > 3 {
+        fail!((MalformedResponseError, "Unsupported offset commit value version"));
+    }
+
+    let offset = try!(read(&mut reader));
+    let leader_epoch = if version >= 3 { Some(try!(read(&mut reader))) } else { None };
+    let metadata = try!(read(&mut reader));
+    let commit_timestamp = try!(read(&mut reader));
+    let expire_timestamp = if version == 1 { Some(try!(read(&mut reader))) } else { None };
+
+    Ok(OffsetCommitValue {
+        offset: offset,
+        leader_epoch: leader_epoch,
+        metadata: metadata,
+        commit_timestamp: commit_timestamp,
+        expire_timestamp: expire_timestamp
+    })
+}
+
+fn decode_group_metadata_value(value: &[u8]) -> KafkaResult<GroupMetadataValue> {
+    let mut reader = io::BufReader::new(value);
+    let version: i16 = try!(read(&mut reader));
+    if version < 0 || version > 3 {
+        fail!((MalformedResponseError, "Unsupported group metadata value version"));
+    }
+
+    let protocol_type = try!(read(&mut reader));
+    let generation = try!(read(&mut reader));
+    let protocol = try!(read(&mut reader));
+    let leader = try!(read(&mut reader));
+    let current_state_timestamp = if version >= 2 { Some(try!(read(&mut reader))) } else { None };
+
+    let count: i32 = try!(read(&mut reader));
+    if count < 0 {
+        fail!((MalformedResponseError, "Negative array size"));
+    }
+    let mut members = Vec::new();
+    for _ in range(0, count) {
+        let member_id = try!(read(&mut reader));
+        let group_instance_id = if version >= 3 { try!(read(&mut reader)) } else { None };
+        let client_id = try!(read(&mut reader));
+        let client_host = try!(read(&mut reader));
+        let rebalance_timeout = if version >= 1 { Some(try!(read(&mut reader))) } else { None };
+        members.push(MemberMetadata {
+            member_id: member_id,
+            group_instance_id: group_instance_id,
+            client_id: client_id,
+            client_host: client_host,
+            rebalance_timeout: rebalance_timeout,
+            session_timeout: try!(read(&mut reader)),
+            subscription: try!(read(&mut reader)),
+            assignment: try!(read(&mut reader))
+        });
+    }
+
+    Ok(GroupMetadataValue {
+        protocol_type: protocol_type,
+        generation: generation,
+        protocol: protocol,
+        leader: leader,
+        current_state_timestamp: current_state_timestamp,
+        members: members
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::MemWriter;
+    use protocol::KafkaSerializable;
+    use super::*;
+
+    #[test]
+    fn test_offset_commit() {
+        let mut key = MemWriter::new();
+        (1i16).encode(&mut key).ok().unwrap();
+        String::from_str("group").encode(&mut key).ok().unwrap();
+        String::from_str("topic").encode(&mut key).ok().unwrap();
+        (3i32).encode(&mut key).ok().unwrap();
+
+        let mut value = MemWriter::new();
+        (1i16).encode(&mut value).ok().unwrap();
+        (42i64).encode(&mut value).ok().unwrap();
+        String::from_str("checkpoint").encode(&mut value).ok().unwrap();
+        (1000i64).encode(&mut value).ok().unwrap();
+        (2000i64).encode(&mut value).ok().unwrap();
+
+        let expected_key = OffsetCommitKey {
+            group: String::from_str("group"),
+            topic: String::from_str("topic"),
+            partition: 3
+        };
+        let expected_value = OffsetCommitValue {
+            offset: 42,
+            leader_epoch: None,
+            metadata: String::from_str("checkpoint"),
+            commit_timestamp: 1000,
+            expire_timestamp: Some(2000)
+        };
+
+        assert_eq!(decode_record(key.get_ref(), Some(value.get_ref())).ok().unwrap(),
+                   OffsetCommit(expected_key.clone(), Some(expected_value)));
+        assert_eq!(decode_record(key.get_ref(), None).ok().unwrap(), OffsetCommit(expected_key, None));
+    }
+
+    #[test]
+    fn test_group_metadata() {
+        let mut key = MemWriter::new();
+        (2i16).encode(&mut key).ok().unwrap();
+        String::from_str("group").encode(&mut key).ok().unwrap();
+
+        let mut value = MemWriter::new();
+        (1i16).encode(&mut value).ok().unwrap();
+        String::from_str("consumer").encode(&mut value).ok().unwrap();
+        (5i32).encode(&mut value).ok().unwrap();
+        Some(String::from_str("range")).encode(&mut value).ok().unwrap();
+        Some(String::from_str("member-1")).encode(&mut value).ok().unwrap();
+        (1i32).encode(&mut value).ok().unwrap();
+        String::from_str("member-1").encode(&mut value).ok().unwrap();
+        String::from_str("client").encode(&mut value).ok().unwrap();
+        String::from_str("/127.0.0.1").encode(&mut value).ok().unwrap();
+        (60000i32).encode(&mut value).ok().unwrap();
+        (10000i32).encode(&mut value).ok().unwrap();
+        vec![1u8].encode(&mut value).ok().unwrap();
+        vec![2u8].encode(&mut value).ok().unwrap();
+
+        match decode_record(key.get_ref(), Some(value.get_ref())).ok().unwrap() {
+            GroupMetadata(group, Some(metadata)) => {
+                assert_eq!(group.as_slice(), "group");
+                assert_eq!(metadata.generation, 5);
+                assert_eq!(metadata.members.len(), 1);
+                assert_eq!(metadata.members[0].rebalance_timeout, Some(60000));
+                assert_eq!(metadata.members[0].assignment, vec![2u8]);
+            },
+            other => panic!("Unexpected record {}", other)
+        }
+    }
+
+    #[test]
+    fn test_unknown_key_version() {
+        assert!(decode_record(&[0x00, 0x07], None).is_err());
+    }
+}
@@ -0,0 +1,104 @@
+use cluster::Cluster;
+use client::KafkaClient;
+use types::*;
+
+/// Whether topics are partitioned alike, as joining them partition by
+/// partition requires.
+#[deriving(Show, PartialEq, Eq, Clone)]
+pub struct CopartitionReport {
+    /// Each topic's partition count, `None` for topics the cluster doesn't have.
+    pub partition_counts: Vec<(String, Option<uint>)>,
+    /// Partition numbers whose leaders differ between the topics, when
+    /// leaders were checked. Leaders move, so this is advisory.
+    pub mismatched_leaders: Vec<i32>
+}
+
+impl CopartitionReport {
+    /// Whether every topic exists with the same partition count.
+    pub fn is_copartitioned(&self) -> bool {
+        match self.partition_counts.as_slice().head() {
+            Some(&(_, Some(count))) => self.partition_counts.iter().all(|&(_, other)| other == Some(count)),
+            _ => false
+        }
+    }
+
+    /// Whether leaders matched too, so each joined pair of partitions is
+    /// read from the same broker.
+    pub fn is_colocated(&self) -> bool {
+        self.is_copartitioned() && self.mismatched_leaders.is_empty()
+    }
+}
+
+impl KafkaClient {
+    /// Checks that `topics` have equal partition counts and, if
+    /// `check_leaders`, that each partition number has the same leader in
+    /// every topic. Stream joins silently give wrong results for topics
+    /// which aren't copartitioned.
+    pub fn verify_copartitioned(&self, topics: &[&str], check_leaders: bool) -> KafkaResult<CopartitionReport> {
+        let names = topics.iter().map(|topic| String::from_str(*topic)).collect();
+        try!(self.load_metadata(names));
+        match self.cluster() {
+            Some(cluster) => Ok(copartition_report(&cluster, topics, check_leaders)),
+            None => fail!((NoMetadata, "No metadata loaded"))
+        }
+    }
+}
+
+fn copartition_report(cluster: &Cluster, topics: &[&str], check_leaders: bool) -> CopartitionReport {
+    let partition_counts = topics.iter()
+        .map(|&topic| (String::from_str(topic), cluster.partitions_for(topic).map(|partitions| partitions.len())))
+        .collect();
+
+    let mut mismatched_leaders = vec![];
+    if check_leaders {
+        let first = topics.head().and_then(|&topic| cluster.partitions_for(topic)).unwrap_or(&[]);
+        for metadata in first.iter() {
+            let matches = topics.iter().all(|&topic| {
+                cluster.partition(&TopicPartition::new(topic, metadata.partition))
+                    .map_or(false, |other| other.leader == metadata.leader)
+            });
+            if !matches {
+                mismatched_leaders.push(metadata.partition);
+            }
+        }
+    }
+
+    CopartitionReport { partition_counts: partition_counts, mismatched_leaders: mismatched_leaders }
+}
+
+#[cfg(test)]
+mod tests {
+    use cluster::Cluster;
+    use protocol::*;
+    use super::copartition_report;
+
+    fn topic(name: &str, leaders: &[i32]) -> TopicMetadata {
+        TopicMetadata {
+            error_code: 0,
+            name: String::from_str(name),
+            partitions: leaders.iter().enumerate().map(|(partition, &leader)| PartitionMetadata {
+                error_code: 0, partition: partition as i32, leader: leader, replicas: vec![leader], isr: vec![leader]
+            }).collect()
+        }
+    }
+
+    #[test]
+    fn test_copartition_report() {
+        let cluster = Cluster::new(&MetadataResponse {
+            brokers: vec![],
+            topics: vec![topic("orders", &[1, 2, 3]), topic("payments", &[1, 3, 3]), topic("users", &[1, 2])]
+        });
+
+        let report = copartition_report(&cluster, &["orders", "payments"], true);
+        assert!(report.is_copartitioned());
+        assert!(!report.is_colocated());
+        assert_eq!(report.mismatched_leaders, vec![1]);
+
+        let report = copartition_report(&cluster, &["orders", "users"], false);
+        assert!(!report.is_copartitioned());
+        assert_eq!(report.partition_counts, vec![(String::from_str("orders"), Some(3)),
+                                                 (String::from_str("users"), Some(2))]);
+
+        assert!(!copartition_report(&cluster, &["orders", "missing"], false).is_copartitioned());
+    }
+}
@@ -0,0 +1,125 @@
+use std::str;
+
+use serialize::base64::{ToBase64, STANDARD};
+use serialize::hex::ToHex;
+use serialize::json;
+
+use consumer::ConsumerRecord;
+
+/// Renders a record's key or value as text.
+pub trait BytesFormatter {
+    fn format(&self, bytes: &[u8]) -> String;
+}
+
+/// UTF-8 text, with invalid sequences replaced.
+pub struct Utf8Formatter;
+
+impl BytesFormatter for Utf8Formatter {
+    fn format(&self, bytes: &[u8]) -> String {
+        String::from_utf8_lossy(bytes).into_string()
+    }
+}
+
+/// Lowercase hex, two digits per byte.
+pub struct HexFormatter;
+
+impl BytesFormatter for HexFormatter {
+    fn format(&self, bytes: &[u8]) -> String {
+        bytes.to_hex()
+    }
+}
+
+/// Indented JSON. Bytes which aren't valid JSON are shown as UTF-8 text.
+pub struct JsonFormatter;
+
+impl BytesFormatter for JsonFormatter {
+    fn format(&self, bytes: &[u8]) -> String {
+        match str::from_utf8(bytes).and_then(|text| json::from_str(text).ok()) {
+            Some(json) => json.to_pretty_str(),
+            None => Utf8Formatter.format(bytes)
+        }
+    }
+}
+
+/// Standard base64 with padding.
+pub struct Base64Formatter;
+
+impl BytesFormatter for Base64Formatter {
+    fn format(&self, bytes: &[u8]) -> String {
+        bytes.to_base64(STANDARD)
+    }
+}
+
+/// Renders records for console or log output, one per line unless a
+/// formatter spreads them over several, as `JsonFormatter` does:
+///
+/// ```text
+/// topic/partition@offset key=... value=...
+/// ```
+///
+/// Missing keys and values are shown as `null`.
+pub struct RecordPrinter {
+    key: Box<BytesFormatter + Send>,
+    value: Box<BytesFormatter + Send>
+}
+
+impl RecordPrinter {
+    /// Shows keys and values as UTF-8 text.
+    pub fn new() -> RecordPrinter {
+        RecordPrinter::with_formatters(box Utf8Formatter, box Utf8Formatter)
+    }
+
+    pub fn with_formatters(key: Box<BytesFormatter + Send>, value: Box<BytesFormatter + Send>) -> RecordPrinter {
+        RecordPrinter { key: key, value: value }
+    }
+
+    pub fn format(&self, record: &ConsumerRecord) -> String {
+        format!("{}/{}@{} key={} value={}", record.topic, record.partition, record.offset,
+                format_bytes(&*self.key, &record.key), format_bytes(&*self.value, &record.value))
+    }
+
+    pub fn format_all(&self, records: &[ConsumerRecord]) -> String {
+        let lines: Vec<String> = records.iter().map(|record| self.format(record)).collect();
+        lines.connect("\n")
+    }
+}
+
+fn format_bytes(formatter: &BytesFormatter, bytes: &Option<Vec<u8>>) -> String {
+    match *bytes {
+        Some(ref bytes) => formatter.format(bytes.as_slice()),
+        None => String::from_str("null")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use consumer::ConsumerRecord;
+    use super::*;
+
+    fn record(key: Option<&[u8]>, value: Option<&[u8]>) -> ConsumerRecord {
+        ConsumerRecord {
+            topic: String::from_str("events"),
+            partition: 2,
+            offset: 42,
+            key: key.map(|key| key.to_vec()),
+            value: value.map(|value| value.to_vec())
+        }
+    }
+
+    #[test]
+    fn test_formatters() {
+        assert_eq!(Utf8Formatter.format(b"caf\xc3\xa9").as_slice(), "café");
+        assert_eq!(HexFormatter.format(&[0x00, 0xab, 0x10]).as_slice(), "00ab10");
+        assert_eq!(Base64Formatter.format(b"kafka").as_slice(), "a2Fma2E=");
+        assert_eq!(JsonFormatter.format(b"{\"a\":1}").as_slice(), "{\n  \"a\": 1\n}");
+        assert_eq!(JsonFormatter.format(b"not json").as_slice(), "not json");
+    }
+
+    #[test]
+    fn test_record_printer() {
+        let printer = RecordPrinter::with_formatters(box HexFormatter, box Utf8Formatter);
+        let records = [record(Some(&[0x01, 0x02]), Some(b"hello")), record(None, None)];
+        assert_eq!(printer.format_all(&records).as_slice(),
+                   "events/2@42 key=0102 value=hello\nevents/2@42 key=null value=null");
+    }
+}
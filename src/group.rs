@@ -0,0 +1,517 @@
+use std::io::timer;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, SeqCst};
+use std::time::Duration;
+
+use time;
+
+use client::KafkaClient;
+use cluster::Cluster;
+use protocol;
+use protocol::*;
+use protocol::heartbeat::v0::{HeartbeatRequest, HeartbeatResponse};
+use protocol::join_group::v0::{JoinGroupRequestV0, JoinGroupResponseV0};
+use protocol::join_group::v1::JoinGroupRequestV1;
+use protocol::sync_group::v0::{SyncGroupRequestV0, SyncGroupResponseV0};
+use types::*;
+use versions;
+use versions::ApiVersionRange;
+
+/// A consumer's place in a group, as JoinGroup gives it.
+#[deriving(Show, PartialEq, Eq, Clone)]
+pub struct GroupMember {
+    pub group_id: String,
+    pub generation_id: i32,
+    pub member_id: String,
+    /// Set for a static member, which keeps its partitions across restarts
+    /// as long as it rejoins within the session timeout. Once joined, only
+    /// set if the coordinator supports static membership.
+    pub group_instance_id: Option<String>
+}
+
+impl GroupMember {
+    /// A member about to join `group_id` for the first time, so without a
+    /// generation or member id yet.
+    pub fn new(group_id: &str, group_instance_id: Option<String>) -> GroupMember {
+        GroupMember {
+            group_id: String::from_str(group_id),
+            generation_id: NO_GENERATION_ID,
+            member_id: String::from_str(UNKNOWN_MEMBER_ID),
+            group_instance_id: group_instance_id
+        }
+    }
+}
+
+/// The name of the assignment strategy consumers here join groups with,
+/// the same as Kafka's own `RangeAssignor` so they can share a group with
+/// other clients using it.
+pub const RANGE_ASSIGNOR: &'static str = "range";
+
+// Times JoinGroup is sent before giving up, as the coordinator may ask for a
+// member id first or start another rebalance before SyncGroup
+const MAX_JOIN_ATTEMPTS: uint = 5;
+
+// The JoinGroup version static members join with
+const STATIC_JOIN_VERSION: i16 = 5;
+
+#[deriving(Show, PartialEq, Eq, Clone)]
+pub enum HeartbeatStatus {
+    /// The coordinator accepted the last heartbeat, or none has been sent yet.
+    HeartbeatOk,
+    /// The coordinator answered with this error, meaning the group is
+    /// rebalancing or has moved on without the member, which has to rejoin.
+    RejoinNeeded(protocol::Error),
+    /// No heartbeat got through for a whole session timeout, so the
+    /// coordinator will have dropped the member, which has to rejoin.
+    SessionExpired,
+    /// Another consumer joined with the member's `group_instance_id`, so
+    /// this one mustn't rejoin.
+    Fenced,
+    /// `stop` was called.
+    HeartbeatStopped
+}
+
+/// Sends heartbeats for a group member on a task of the client's `Spawner`,
+/// so a slow processing loop can't get the member evicted. Heartbeats stop
+/// once the member has to rejoin, at which point a new `Heartbeater` is
+/// needed for the new generation, or when it's stopped or dropped.
+pub struct Heartbeater {
+    stopped: Arc<AtomicBool>,
+    status: Arc<Mutex<HeartbeatStatus>>
+}
+
+impl Heartbeater {
+    /// Heartbeats every `interval`, which should be well under
+    /// `session_timeout`, a third of it being usual.
+    pub fn start(client: KafkaClient, member: GroupMember, interval: Duration,
+                 session_timeout: Duration) -> Heartbeater {
+        let stopped = Arc::new(AtomicBool::new(false));
+        let status = Arc::new(Mutex::new(HeartbeatOk));
+
+        let (task_client, task_stopped, task_status) = (client.clone(), stopped.clone(), status.clone());
+        client.spawn(proc() {
+            heartbeat(task_client, member, interval, session_timeout, task_stopped, task_status)
+        });
+        Heartbeater { stopped: stopped, status: status }
+    }
+
+    /// Checked by the consumer in its loop, to rejoin when needed.
+    pub fn status(&self) -> HeartbeatStatus {
+        self.status.lock().clone()
+    }
+
+    pub fn stop(&self) {
+        self.stopped.store(true, SeqCst);
+    }
+}
+
+impl Drop for Heartbeater {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+impl KafkaClient {
+    /// Every group the cluster's brokers coordinate, asking each broker in
+    /// turn since each only knows its own groups.
+    pub fn list_groups(&self) -> KafkaResult<Vec<ListGroupsGroup>> {
+        try!(self.load_metadata(vec![]));
+        let brokers: Vec<(String, u16)> = match self.cluster() {
+            Some(cluster) => cluster.brokers().iter().map(|broker| (broker.host.clone(), broker.port as u16)).collect(),
+            None => fail!((NoMetadata, "No metadata loaded"))
+        };
+
+        let mut groups = vec![];
+        for (host, port) in brokers.into_iter() {
+            let response: ListGroupsResponse = try!(self.send_to_host(host.as_slice(), port, ListGroupsRequest));
+            try!(check_error_code(response.error_code));
+            groups.extend(response.groups.into_iter());
+        }
+        groups.sort_by(|a, b| a.group_id.cmp(&b.group_id));
+        Ok(groups)
+    }
+
+    /// The state, protocol and members of a group, from its coordinator.
+    pub fn describe_group(&self, group: &str) -> KafkaResult<DescribeGroupsGroup> {
+        let (host, port) = try!(self.group_coordinator(group));
+        let request = DescribeGroupsRequest { group_ids: vec![String::from_str(group)] };
+        let response: DescribeGroupsResponse = try!(self.send_to_host(host.as_slice(), port, request));
+        match response.groups.into_iter().find(|described| described.group_id.as_slice() == group) {
+            Some(described) => {
+                try!(check_error_code(described.error_code));
+                Ok(described)
+            },
+            None => fail!((MalformedResponseError, "DescribeGroups response is missing the requested group"))
+        }
+    }
+
+    /// Describes each of `groups` in turn.
+    pub fn describe_groups(&self, groups: &[&str]) -> KafkaResult<Vec<DescribeGroupsGroup>> {
+        let mut described = vec![];
+        for group in groups.iter() {
+            described.push(try!(self.describe_group(*group)));
+        }
+        Ok(described)
+    }
+
+    /// Joins `member`'s group as a consumer of `topics`, working out every
+    /// member's partitions with `RANGE_ASSIGNOR` if chosen to lead, and
+    /// returns the member's place in the new generation along with its
+    /// partitions. Blocks until every member has rejoined, for up to
+    /// `rebalance_timeout`, which has to stay below the connection's
+    /// `read_timeout`.
+    ///
+    /// A static member, with a `group_instance_id`, rejoining within
+    /// `session_timeout` gets its partitions back without the rest of the
+    /// group rebalancing. That needs a coordinator from Kafka 2.3 on; older
+    /// ones are joined without the instance id, with JoinGroup v0 or v1 and
+    /// SyncGroup v0. Fails with `FencedInstanceIdCode` if another consumer
+    /// has joined with the same instance id.
+    pub fn join_consumer_group(&self, member: &GroupMember, topics: &[String], session_timeout: Duration,
+                               rebalance_timeout: Duration) -> KafkaResult<(GroupMember, Vec<TopicPartition>)> {
+        let metadata = try!(ConsumerProtocolSubscription::new(topics.to_vec()).to_bytes());
+        let mut member_id = member.member_id.clone();
+        let mut last_error = None;
+        for _ in range(0, MAX_JOIN_ATTEMPTS) {
+            let (host, port) = try!(self.group_coordinator(member.group_id.as_slice()));
+            let version = join_version(member, try!(self.broker_versions(host.as_slice(), port)).as_slice());
+            let instance = if version == STATIC_JOIN_VERSION { member.group_instance_id.clone() } else { None };
+            let request = JoinGroupRequest {
+                group_id: member.group_id.clone(),
+                session_timeout_ms: session_timeout.num_milliseconds() as i32,
+                rebalance_timeout_ms: rebalance_timeout.num_milliseconds() as i32,
+                member_id: member_id.clone(),
+                group_instance_id: instance.clone(),
+                protocol_type: String::from_str(CONSUMER_PROTOCOL_TYPE),
+                protocols: vec![JoinGroupProtocol { name: String::from_str(RANGE_ASSIGNOR), metadata: metadata.clone() }]
+            };
+            let joined = try!(self.send_join_group(host.as_slice(), port, version, request));
+            match check_error_code(joined.error_code) {
+                Ok(()) => (),
+                // The coordinator has picked a member id to join with
+                Err(KafkaError { kind: BrokerError(MemberIdRequiredCode), .. }) => {
+                    member_id = joined.member_id;
+                    continue;
+                },
+                // The coordinator has forgotten the member, so it joins afresh
+                Err(err @ KafkaError { kind: BrokerError(UnknownMemberIdCode), .. }) => {
+                    member_id = String::from_str(UNKNOWN_MEMBER_ID);
+                    last_error = Some(err);
+                    continue;
+                },
+                Err(err) => return Err(err)
+            }
+
+            let assignments = if joined.is_leader() {
+                try!(self.assign_range(joined.members.as_slice()))
+            } else {
+                vec![]
+            };
+            let joined_member = GroupMember {
+                group_id: member.group_id.clone(),
+                generation_id: joined.generation_id,
+                member_id: joined.member_id,
+                group_instance_id: instance
+            };
+            match self.sync_group(&joined_member, assignments) {
+                Ok(partitions) => return Ok((joined_member, partitions)),
+                // Another rebalance started before this one finished
+                Err(err @ KafkaError { kind: BrokerError(RebalanceInProgressCode), .. }) => {
+                    member_id = joined_member.member_id;
+                    last_error = Some(err);
+                },
+                Err(err) => return Err(err)
+            }
+        }
+        match last_error {
+            Some(err) => Err(err),
+            None => fail!(RebalanceInProgressCode)
+        }
+    }
+
+    // Sends `request` as JoinGroup `version`, dropping what older versions
+    // don't have, and reads the answer as a version 5 response
+    fn send_join_group(&self, host: &str, port: u16, version: i16,
+                       request: JoinGroupRequest) -> KafkaResult<JoinGroupResponse> {
+        let response: JoinGroupResponseV0 = match version {
+            STATIC_JOIN_VERSION => return self.send_to_host(host, port, request),
+            1 => try!(self.send_to_host(host, port, JoinGroupRequestV1 {
+                group_id: request.group_id,
+                session_timeout_ms: request.session_timeout_ms,
+                rebalance_timeout_ms: request.rebalance_timeout_ms,
+                member_id: request.member_id,
+                protocol_type: request.protocol_type,
+                protocols: request.protocols
+            })),
+            _ => try!(self.send_to_host(host, port, JoinGroupRequestV0 {
+                group_id: request.group_id,
+                session_timeout_ms: request.session_timeout_ms,
+                member_id: request.member_id,
+                protocol_type: request.protocol_type,
+                protocols: request.protocols
+            }))
+        };
+        Ok(JoinGroupResponse {
+            throttle_time_ms: 0,
+            error_code: response.error_code,
+            generation_id: response.generation_id,
+            protocol_name: response.protocol_name,
+            leader: response.leader,
+            member_id: response.member_id,
+            members: response.members.into_iter().map(|member| JoinGroupMember {
+                member_id: member.member_id,
+                group_instance_id: None,
+                metadata: member.metadata
+            }).collect()
+        })
+    }
+
+    // Sends the leader's assignments, or none from other members, and reads
+    // back the member's own. Static members joined with JoinGroup v5 and
+    // sync with the matching version 3.
+    fn sync_group(&self, member: &GroupMember,
+                  assignments: Vec<SyncGroupAssignment>) -> KafkaResult<Vec<TopicPartition>> {
+        let (host, port) = try!(self.group_coordinator(member.group_id.as_slice()));
+        let (error_code, assignment) = match member.group_instance_id {
+            Some(ref instance) => {
+                let request = SyncGroupRequest {
+                    group_id: member.group_id.clone(),
+                    generation_id: member.generation_id,
+                    member_id: member.member_id.clone(),
+                    group_instance_id: Some(instance.clone()),
+                    assignments: assignments
+                };
+                let response: SyncGroupResponse = try!(self.send_to_host(host.as_slice(), port, request));
+                (response.error_code, response.assignment)
+            },
+            None => {
+                let request = SyncGroupRequestV0 {
+                    group_id: member.group_id.clone(),
+                    generation_id: member.generation_id,
+                    member_id: member.member_id.clone(),
+                    assignments: assignments
+                };
+                let response: SyncGroupResponseV0 = try!(self.send_to_host(host.as_slice(), port, request));
+                (response.error_code, response.assignment)
+            }
+        };
+        try!(check_error_code(error_code));
+        Ok(try!(ConsumerProtocolAssignment::from_bytes(assignment.as_slice())).partitions())
+    }
+
+    // The leader's assignment for every member, from their subscriptions
+    fn assign_range(&self, members: &[JoinGroupMember]) -> KafkaResult<Vec<SyncGroupAssignment>> {
+        let mut subscriptions = vec![];
+        for member in members.iter() {
+            let subscription = try!(ConsumerProtocolSubscription::from_bytes(member.metadata.as_slice()));
+            subscriptions.push((member.member_id.clone(), member.group_instance_id.clone(), subscription.topics));
+        }
+        let mut topics: Vec<String> = vec![];
+        for &(_, _, ref subscribed) in subscriptions.iter() {
+            topics.push_all(subscribed.as_slice());
+        }
+        topics.sort();
+        topics.dedup();
+        try!(self.load_metadata(topics));
+        let cluster = match self.cluster() {
+            Some(cluster) => cluster,
+            None => fail!((NoMetadata, "No metadata loaded"))
+        };
+
+        let mut assignments = vec![];
+        for (member_id, partitions) in range_assignment(subscriptions.as_slice(), &cluster).into_iter() {
+            let assignment = try!(ConsumerProtocolAssignment::new(partitions.as_slice()).to_bytes());
+            assignments.push(SyncGroupAssignment { member_id: member_id, assignment: assignment });
+        }
+        Ok(assignments)
+    }
+}
+
+// The JoinGroup version to join with: 5 for a static member of a group whose
+// coordinator supports it, and otherwise the newest from before static
+// membership
+fn join_version(member: &GroupMember, broker_versions: &[ApiVersionRange]) -> i16 {
+    if member.group_instance_id.is_some() && versions::supports_static_membership(broker_versions) {
+        STATIC_JOIN_VERSION
+    } else {
+        versions::negotiate(versions::JOIN_GROUP, broker_versions).unwrap_or(0)
+    }
+}
+
+// Splits each topic's partitions, in order, into a contiguous range for each
+// member subscribed to it. As with Java's RangeAssignor, static members come
+// first in instance id order, so they keep their partitions across restarts
+// under new member ids, then the rest in member id order. While partitions
+// don't divide evenly the first members take one more. Every member is
+// listed, if only with no partitions.
+fn range_assignment(subscriptions: &[(String, Option<String>, Vec<String>)],
+                    cluster: &Cluster) -> Vec<(String, Vec<TopicPartition>)> {
+    let mut ordered: Vec<&(String, Option<String>, Vec<String>)> = subscriptions.iter().collect();
+    ordered.sort_by(|&&(ref a_member, ref a_instance, _), &&(ref b_member, ref b_instance, _)| {
+        match (a_instance, b_instance) {
+            (&Some(ref a), &Some(ref b)) => a.cmp(b),
+            (&Some(_), &None) => Less,
+            (&None, &Some(_)) => Greater,
+            (&None, &None) => a_member.cmp(b_member)
+        }
+    });
+    let mut assignment: Vec<(String, Vec<TopicPartition>)> = ordered.iter()
+        .map(|&&(ref member_id, _, _)| (member_id.clone(), vec![]))
+        .collect();
+
+    for topic in cluster.topics().into_iter() {
+        let partitions = match cluster.partitions_for(topic.as_slice()) {
+            Some(partitions) => partitions,
+            None => continue
+        };
+        let members: Vec<uint> = range(0, ordered.len()).filter(|&i| ordered[i].2.contains(topic)).collect();
+        if members.is_empty() {
+            continue;
+        }
+
+        let (each, extra) = (partitions.len() / members.len(), partitions.len() % members.len());
+        let mut start = 0;
+        for (n, &i) in members.iter().enumerate() {
+            let count = each + if n < extra { 1 } else { 0 };
+            for partition in partitions.slice(start, start + count).iter() {
+                assignment.as_mut_slice()[i].1.push(TopicPartition::new(topic.as_slice(), partition.partition));
+            }
+            start += count;
+        }
+    }
+    assignment
+}
+
+fn heartbeat(client: KafkaClient, member: GroupMember, interval: Duration, session_timeout: Duration,
+             stopped: Arc<AtomicBool>, status: Arc<Mutex<HeartbeatStatus>>) {
+    let session_timeout_ns = session_timeout.num_milliseconds() as u64 * 1000000;
+    let mut last_success = time::precise_time_ns();
+    loop {
+        if stopped.load(SeqCst) {
+            *status.lock() = HeartbeatStopped;
+            return;
+        }
+
+        let result = send_heartbeat(&client, &member);
+        let now = time::precise_time_ns();
+        if result.is_ok() {
+            last_success = now;
+        }
+        match heartbeat_outcome(&result, now - last_success, session_timeout_ns) {
+            Some(outcome) => {
+                *status.lock() = outcome;
+                return;
+            },
+            None => ()
+        }
+
+        // Sleep in short steps so stopping doesn't wait out the interval
+        let wake = time::precise_time_ns() + interval.num_milliseconds() as u64 * 1000000;
+        while !stopped.load(SeqCst) && time::precise_time_ns() < wake {
+            timer::sleep(Duration::milliseconds(10));
+        }
+    }
+}
+
+// Version 3 with the instance id for static members, so the coordinator
+// can tell when they've been fenced, and version 0 otherwise
+fn send_heartbeat(client: &KafkaClient, member: &GroupMember) -> KafkaResult<()> {
+    let (host, port) = try!(client.group_coordinator(member.group_id.as_slice()));
+    let error_code = match member.group_instance_id {
+        Some(ref instance) => {
+            let request = HeartbeatRequestV3 {
+                group_id: member.group_id.clone(),
+                group_generation_id: member.generation_id,
+                member_id: member.member_id.clone(),
+                group_instance_id: Some(instance.clone())
+            };
+            let response: HeartbeatResponseV3 = try!(client.send_to_host(host.as_slice(), port, request));
+            response.error_code
+        },
+        None => {
+            let request = HeartbeatRequest {
+                group_id: member.group_id.clone(),
+                group_generation_id: member.generation_id,
+                member_id: member.member_id.clone()
+            };
+            let response: HeartbeatResponse = try!(client.send_to_host(host.as_slice(), port, request));
+            response.error_code
+        }
+    };
+    check_error_code(error_code)
+}
+
+// Whether heartbeats have to stop after a heartbeat with `result`, `since`
+// ns after the last one to get through. Other errors, such as the
+// coordinator moving, are retried until the session times out.
+fn heartbeat_outcome(result: &KafkaResult<()>, since: u64, session_timeout: u64) -> Option<HeartbeatStatus> {
+    match *result {
+        Err(KafkaError { kind: BrokerError(ref error), .. }) if *error == RebalanceInProgressCode ||
+                                                               *error == IllegalGenerationCode ||
+                                                               *error == UnknownMemberIdCode => {
+            Some(RejoinNeeded(error.clone()))
+        },
+        Err(KafkaError { kind: BrokerError(FencedInstanceIdCode), .. }) => Some(Fenced),
+        Err(_) if since >= session_timeout => Some(SessionExpired),
+        _ => None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::error::FromError;
+    use protocol::*;
+    use types::*;
+    use super::*;
+    use super::{heartbeat_outcome, range_assignment};
+    use cluster::Cluster;
+
+    fn error(error: Error) -> KafkaResult<()> {
+        Err(FromError::from_error(error))
+    }
+
+    #[test]
+    fn test_heartbeat_outcome() {
+        assert_eq!(heartbeat_outcome(&Ok(()), 0, 100), None);
+        assert_eq!(heartbeat_outcome(&error(RebalanceInProgressCode), 0, 100), Some(RejoinNeeded(RebalanceInProgressCode)));
+        assert_eq!(heartbeat_outcome(&error(UnknownMemberIdCode), 0, 100), Some(RejoinNeeded(UnknownMemberIdCode)));
+        assert_eq!(heartbeat_outcome(&error(FencedInstanceIdCode), 0, 100), Some(Fenced));
+        // Retried until the session times out
+        assert_eq!(heartbeat_outcome(&error(NotCoordinatorForConsumerCode), 50, 100), None);
+        assert_eq!(heartbeat_outcome(&error(NotCoordinatorForConsumerCode), 100, 100), Some(SessionExpired));
+    }
+
+    #[test]
+    fn test_range_assignment() {
+        let topic = |name: &str, partitions: i32| TopicMetadata {
+            error_code: 0,
+            name: String::from_str(name),
+            partitions: range(0, partitions).map(|partition| PartitionMetadata {
+                error_code: 0, partition: partition, leader: 1, replicas: vec![1], isr: vec![1]
+            }).collect()
+        };
+        let cluster = Cluster::new(&MetadataResponse { brokers: vec![], topics: vec![topic("a", 5), topic("b", 2)] });
+        let both = vec![String::from_str("a"), String::from_str("b")];
+        let subscriptions = [(String::from_str("m-2"), None, both.clone()),
+                             (String::from_str("m-1"), None, both.clone()),
+                             (String::from_str("m-3"), None, vec![String::from_str("b")])];
+
+        assert_eq!(range_assignment(&subscriptions, &cluster), vec![
+            (String::from_str("m-1"), vec![TopicPartition::new("a", 0), TopicPartition::new("a", 1),
+                                           TopicPartition::new("a", 2), TopicPartition::new("b", 0)]),
+            (String::from_str("m-2"), vec![TopicPartition::new("a", 3), TopicPartition::new("a", 4),
+                                           TopicPartition::new("b", 1)]),
+            (String::from_str("m-3"), vec![])]);
+
+        // Static members first, by instance id rather than member id
+        let subscriptions = [(String::from_str("m-1"), None, both.clone()),
+                             (String::from_str("m-2"), Some(String::from_str("host-2")), both.clone()),
+                             (String::from_str("m-3"), Some(String::from_str("host-1")), both)];
+        assert_eq!(range_assignment(&subscriptions, &cluster), vec![
+            (String::from_str("m-3"), vec![TopicPartition::new("a", 0), TopicPartition::new("a", 1),
+                                           TopicPartition::new("b", 0)]),
+            (String::from_str("m-2"), vec![TopicPartition::new("a", 2), TopicPartition::new("a", 3),
+                                           TopicPartition::new("b", 1)]),
+            (String::from_str("m-1"), vec![TopicPartition::new("a", 4)])]);
+    }
+}
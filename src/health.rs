@@ -0,0 +1,284 @@
+use std::io::timer;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, SeqCst};
+use std::time::Duration;
+
+use time;
+
+use client::KafkaClient;
+use producer::{Producer, ProducerRecord};
+use protocol::*;
+use types::*;
+
+/// How deeply `health_check` exercises the cluster.
+#[deriving(Show, PartialEq, Eq, Clone)]
+pub enum HealthProbe {
+    /// Produce a small canary record and fetch it back.
+    ProduceAndFetch,
+    /// Only check metadata and that a group coordinator is reachable, for
+    /// clients which aren't allowed to write.
+    ReadOnly
+}
+
+#[deriving(Show, Clone)]
+pub struct HealthCheck {
+    pub name: &'static str,
+    pub latency: Duration,
+    pub result: KafkaResult<()>
+}
+
+/// The outcome of each step of a health check, in the order they ran. Steps
+/// after a failed one are skipped.
+#[deriving(Show, Clone)]
+pub struct HealthReport {
+    pub topic: String,
+    pub checks: Vec<HealthCheck>
+}
+
+impl HealthReport {
+    pub fn is_healthy(&self) -> bool {
+        self.checks.iter().all(|check| check.result.is_ok())
+    }
+
+    /// The first failed check, if any.
+    pub fn failure(&self) -> Option<&HealthCheck> {
+        self.checks.iter().find(|check| check.result.is_err())
+    }
+
+    fn run<T>(&mut self, name: &'static str, check: || -> KafkaResult<T>) -> Option<T> {
+        let start = time::precise_time_ns();
+        let result = check();
+        let latency = Duration::nanoseconds((time::precise_time_ns() - start) as i64);
+        let (result, value) = match result {
+            Ok(value) => (Ok(()), Some(value)),
+            Err(err) => (Err(err), None)
+        };
+        self.checks.push(HealthCheck { name: name, latency: latency, result: result });
+        value
+    }
+}
+
+impl KafkaClient {
+    /// Checks that `topic` can be used, as a readiness probe would.
+    pub fn health_check(&self, topic: &str, probe: HealthProbe) -> HealthReport {
+        let mut report = HealthReport { topic: String::from_str(topic), checks: vec![] };
+
+        let metadata = report.run("Metadata", || {
+            let metadata = try!(self.load_metadata(vec![String::from_str(topic)]));
+            match metadata.topics.iter().find(|t| t.name.as_slice() == topic) {
+                Some(topic_metadata) => check_error_code(topic_metadata.error_code),
+                None => fail!(UnknownTopicOrPartition)
+            }
+        });
+        if metadata.is_none() {
+            return report;
+        }
+
+        match probe {
+            ProduceAndFetch => {
+                let value = canary_value();
+                let producer = Producer::new(self.clone());
+                let written = report.run("Produce", || {
+                    producer.send(ProducerRecord::new(topic, None, Some(value.clone())))
+                });
+                match written {
+                    Some((partition, offset)) => {
+                        report.run("Fetch", || {
+                            let fetched = try!(self.fetch_partition(&partition, offset, 64 * 1024));
+                            find_canary(&fetched, offset, value.as_slice())
+                        });
+                    },
+                    None => ()
+                }
+            },
+            ReadOnly => {
+                report.run("Coordinator", || self.check_coordinator());
+            }
+        }
+        report
+    }
+
+    // Finds a coordinator and makes sure it answers too
+    fn check_coordinator(&self) -> KafkaResult<()> {
+        let request = ConsumerMetadataRequest { group: String::from_str("health-check") };
+        let response: ConsumerMetadataResponse = try!(self.send_to_any(request.clone()));
+        try!(check_error_code(response.error_code));
+
+        let response: ConsumerMetadataResponse = try!(self.send_to_host(response.coordinator_host.as_slice(),
+                                                                         response.coordinator_port as u16,
+                                                                         request));
+        check_error_code(response.error_code)
+    }
+}
+
+fn canary_value() -> Vec<u8> {
+    format!("canary {}", time::precise_time_ns()).into_bytes()
+}
+
+fn find_canary(fetched: &FetchResponsePartition, offset: i64, value: &[u8]) -> KafkaResult<()> {
+    let WithSize(ref message_set) = fetched.messages;
+    for element in message_set.messages.iter().filter(|element| element.offset == offset) {
+        let WithSize(ref message) = element.message;
+        match message.value {
+            Some(ref found) if found.as_slice() == value => return Ok(()),
+            _ => fail!((MalformedResponseError, "Fetched canary doesn't match the one produced"))
+        }
+    }
+    fail!((MalformedResponseError, "Produced canary wasn't fetched back"))
+}
+
+#[deriving(Show, Clone)]
+pub struct CanaryConfig {
+    /// Time between canary records.
+    pub interval: Duration,
+    /// Longest acceptable wait for a canary to be acknowledged.
+    pub ack_slo: Duration,
+    /// Longest acceptable time from sending a canary to fetching it back.
+    pub end_to_end_slo: Duration
+}
+
+impl CanaryConfig {
+    pub fn new() -> CanaryConfig {
+        CanaryConfig {
+            interval: Duration::seconds(10),
+            ack_slo: Duration::milliseconds(500),
+            end_to_end_slo: Duration::seconds(2)
+        }
+    }
+}
+
+#[deriving(Show, Clone)]
+pub enum CanaryEvent {
+    /// A canary made it round, with its (ack, end to end) latencies.
+    CanaryDelivered(Duration, Duration),
+    AckSloBreached(Duration),
+    EndToEndSloBreached(Duration),
+    CanaryFailed(KafkaError)
+}
+
+/// Publishes a canary record to a topic every `interval` from a background
+/// task, fetching each back to measure latency. Every canary produces a
+/// `CanaryDelivered` or `CanaryFailed` event, followed by an event for each
+/// SLO it breached. Publishing stops when the publisher is dropped.
+pub struct CanaryPublisher {
+    events: Receiver<CanaryEvent>,
+    stopped: Arc<AtomicBool>
+}
+
+impl CanaryPublisher {
+    pub fn start(client: &KafkaClient, topic: &str, config: CanaryConfig) -> CanaryPublisher {
+        let (tx, rx) = channel();
+        let stopped = Arc::new(AtomicBool::new(false));
+
+        let (task_client, task_stopped, topic) = (client.clone(), stopped.clone(), String::from_str(topic));
+        client.spawn(proc() {
+            let producer = Producer::new(task_client.clone());
+            while !task_stopped.load(SeqCst) {
+                let events = match round_trip(&task_client, &producer, topic.as_slice()) {
+                    Ok((ack, end_to_end)) => latency_events(&config, ack, end_to_end),
+                    Err(err) => vec![CanaryFailed(err)]
+                };
+                for event in events.into_iter() {
+                    // Nobody is listening any more
+                    if tx.send_opt(event).is_err() {
+                        return;
+                    }
+                }
+                timer::sleep(config.interval);
+            }
+        });
+
+        CanaryPublisher { events: rx, stopped: stopped }
+    }
+
+    pub fn events(&self) -> &Receiver<CanaryEvent> {
+        &self.events
+    }
+}
+
+impl Drop for CanaryPublisher {
+    fn drop(&mut self) {
+        self.stopped.store(true, SeqCst);
+    }
+}
+
+fn elapsed_since(start: u64) -> Duration {
+    Duration::nanoseconds((time::precise_time_ns() - start) as i64)
+}
+
+// The ack and end to end latency of one canary
+fn round_trip(client: &KafkaClient, producer: &Producer, topic: &str) -> KafkaResult<(Duration, Duration)> {
+    let value = canary_value();
+    let start = time::precise_time_ns();
+    let (partition, offset) = try!(producer.send(ProducerRecord::new(topic, None, Some(value.clone()))));
+    let ack = elapsed_since(start);
+
+    let fetched = try!(client.fetch_partition(&partition, offset, 64 * 1024));
+    try!(find_canary(&fetched, offset, value.as_slice()));
+    Ok((ack, elapsed_since(start)))
+}
+
+fn latency_events(config: &CanaryConfig, ack: Duration, end_to_end: Duration) -> Vec<CanaryEvent> {
+    let mut events = vec![CanaryDelivered(ack, end_to_end)];
+    if ack > config.ack_slo {
+        events.push(AckSloBreached(ack));
+    }
+    if end_to_end > config.end_to_end_slo {
+        events.push(EndToEndSloBreached(end_to_end));
+    }
+    events
+}
+
+#[cfg(test)]
+mod tests {
+    use std::error::FromError;
+    use std::time::Duration;
+    use protocol::*;
+    use types::*;
+    use super::*;
+    use super::{find_canary, latency_events};
+
+    fn fetched(offset: i64, value: &str) -> FetchResponsePartition {
+        FetchResponsePartition {
+            partition: 0,
+            error_code: 0,
+            highwater_mark_offset: offset + 1,
+            messages: WithSize(MessageSet { messages: vec![MessageSetElement {
+                offset: offset,
+                message: WithSize(Message::new(None, Some(value.as_bytes().to_vec())).ok().unwrap())
+            }]})
+        }
+    }
+
+    #[test]
+    fn test_find_canary() {
+        assert!(find_canary(&fetched(5, "canary"), 5, b"canary").is_ok());
+        assert!(find_canary(&fetched(5, "other"), 5, b"canary").is_err());
+        assert!(find_canary(&fetched(4, "canary"), 5, b"canary").is_err());
+    }
+
+    #[test]
+    fn test_report() {
+        let check = |name, result| HealthCheck { name: name, latency: Duration::milliseconds(3), result: result };
+        let mut report = HealthReport { topic: String::from_str("events"), checks: vec![check("Metadata", Ok(()))] };
+        assert!(report.is_healthy());
+
+        report.checks.push(check("Produce", Err(FromError::from_error(LeaderNotAvailable))));
+        assert!(!report.is_healthy());
+        assert_eq!(report.failure().unwrap().name, "Produce");
+    }
+
+    #[test]
+    fn test_latency_events() {
+        let config = CanaryConfig::new();
+        let events = latency_events(&config, Duration::milliseconds(20), Duration::milliseconds(50));
+        assert_eq!(events.len(), 1);
+
+        let events = latency_events(&config, Duration::milliseconds(600), Duration::seconds(3));
+        assert_eq!(events.len(), 3);
+        match events[2] {
+            EndToEndSloBreached(latency) => assert_eq!(latency, Duration::seconds(3)),
+            ref other => panic!("Unexpected event {}", other)
+        }
+    }
+}
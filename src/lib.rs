@@ -3,7 +3,34 @@
 #![feature(tuple_indexing)]
 #![experimental]
 
+extern crate flate;
+extern crate regex;
+extern crate serialize;
+extern crate time;
+
 mod macros;
 
 pub mod types;
 pub mod protocol;
+pub mod versions;
+pub mod borrowed;
+pub mod buffer;
+pub mod codec;
+pub mod connection;
+pub mod cluster;
+pub mod scoring;
+pub mod spawn;
+pub mod cancel;
+pub mod trace;
+pub mod client;
+pub mod partitioner;
+pub mod producer;
+pub mod routing;
+pub mod cache;
+pub mod consumer;
+pub mod group;
+pub mod display;
+pub mod health;
+pub mod copartition;
+pub mod admin;
+pub mod consumer_offsets;
@@ -0,0 +1,233 @@
+use std::collections::HashMap;
+use std::rand::{Rng, XorShiftRng};
+
+/// Chooses the partition for records sent without an explicit one.
+pub trait Partitioner {
+    /// Picks a partition in `0..num_partitions` for a record with `key`.
+    fn partition(&mut self, key: Option<&[u8]>, num_partitions: i32) -> i32;
+
+    /// Like `partition`, also given the topic and the partitions which
+    /// currently have a leader. This is what the producer calls, so
+    /// partitioners that can avoid unavailable partitions should override it.
+    fn partition_among(&mut self, _topic: &str, key: Option<&[u8]>, num_partitions: i32, _available: &[i32]) -> i32 {
+        self.partition(key, num_partitions)
+    }
+
+    /// Called by batching producers when the batch for a partition fills up
+    /// and a new one is started.
+    fn on_new_batch(&mut self, _topic: &str, _partition: i32) {}
+}
+
+/// The murmur2 hash used by the Java client to partition keyed records.
+pub fn murmur2(data: &[u8]) -> i32 {
+    let m = 0x5bd1e995u32;
+    let length = data.len();
+    let mut h = 0x9747b28cu32 ^ length as u32;
+
+    for chunk in data.chunks(4).filter(|chunk| chunk.len() == 4) {
+        let mut k = chunk[0] as u32 | (chunk[1] as u32 << 8) | (chunk[2] as u32 << 16) | (chunk[3] as u32 << 24);
+        k *= m;
+        k ^= k >> 24;
+        k *= m;
+        h *= m;
+        h ^= k;
+    }
+
+    let tail = data.slice_from(length & !3);
+    if tail.len() >= 3 {
+        h ^= tail[2] as u32 << 16;
+    }
+    if tail.len() >= 2 {
+        h ^= tail[1] as u32 << 8;
+    }
+    if tail.len() >= 1 {
+        h ^= tail[0] as u32;
+        h *= m;
+    }
+
+    h ^= h >> 13;
+    h *= m;
+    h ^= h >> 15;
+    h as i32
+}
+
+/// Partitions keyed records exactly as the Java client's default partitioner
+/// does, so both put a given key on the same partition. Keyless records go to
+/// partition 0.
+pub struct Murmur2Partitioner;
+
+impl Partitioner for Murmur2Partitioner {
+    fn partition(&mut self, key: Option<&[u8]>, num_partitions: i32) -> i32 {
+        match key {
+            Some(key) => murmur2_partition(key, num_partitions),
+            None => 0
+        }
+    }
+}
+
+fn murmur2_partition(key: &[u8], num_partitions: i32) -> i32 {
+    (murmur2(key) & 0x7fffffff) % num_partitions
+}
+
+/// Hashes keyed records like `Murmur2Partitioner`, and spreads keyless
+/// records evenly by cycling through the partitions, skipping any without a
+/// leader.
+pub struct RoundRobinPartitioner {
+    counter: uint
+}
+
+impl RoundRobinPartitioner {
+    /// Starts the cycle at a random point, so producers started together
+    /// don't all write to the same partition at once.
+    pub fn new<R:Rng>(rng: &mut R) -> RoundRobinPartitioner {
+        RoundRobinPartitioner { counter: rng.gen() }
+    }
+
+    fn next(&mut self) -> uint {
+        let next = self.counter;
+        self.counter += 1;
+        next
+    }
+}
+
+impl Partitioner for RoundRobinPartitioner {
+    fn partition(&mut self, key: Option<&[u8]>, num_partitions: i32) -> i32 {
+        match key {
+            Some(key) => murmur2_partition(key, num_partitions),
+            None => (self.next() % num_partitions as uint) as i32
+        }
+    }
+
+    fn partition_among(&mut self, _topic: &str, key: Option<&[u8]>, num_partitions: i32, available: &[i32]) -> i32 {
+        // Keys must keep their partition even while it's unavailable
+        if key.is_some() || available.is_empty() {
+            return self.partition(key, num_partitions);
+        }
+        available[self.next() % available.len()]
+    }
+}
+
+/// Hashes keyed records like `Murmur2Partitioner`, and sends keyless records
+/// for a topic to one randomly chosen partition until its batch fills, then
+/// moves on to another. Fuller batches mean fewer, larger requests, which
+/// matters most for small records.
+pub struct StickyPartitioner {
+    rng: XorShiftRng,
+    sticky: HashMap<String, i32>
+}
+
+impl StickyPartitioner {
+    pub fn new(rng: XorShiftRng) -> StickyPartitioner {
+        StickyPartitioner { rng: rng, sticky: HashMap::new() }
+    }
+
+    fn choose(&mut self, num_partitions: i32, available: &[i32], previous: Option<i32>) -> i32 {
+        let candidates: Vec<i32> = if available.is_empty() {
+            range(0, num_partitions).collect()
+        } else {
+            available.to_vec()
+        };
+        // Only stay put when there's nowhere else to go
+        let others: Vec<i32> = candidates.iter().map(|&p| p).filter(|&p| Some(p) != previous).collect();
+        match self.rng.choose(others.as_slice()) {
+            Some(&partition) => partition,
+            None => candidates[0]
+        }
+    }
+}
+
+impl Partitioner for StickyPartitioner {
+    fn partition(&mut self, key: Option<&[u8]>, num_partitions: i32) -> i32 {
+        match key {
+            Some(key) => murmur2_partition(key, num_partitions),
+            None => self.choose(num_partitions, &[], None)
+        }
+    }
+
+    fn partition_among(&mut self, topic: &str, key: Option<&[u8]>, num_partitions: i32, available: &[i32]) -> i32 {
+        if key.is_some() {
+            return self.partition(key, num_partitions);
+        }
+
+        let current = self.sticky.get(&String::from_str(topic)).map(|&partition| partition);
+        match current {
+            // Leadership may have moved, or the topic shrunk, since the partition was chosen
+            Some(partition) if partition < num_partitions && (available.is_empty() || available.contains(&partition)) => partition,
+            _ => {
+                let partition = self.choose(num_partitions, available, current);
+                self.sticky.insert(String::from_str(topic), partition);
+                partition
+            }
+        }
+    }
+
+    fn on_new_batch(&mut self, topic: &str, partition: i32) {
+        let topic = String::from_str(topic);
+        if self.sticky.get(&topic) == Some(&partition) {
+            self.sticky.remove(&topic);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rand::{SeedableRng, XorShiftRng};
+    use super::*;
+
+    #[test]
+    fn test_murmur2() {
+        // Values from the Java client's own tests
+        assert_eq!(murmur2(b"21"), -973932308);
+        assert_eq!(murmur2(b"foobar"), -790332482);
+        assert_eq!(murmur2(b"a-little-bit-long-string"), -985981536);
+        assert_eq!(murmur2(b"a-little-bit-longer-string"), -1486304829);
+        assert_eq!(murmur2(b"lkjh234lh9fiuh90y23oiuhsafujhadof229phr9h19h89h8"), -58897971);
+        assert_eq!(murmur2(b"abc"), 479470107);
+    }
+
+    #[test]
+    fn test_murmur2_partitioner() {
+        let mut partitioner = Murmur2Partitioner;
+        assert_eq!(partitioner.partition(Some(b"foobar"), 12), (-790332482i32 & 0x7fffffff) % 12);
+        assert_eq!(partitioner.partition(None, 12), 0);
+    }
+
+    #[test]
+    fn test_round_robin_partitioner() {
+        let mut partitioner = RoundRobinPartitioner::new(&mut XorShiftRng::new_unseeded());
+        let first = partitioner.partition(None, 3);
+        assert_eq!(partitioner.partition(None, 3), (first + 1) % 3);
+        assert_eq!(partitioner.partition(None, 3), (first + 2) % 3);
+        assert_eq!(partitioner.partition(Some(b"foobar"), 12), (-790332482i32 & 0x7fffffff) % 12);
+    }
+
+    #[test]
+    fn test_round_robin_skips_unavailable() {
+        let mut partitioner = RoundRobinPartitioner::new(&mut XorShiftRng::from_seed([1, 2, 3, 4]));
+        let mut chosen: Vec<i32> = range(0u, 4).map(|_| partitioner.partition_among("events", None, 4, &[1, 3])).collect();
+        chosen.sort();
+        assert_eq!(chosen, vec![1, 1, 3, 3]);
+
+        // Keyed records ignore availability
+        assert_eq!(partitioner.partition_among("events", Some(b"foobar"), 12, &[0]), (-790332482i32 & 0x7fffffff) % 12);
+    }
+
+    #[test]
+    fn test_sticky_partitioner() {
+        let mut partitioner = StickyPartitioner::new(SeedableRng::from_seed([1, 2, 3, 4]));
+        let first = partitioner.partition_among("events", None, 4, &[0, 1, 2, 3]);
+        for _ in range(0u, 10) {
+            assert_eq!(partitioner.partition_among("events", None, 4, &[0, 1, 2, 3]), first);
+        }
+
+        partitioner.on_new_batch("events", first);
+        let second = partitioner.partition_among("events", None, 4, &[0, 1, 2, 3]);
+        assert!(second != first);
+
+        // Moves on when the partition loses its leader
+        let available: Vec<i32> = range(0, 4).filter(|&p| p != second).collect();
+        assert!(partitioner.partition_among("events", None, 4, available.as_slice()) != second);
+
+        assert_eq!(partitioner.partition_among("events", Some(b"foobar"), 12, &[0]), (-790332482i32 & 0x7fffffff) % 12);
+    }
+}
@@ -0,0 +1,1041 @@
+use std::cmp;
+use std::collections::{HashMap, RingBuf};
+use std::io::timer;
+use std::mem;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUint, SeqCst};
+use std::time::Duration;
+
+use time;
+
+use cancel::CancellationToken;
+use client::KafkaClient;
+use partitioner::{Partitioner, RoundRobinPartitioner};
+use protocol::*;
+use routing::Router;
+use types::*;
+
+/// Which replicas must have written a batch before it counts as sent.
+#[deriving(Show, PartialEq, Eq, Clone)]
+pub enum RequiredAcks {
+    /// acks=0: the broker sends no response at all, so errors go unnoticed
+    /// and the offsets of records aren't known.
+    AcksNone,
+    /// acks=1: the leader has written the batch.
+    AcksLeader,
+    /// acks=all: every in-sync replica has written the batch. Combined with
+    /// the topic's `min.insync.replicas`, this survives losing the leader.
+    AcksAll
+}
+
+impl RequiredAcks {
+    /// The value sent in produce requests.
+    pub fn as_i16(&self) -> i16 {
+        match *self {
+            AcksNone => 0,
+            AcksLeader => 1,
+            AcksAll => -1
+        }
+    }
+}
+
+/// The offset reported for records sent with `AcksNone`.
+pub const UNKNOWN_OFFSET: i64 = -1;
+
+/// The partition count keyed records are hashed over. Adding partitions to a
+/// topic moves most keys to other partitions, breaking applications which
+/// rely on a key being on the same partition number as in other topics.
+#[deriving(Show, PartialEq, Eq, Clone)]
+pub enum PartitionCountPin {
+    /// Hash over the topic's current partition count.
+    PinNone,
+    /// Hash over the count when the producer first sent a keyed record to
+    /// the topic.
+    PinAtFirstUse,
+    /// Hash over the given count for each topic listed, and over the count
+    /// at first use for others. Sending a keyed record fails with
+    /// `ConfigError(PartitionCountPinExceeded)` while a topic has fewer
+    /// partitions than its pin.
+    PinTo(Vec<(String, i32)>)
+}
+
+/// Which produce requests are decoded again after encoding and checked
+/// before they're sent, to catch a serialization bug before it writes
+/// corrupt messages to a topic.
+#[deriving(Show, PartialEq, Eq, Clone)]
+pub enum BatchVerification {
+    VerifyNone,
+    /// One request in every so many.
+    VerifySampled(uint),
+    VerifyAll
+}
+
+/// Told when a topic has more partitions than the count its keyed records
+/// are pinned to. Keyed records keep going to the pinned partitions.
+pub trait PartitionGrowthListener {
+    fn on_partition_growth(&mut self, topic: &str, pinned: i32, current: i32);
+}
+
+#[deriving(Show, Clone)]
+pub struct ProducerConfig {
+    pub required_acks: RequiredAcks,
+    /// How long in ms the broker may wait for those acknowledgements.
+    pub ack_timeout: i32,
+    /// Check records against the topic's configuration before sending,
+    /// rejecting oversized messages and tombstones for uncompacted topics.
+    /// Needs brokers supporting DescribeConfigs (0.11 and later).
+    pub check_topic_config: bool,
+    /// Bytes of messages a `BatchingProducer` collects for a partition
+    /// before sending them.
+    pub batch_size: uint,
+    /// How long a `BatchingProducer` waits for a batch to fill before
+    /// sending it anyway.
+    pub linger: Duration,
+    /// How many times a batch is sent again after a retriable error other
+    /// than leadership moving, such as the broker timing out waiting for
+    /// replicas. A timed out batch may already have been written, so retries
+    /// can duplicate records. Leadership moves are retried by the client, up
+    /// to its `max_routing_retries`.
+    pub retries: uint,
+    /// Pause before each retry.
+    pub retry_backoff: Duration,
+    /// Batches a `BatchingProducer` may have unacknowledged for any one
+    /// partition; sending more waits for one to finish. Batches are sent in
+    /// the order they were filled, but with retries only a limit of 1
+    /// guarantees records are written in the order sent.
+    pub max_in_flight: uint,
+    /// Defaults to `PinNone`.
+    pub partition_count_pin: PartitionCountPin,
+    /// Defaults to `VerifyNone`. A request failing the check isn't sent, and
+    /// the send fails with `MalformedRequestError`.
+    pub verify_batches: BatchVerification,
+    /// How long dropping a `BatchingProducer` waits for queued records to be
+    /// sent before discarding them. Failed batches aren't retried after it,
+    /// but a request already sent is waited for until the connection's
+    /// `read_timeout`, so dropping can take up to the sum of the two.
+    pub close_timeout: Duration
+}
+
+impl ProducerConfig {
+    pub fn new() -> ProducerConfig {
+        ProducerConfig {
+            required_acks: AcksLeader,
+            ack_timeout: 1500,
+            check_topic_config: false,
+            batch_size: 16384,
+            linger: Duration::milliseconds(5),
+            retries: 3,
+            retry_backoff: Duration::milliseconds(100),
+            max_in_flight: 5,
+            partition_count_pin: PinNone,
+            verify_batches: VerifyNone,
+            close_timeout: Duration::seconds(5)
+        }
+    }
+}
+
+#[deriving(Show, PartialEq, Eq, Clone)]
+pub struct ProducerRecord {
+    pub topic: String,
+    /// Chosen by the producer's `Partitioner` when not given.
+    pub partition: Option<i32>,
+    pub key: Option<Vec<u8>>,
+    pub value: Option<Vec<u8>>,
+    /// Named values for a `Router` to pick the record's destination by. The
+    /// message format spoken here predates headers, so they aren't sent.
+    pub headers: Vec<(String, Vec<u8>)>
+}
+
+impl ProducerRecord {
+    pub fn new(topic: &str, key: Option<Vec<u8>>, value: Option<Vec<u8>>) -> ProducerRecord {
+        ProducerRecord {
+            topic: String::from_str(topic),
+            partition: None,
+            key: key,
+            value: value,
+            headers: vec![]
+        }
+    }
+
+    pub fn with_header(mut self, name: &str, value: Vec<u8>) -> ProducerRecord {
+        self.headers.push((String::from_str(name), value));
+        self
+    }
+
+    /// The value of the first header called `name`.
+    pub fn header(&self, name: &str) -> Option<&[u8]> {
+        self.headers.iter().find(|&&(ref header, _)| header.as_slice() == name).map(|&(_, ref value)| value.as_slice())
+    }
+}
+
+/// Writes records to the leaders of their partitions.
+pub struct Producer {
+    client: KafkaClient,
+    config: ProducerConfig,
+    partitioner: Mutex<Box<Partitioner + Send>>,
+    router: Option<Router>,
+    // The count each topic's keys are hashed over, and the count last seen
+    pinned_counts: Mutex<HashMap<String, (i32, i32)>>,
+    growth_listener: Mutex<Option<Box<PartitionGrowthListener + Send>>>,
+    // Produce requests built, for sampling which to verify
+    requests_built: AtomicUint
+}
+
+impl Producer {
+    pub fn new(client: KafkaClient) -> Producer {
+        Producer::with_config(client, ProducerConfig::new())
+    }
+
+    pub fn with_config(client: KafkaClient, config: ProducerConfig) -> Producer {
+        let partitioner = box RoundRobinPartitioner::new(&mut client.new_rng());
+        Producer::with_partitioner(client, config, partitioner)
+    }
+
+    pub fn with_partitioner(client: KafkaClient, config: ProducerConfig,
+                            partitioner: Box<Partitioner + Send>) -> Producer {
+        Producer {
+            client: client,
+            config: config,
+            partitioner: Mutex::new(partitioner),
+            router: None,
+            pinned_counts: Mutex::new(HashMap::new()),
+            growth_listener: Mutex::new(None),
+            requests_built: AtomicUint::new(0)
+        }
+    }
+
+    /// Routes every record sent by its headers before partitioning it.
+    pub fn set_router(&mut self, router: Router) {
+        self.router = Some(router);
+    }
+
+    /// Reports topics growing past their pinned partition count, see
+    /// `ProducerConfig::partition_count_pin`.
+    pub fn set_growth_listener(&mut self, listener: Box<PartitionGrowthListener + Send>) {
+        *self.growth_listener.lock() = Some(listener);
+    }
+
+    /// Sends a record and waits for it to be acknowledged, returning where it
+    /// was written. With `AcksNone` this returns once the record is written to
+    /// the connection, with an offset of `UNKNOWN_OFFSET`.
+    pub fn send(&self, record: ProducerRecord) -> KafkaResult<(TopicPartition, i64)> {
+        let (partition, message) = try!(self.prepare(record));
+        let offset = try!(self.send_messages(&partition, vec![message], None));
+        Ok((partition, offset))
+    }
+
+    /// Deletes `key` from a compacted topic by writing a tombstone, a record
+    /// with the key and no value. With a keyed partitioner the tombstone goes
+    /// to the same partition as the values it removes; compaction then drops
+    /// those values, and after `delete.retention.ms` the tombstone too.
+    ///
+    /// Only compacted topics (`cleanup.policy=compact`) treat tombstones
+    /// specially. Anywhere else this just appends a record with a null value,
+    /// so with `check_topic_config` set such topics are refused.
+    pub fn delete_key(&self, topic: &str, key: Vec<u8>) -> KafkaResult<(TopicPartition, i64)> {
+        if self.config.check_topic_config && !try!(self.client.topic_config(topic)).is_compacted() {
+            fail!((NotCompacted, "Tombstones can only delete keys from compacted topics"));
+        }
+        self.send(ProducerRecord::new(topic, Some(key), None))
+    }
+
+    // Partitions a record and turns it into a message the topic will accept
+    fn prepare(&self, record: ProducerRecord) -> KafkaResult<(TopicPartition, Message)> {
+        let record = match self.router {
+            Some(ref router) => router.route(record),
+            None => record
+        };
+        let partition = TopicPartition {
+            partition: try!(self.partition_for(&record)),
+            topic: record.topic
+        };
+        let message = try!(Message::new(record.key, record.value));
+        if self.config.check_topic_config {
+            let config = try!(self.client.topic_config(partition.topic.as_slice()));
+            // The broker's limit covers the offset and size that precede the message
+            let size = 12 + try!(message.size());
+            match config.max_message_bytes {
+                Some(max) if size > max => fail!((MalformedRequestError, "Message is larger than the topic's max.message.bytes")),
+                _ => ()
+            }
+        }
+        Ok((partition, message))
+    }
+
+    // Returns the offset of the first message. No retry is started once past
+    // the deadline, in `precise_time_ns`.
+    fn send_messages(&self, partition: &TopicPartition, messages: Vec<Message>,
+                     deadline: Option<u64>) -> KafkaResult<i64> {
+        let request = try!(produce_request(&self.config, partition, messages));
+        let built = self.requests_built.fetch_add(1, SeqCst);
+        let verify = match self.config.verify_batches {
+            VerifyNone => false,
+            VerifySampled(every) => built % cmp::max(every, 1) == 0,
+            VerifyAll => true
+        };
+        if verify {
+            try!(verify_request(&request));
+        }
+        let mut attempt = 0;
+        loop {
+            let result = self.send_request(partition, request.clone());
+            match result {
+                // send_to_leader has already retried routing errors
+                Err(ref err) if err.is_retriable() && !err.is_routing_error() && attempt < self.config.retries
+                        && !past(deadline) => (),
+                _ => return result
+            }
+
+            attempt += 1;
+            timer::sleep(self.config.retry_backoff);
+            // The leader may have moved even if the broker didn't say so
+            try!(self.client.load_metadata(vec![partition.topic.clone()]));
+        }
+    }
+
+    fn send_request(&self, partition: &TopicPartition, request: ProduceRequest) -> KafkaResult<i64> {
+        let acknowledged = request.expects_response();
+        self.client.send_to_leader(partition, request, |response: ProduceResponse| {
+            if !acknowledged {
+                return Ok(UNKNOWN_OFFSET);
+            }
+            match response.results().find(|&(ref result_partition, _)| result_partition == partition) {
+                Some((_, result)) => Ok(try!(result)),
+                None => fail!((MalformedResponseError, "Produce response is missing the requested partition"))
+            }
+        })
+    }
+
+    fn partition_for(&self, record: &ProducerRecord) -> KafkaResult<i32> {
+        match record.partition {
+            Some(partition) => return Ok(partition),
+            None => ()
+        }
+
+        let cluster = match self.client.cluster() {
+            Some(ref cluster) if cluster.partitions_for(record.topic.as_slice()).is_some() => cluster.clone(),
+            _ => {
+                try!(self.client.load_metadata(vec![record.topic.clone()]));
+                match self.client.cluster() {
+                    Some(cluster) => cluster,
+                    None => fail!(UnknownTopicOrPartition)
+                }
+            }
+        };
+        let (count, available) = match cluster.partitions_for(record.topic.as_slice()) {
+            Some(partitions) => {
+                let available: Vec<i32> = partitions.iter()
+                    .map(|metadata| metadata.partition)
+                    .filter(|&partition| cluster.leader_for(&TopicPartition::new(record.topic.as_slice(), partition)).is_some())
+                    .collect();
+                (partitions.len(), available)
+            },
+            None => fail!(UnknownTopicOrPartition)
+        };
+        if count == 0 {
+            fail!(LeaderNotAvailable);
+        }
+        let key = record.key.as_ref().map(|key| key.as_slice());
+        let count = match key {
+            Some(_) => try!(self.pinned_count(record.topic.as_slice(), count as i32)),
+            None => count as i32
+        };
+        let partition = self.partitioner.lock().partition_among(record.topic.as_slice(), key, count,
+                                                                available.as_slice());
+        if partition < 0 || partition >= count {
+            fail!((PartitionerOutOfRange, "Partitioner chose a partition the topic doesn't have"));
+        }
+        Ok(partition)
+    }
+
+    // The count to hash keys over given the topic's current count, telling
+    // the growth listener the first time the topic is seen to have grown
+    fn pinned_count(&self, topic: &str, current: i32) -> KafkaResult<i32> {
+        let (pinned, grew) = try!(pin_count(&mut *self.pinned_counts.lock(), &self.config.partition_count_pin,
+                                            topic, current));
+        if grew {
+            match *self.growth_listener.lock() {
+                Some(ref mut listener) => listener.on_partition_growth(topic, pinned, current),
+                None => ()
+            }
+        }
+        Ok(pinned)
+    }
+}
+
+// Returns the count to hash over, and whether the topic has newly grown past
+// it. A pin above the current count would hash keys to partitions that don't
+// exist.
+fn pin_count(pins: &mut HashMap<String, (i32, i32)>, pin: &PartitionCountPin, topic: &str,
+             current: i32) -> KafkaResult<(i32, bool)> {
+    let configured = match *pin {
+        PinNone => return Ok((current, false)),
+        PinAtFirstUse => None,
+        PinTo(ref counts) => counts.iter().find(|&&(ref name, _)| name.as_slice() == topic).map(|&(_, count)| count)
+    };
+    let topic = String::from_str(topic);
+    let (pinned, last_seen) = match pins.get(&topic) {
+        Some(&counts) => counts,
+        None => {
+            let pinned = configured.unwrap_or(current);
+            (pinned, pinned)
+        }
+    };
+    if pinned > current {
+        fail!((PartitionCountPinExceeded, "Partition count pin is above the topic's partition count"));
+    }
+    pins.insert(topic, (pinned, current));
+    Ok((pinned, current > pinned && current != last_seen))
+}
+
+// Checks the request decodes to itself and that every message's CRC matches
+// its contents, as the broker will check them
+fn verify_request(request: &ProduceRequest) -> KafkaResult<()> {
+    let decoded: ProduceRequest = try!(decode_from_slice(try!(encode_to_vec(request)).as_slice()));
+    if decoded != *request {
+        fail!((MalformedRequestError, "Produce request doesn't decode to what was encoded"));
+    }
+    for topic in decoded.topics.iter() {
+        for partition in topic.partitions.iter() {
+            let WithSize(ref message_set) = partition.message_set;
+            for element in message_set.messages.iter() {
+                let WithSize(ref message) = element.message;
+                if message.crc != try!(message.compute_crc()) {
+                    fail!((MalformedRequestError, "Message CRC doesn't match its contents"));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn produce_request(config: &ProducerConfig, partition: &TopicPartition,
+                   messages: Vec<Message>) -> KafkaResult<ProduceRequest> {
+    let mut builder = MessageSetBuilder::new();
+    for message in messages.into_iter() {
+        try!(builder.append_message(message));
+    }
+
+    Ok(ProduceRequest {
+        required_acks: config.required_acks.as_i16(),
+        timeout: config.ack_timeout,
+        topics: vec![ProduceRequestTopic {
+            name: partition.topic.clone(),
+            partitions: vec![ProduceRequestPartition {
+                partition: partition.partition,
+                message_set: WithSize(builder.build())
+            }]
+        }]
+    })
+}
+
+/// Where a record was written, or why it couldn't be.
+pub type Delivery = KafkaResult<(TopicPartition, i64)>;
+
+/// Resolves once the batch holding a record has been acknowledged.
+pub struct DeliveryHandle {
+    receiver: Receiver<Delivery>
+}
+
+impl DeliveryHandle {
+    /// Blocks until the record's fate is known.
+    pub fn wait(self) -> Delivery {
+        match self.receiver.recv_opt() {
+            Ok(delivery) => delivery,
+            Err(()) => fail!((CancelledError, "The record was discarded before being sent"))
+        }
+    }
+
+    /// The record's fate, if already known.
+    pub fn poll(&self) -> Option<Delivery> {
+        self.receiver.try_recv().ok()
+    }
+}
+
+struct Batch {
+    messages: Vec<Message>,
+    // One per message, in the same order
+    deliveries: Vec<Sender<Delivery>>,
+    size: uint,
+    started_at: u64
+}
+
+impl Batch {
+    // Resolves every record's handle, ignoring those nobody kept
+    fn deliver(self, partition: &TopicPartition, result: KafkaResult<i64>) {
+        for (i, delivery) in self.deliveries.into_iter().enumerate() {
+            let _ = delivery.send_opt(match result {
+                Ok(UNKNOWN_OFFSET) => Ok((partition.clone(), UNKNOWN_OFFSET)),
+                Ok(offset) => Ok((partition.clone(), offset + i as i64)),
+                Err(ref err) => Err(err.clone())
+            });
+        }
+    }
+}
+
+// Records waiting to be sent, by partition
+struct Accumulator {
+    batches: HashMap<TopicPartition, Batch>,
+    // Batches taken out to be sent, oldest first, until one of the
+    // partition's in-flight slots is free to send them in
+    ready: HashMap<TopicPartition, RingBuf<Batch>>,
+    // Batches taken out to be sent which haven't been acknowledged yet
+    sending: uint,
+    // The same, by topic
+    sending_by_topic: HashMap<String, uint>,
+    // Of those, the ones actually sent, by partition
+    in_flight: HashMap<TopicPartition, uint>,
+    closed: bool
+}
+
+impl Accumulator {
+    fn new() -> Accumulator {
+        Accumulator {
+            batches: HashMap::new(),
+            ready: HashMap::new(),
+            sending: 0,
+            sending_by_topic: HashMap::new(),
+            in_flight: HashMap::new(),
+            closed: false
+        }
+    }
+
+    // Queues a partition's batch to be sent, counting it until `finish`.
+    // Returns whether there was one.
+    fn take(&mut self, partition: &TopicPartition) -> bool {
+        let batch = match self.batches.remove(partition) {
+            Some(batch) => batch,
+            None => return false
+        };
+        if !self.ready.contains_key(partition) {
+            self.ready.insert(partition.clone(), RingBuf::new());
+        }
+        self.ready.get_mut(partition).unwrap().push_back(batch);
+
+        self.sending += 1;
+        let count = self.sending_by_topic.get(&partition.topic).map_or(0, |&count| count);
+        self.sending_by_topic.insert(partition.topic.clone(), count + 1);
+        true
+    }
+
+    fn has_ready(&self, partition: &TopicPartition) -> bool {
+        self.ready.get(partition).map_or(false, |queue| !queue.is_empty())
+    }
+
+    // The partition's oldest queued batch, for the holder of an in-flight slot
+    fn pop_ready(&mut self, partition: &TopicPartition) -> Option<Batch> {
+        let (batch, empty) = match self.ready.get_mut(partition) {
+            Some(queue) => (queue.pop_front(), queue.is_empty()),
+            None => return None
+        };
+        if empty {
+            self.ready.remove(partition);
+        }
+        batch
+    }
+
+    // Drops the partition's queued batches, which tells their handles the
+    // records were discarded
+    fn discard_ready(&mut self, partition: &TopicPartition) {
+        let discarded = self.ready.remove(partition).map_or(0, |queue| queue.len());
+        for _ in range(0, discarded) {
+            self.finish(partition);
+        }
+    }
+
+    // Called once a batch taken out has been acknowledged or given up on
+    fn finish(&mut self, partition: &TopicPartition) {
+        self.sending -= 1;
+        let remaining = match self.sending_by_topic.get_mut(&partition.topic) {
+            Some(count) => {
+                *count -= 1;
+                *count
+            },
+            None => return
+        };
+        if remaining == 0 {
+            self.sending_by_topic.remove(&partition.topic);
+        }
+    }
+
+    // Batches of the given topics taken out but not yet finished
+    fn sending_for(&self, topics: &[&str]) -> uint {
+        self.sending_by_topic.iter()
+            .filter(|&(topic, _)| topics.contains(&topic.as_slice()))
+            .fold(0, |total, (_, &count)| total + count)
+    }
+
+    // Takes one of the partition's in-flight slots, if any are free
+    fn try_acquire(&mut self, partition: &TopicPartition, max_in_flight: uint) -> bool {
+        let count = self.in_flight.get(partition).map_or(0, |&count| count);
+        if count >= cmp::max(max_in_flight, 1) {
+            return false;
+        }
+        self.in_flight.insert(partition.clone(), count + 1);
+        true
+    }
+
+    fn release(&mut self, partition: &TopicPartition) {
+        let remaining = match self.in_flight.get_mut(partition) {
+            Some(count) => {
+                *count -= 1;
+                *count
+            },
+            None => return
+        };
+        if remaining == 0 {
+            self.in_flight.remove(partition);
+        }
+    }
+
+    // Queues the partition's batch to be sent once it has reached
+    // `batch_size`, returning whether it did
+    fn append(&mut self, partition: &TopicPartition, message: Message, delivery: Sender<Delivery>,
+              batch_size: uint, now: u64) -> bool {
+        let size = 12 + message.size().unwrap_or(0) as uint;
+        let full = {
+            if !self.batches.contains_key(partition) {
+                self.batches.insert(partition.clone(), Batch {
+                    messages: vec![],
+                    deliveries: vec![],
+                    size: 0,
+                    started_at: now
+                });
+            }
+            let batch = self.batches.get_mut(partition).unwrap();
+            batch.messages.push(message);
+            batch.deliveries.push(delivery);
+            batch.size += size;
+            batch.size >= batch_size
+        };
+
+        full && self.take(partition)
+    }
+
+    // Queues the batches that have waited at least `linger_ns` to be sent,
+    // returning their partitions
+    fn drain_expired(&mut self, now: u64, linger_ns: u64) -> Vec<TopicPartition> {
+        let expired: Vec<TopicPartition> = self.batches.iter()
+            .filter(|&(_, batch)| now - batch.started_at >= linger_ns)
+            .map(|(partition, _)| partition.clone())
+            .collect();
+        self.take_all(expired)
+    }
+
+    fn drain_all(&mut self) -> Vec<TopicPartition> {
+        let partitions: Vec<TopicPartition> = self.batches.keys().map(|partition| partition.clone()).collect();
+        self.take_all(partitions)
+    }
+
+    fn drain_topics(&mut self, topics: &[&str]) -> Vec<TopicPartition> {
+        let partitions: Vec<TopicPartition> = self.batches.keys()
+            .filter(|partition| topics.contains(&partition.topic.as_slice()))
+            .map(|partition| partition.clone())
+            .collect();
+        self.take_all(partitions)
+    }
+
+    fn take_all(&mut self, partitions: Vec<TopicPartition>) -> Vec<TopicPartition> {
+        partitions.into_iter().filter(|partition| self.take(partition)).collect()
+    }
+}
+
+/// A producer which collects records into per-partition batches, sending a
+/// batch once it holds `batch_size` bytes or has waited for `linger`, so many
+/// small records share one request.
+///
+/// Sending a record only queues it, returning a `DeliveryHandle` for finding
+/// out where it was written. Batches that fill up are sent by the call which
+/// filled them, and lingering ones from a background task started with the
+/// client's `Spawner`. Dropping the producer flushes it, for up to
+/// `close_timeout` plus the connection's `read_timeout` for a request
+/// already sent then.
+pub struct BatchingProducer {
+    producer: Arc<Producer>,
+    accumulator: Arc<Mutex<Accumulator>>
+}
+
+impl BatchingProducer {
+    pub fn new(client: KafkaClient, config: ProducerConfig) -> BatchingProducer {
+        BatchingProducer::from_producer(Producer::with_config(client, config))
+    }
+
+    pub fn from_producer(producer: Producer) -> BatchingProducer {
+        let producer = Arc::new(producer);
+        let accumulator = Arc::new(Mutex::new(Accumulator::new()));
+
+        let (task_producer, task_accumulator) = (producer.clone(), accumulator.clone());
+        producer.client.spawn(proc() linger(task_producer, task_accumulator));
+
+        BatchingProducer { producer: producer, accumulator: accumulator }
+    }
+
+    /// Queues a record, sending its partition's batch if this fills it.
+    /// Errors in sending the batch are reported through the handle.
+    pub fn send(&self, record: ProducerRecord) -> KafkaResult<DeliveryHandle> {
+        let (partition, message) = try!(self.producer.prepare(record));
+        let (tx, rx) = channel();
+        let full = self.accumulator.lock().append(&partition, message, tx, self.producer.config.batch_size,
+                                                  time::precise_time_ns());
+        if full {
+            self.producer.partitioner.lock().on_new_batch(partition.topic.as_slice(), partition.partition);
+            send_ready(&*self.producer, &*self.accumulator, &partition, None);
+        }
+        Ok(DeliveryHandle { receiver: rx })
+    }
+
+    /// Sends every queued record, and blocks until they and any batches
+    /// already being sent have been acknowledged. Errors are reported through
+    /// each record's handle.
+    pub fn flush(&self) {
+        self.flush_until(None);
+    }
+
+    /// Sends the queued records of `topics` and waits until every record of
+    /// theirs accepted before the call has been acknowledged or has failed,
+    /// as a batch job needs before checkpointing its own progress. Fails with
+    /// a `TimeoutError` if that takes longer than `timeout`; the records are
+    /// still sent, and their handles say how that went.
+    ///
+    /// Batches of these topics accepted during the call are waited for too,
+    /// so records sent continuously from other threads can hold it up.
+    pub fn flush_barrier(&self, topics: &[&str], timeout: Duration) -> KafkaResult<()> {
+        let deadline = time::precise_time_ns() + timeout.num_milliseconds() as u64 * 1000000;
+
+        let partitions = self.accumulator.lock().drain_topics(topics);
+        for partition in partitions.iter() {
+            send_ready(&*self.producer, &*self.accumulator, partition, None);
+        }
+
+        while self.accumulator.lock().sending_for(topics) > 0 {
+            if time::precise_time_ns() >= deadline {
+                fail!((TimeoutError, "Records were still being sent at the flush barrier's deadline"));
+            }
+            timer::sleep(Duration::milliseconds(1));
+        }
+        Ok(())
+    }
+
+    /// Like `flush`, but fails with a `CancelledError` soon after `token` is
+    /// cancelled. Queued batches not yet sent by then are discarded, which
+    /// their handles report; those already sent are still acknowledged.
+    pub fn flush_cancellable(&self, token: &CancellationToken) -> KafkaResult<()> {
+        let partitions = self.accumulator.lock().drain_all();
+        for partition in partitions.iter() {
+            if token.is_cancelled() {
+                self.accumulator.lock().discard_ready(partition);
+            } else {
+                send_ready(&*self.producer, &*self.accumulator, partition, None);
+            }
+        }
+
+        while self.accumulator.lock().sending > 0 {
+            try!(token.check());
+            timer::sleep(Duration::milliseconds(1));
+        }
+        token.check()
+    }
+
+    // Batches still unsent at the deadline are discarded, and failed ones
+    // aren't retried past it, but a request already sent then is waited for
+    // until its read timeout. Returns whether everything was acknowledged.
+    fn flush_until(&self, deadline: Option<u64>) -> bool {
+        let partitions = self.accumulator.lock().drain_all();
+        for partition in partitions.iter() {
+            send_ready(&*self.producer, &*self.accumulator, partition, deadline);
+        }
+
+        // Wait for batches other threads are sending
+        while self.accumulator.lock().sending > 0 {
+            if past(deadline) {
+                return false;
+            }
+            timer::sleep(Duration::milliseconds(1));
+        }
+        true
+    }
+}
+
+// Sends the partition's queued batches until none are left. Each is sent by
+// whichever caller gets one of the partition's in-flight slots, taking the
+// oldest batch with it, so batches beyond `max_in_flight` wait their turn and
+// are sent in the order they were queued. Those still queued once past the
+// deadline are discarded.
+fn send_ready(producer: &Producer, accumulator: &Mutex<Accumulator>, partition: &TopicPartition,
+              deadline: Option<u64>) {
+    loop {
+        let next = {
+            let mut accumulator = accumulator.lock();
+            if !accumulator.has_ready(partition) {
+                return;
+            }
+            if past(deadline) {
+                accumulator.discard_ready(partition);
+                return;
+            }
+            if accumulator.try_acquire(partition, producer.config.max_in_flight) {
+                accumulator.pop_ready(partition)
+            } else {
+                None
+            }
+        };
+
+        let mut batch = match next {
+            Some(batch) => batch,
+            None => {
+                timer::sleep(Duration::milliseconds(1));
+                continue;
+            }
+        };
+        let messages = mem::replace(&mut batch.messages, vec![]);
+        let result = producer.send_messages(partition, messages, deadline);
+        batch.deliver(partition, result);
+
+        let mut accumulator = accumulator.lock();
+        accumulator.release(partition);
+        accumulator.finish(partition);
+    }
+}
+
+impl Drop for BatchingProducer {
+    fn drop(&mut self) {
+        self.accumulator.lock().closed = true;
+        let timeout = self.producer.config.close_timeout.num_milliseconds() as u64 * 1000000;
+        self.flush_until(Some(time::precise_time_ns() + timeout));
+    }
+}
+
+// Sends batches which have lingered long enough, until the producer is dropped
+fn linger(producer: Arc<Producer>, accumulator: Arc<Mutex<Accumulator>>) {
+    let linger = producer.config.linger;
+    let linger_ns = linger.num_milliseconds() as u64 * 1000000;
+    // Check often enough that no batch waits much more than `linger`
+    let tick = Duration::milliseconds(cmp::max(linger.num_milliseconds() / 2, 1));
+
+    loop {
+        timer::sleep(tick);
+        let expired = {
+            let mut accumulator = accumulator.lock();
+            if accumulator.closed {
+                return;
+            }
+            accumulator.drain_expired(time::precise_time_ns(), linger_ns)
+        };
+
+        for partition in expired.iter() {
+            send_ready(&*producer, &*accumulator, partition, None);
+        }
+    }
+}
+
+fn past(deadline: Option<u64>) -> bool {
+    deadline.map_or(false, |deadline| time::precise_time_ns() >= deadline)
+}
+
+#[cfg(test)]
+mod tests {
+    use protocol::*;
+    use types::*;
+    use super::*;
+    use std::error::FromError;
+    use super::{produce_request, pin_count, verify_request, Accumulator, Batch};
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_verify_request() {
+        let message = Message::new(Some(b"key".to_vec()), Some(b"value".to_vec())).ok().unwrap();
+        let mut request = produce_request(&ProducerConfig::new(), &TopicPartition::new("events", 0),
+                                          vec![message]).ok().unwrap();
+        assert!(verify_request(&request).is_ok());
+
+        {
+            let WithSize(ref mut message_set) = request.topics.as_mut_slice()[0].partitions.as_mut_slice()[0].message_set;
+            let WithSize(ref mut message) = message_set.messages.as_mut_slice()[0].message;
+            message.value = Some(b"corrupt".to_vec());
+        }
+        assert!(verify_request(&request).is_err());
+    }
+
+    #[test]
+    fn test_pin_count() {
+        let mut pins = HashMap::new();
+        assert_eq!(pin_count(&mut pins, &PinNone, "events", 4).ok().unwrap(), (4, false));
+        assert_eq!(pin_count(&mut pins, &PinNone, "events", 8).ok().unwrap(), (8, false));
+
+        assert_eq!(pin_count(&mut pins, &PinAtFirstUse, "events", 4).ok().unwrap(), (4, false));
+        assert_eq!(pin_count(&mut pins, &PinAtFirstUse, "events", 8).ok().unwrap(), (4, true));
+        // Only reported once per change
+        assert_eq!(pin_count(&mut pins, &PinAtFirstUse, "events", 8).ok().unwrap(), (4, false));
+        assert_eq!(pin_count(&mut pins, &PinAtFirstUse, "events", 12).ok().unwrap(), (4, true));
+
+        let pin = PinTo(vec![(String::from_str("users"), 6), (String::from_str("small"), 4)]);
+        assert_eq!(pin_count(&mut pins, &pin, "users", 12).ok().unwrap(), (6, true));
+        assert_eq!(pin_count(&mut pins, &pin, "orders", 3).ok().unwrap(), (3, false));
+
+        assert_eq!(pin_count(&mut pins, &pin, "small", 2).unwrap_err().kind, ConfigError(PartitionCountPinExceeded));
+        assert!(pins.get(&String::from_str("small")).is_none());
+    }
+
+    #[test]
+    fn test_tombstone_request() {
+        let message = Message::new(Some(b"user-1".to_vec()), None).ok().unwrap();
+        let request = produce_request(&ProducerConfig::new(), &TopicPartition::new("users", 3),
+                                      vec![message.clone()]).ok().unwrap();
+        assert_eq!(request.required_acks, 1);
+        assert_eq!(request.topics[0].partitions[0].partition, 3);
+
+        let WithSize(ref message_set) = request.topics[0].partitions[0].message_set;
+        let WithSize(ref sent) = message_set.messages[0].message;
+        assert_eq!(*sent, message);
+        assert!(sent.value.is_none());
+    }
+
+    #[test]
+    fn test_required_acks() {
+        let config = ProducerConfig { required_acks: AcksNone, ..ProducerConfig::new() };
+        let request = produce_request(&config, &TopicPartition::new("events", 0), vec![]).ok().unwrap();
+        assert_eq!(request.required_acks, 0);
+        assert!(!request.expects_response());
+        assert_eq!(AcksAll.as_i16(), -1);
+
+        let (sender, receiver) = channel();
+        let batch = Batch { messages: vec![], deliveries: vec![sender.clone(), sender], size: 0, started_at: 0 };
+        batch.deliver(&TopicPartition::new("events", 0), Ok(UNKNOWN_OFFSET));
+        assert_eq!(receiver.recv().ok().unwrap().1, UNKNOWN_OFFSET);
+        assert_eq!(receiver.recv().ok().unwrap().1, UNKNOWN_OFFSET);
+    }
+
+    #[test]
+    fn test_accumulator_batch_size() {
+        let mut accumulator = Accumulator::new();
+        let partition = TopicPartition::new("events", 0);
+        let message = Message::new(None, Some(vec![0u8, ..20])).ok().unwrap();
+        // 12 bytes of offset and size, 14 of message header, 20 of value
+        assert!(!accumulator.append(&partition, message.clone(), channel().0, 100, 0));
+        assert!(!accumulator.append(&partition, message.clone(), channel().0, 100, 1));
+
+        assert!(accumulator.append(&partition, message.clone(), channel().0, 100, 2));
+        let batch = accumulator.pop_ready(&partition).unwrap();
+        assert_eq!(batch.messages.len(), 3);
+        assert_eq!(batch.started_at, 0);
+        assert!(accumulator.batches.is_empty());
+        assert_eq!(accumulator.sending, 1);
+    }
+
+    #[test]
+    fn test_accumulator_linger() {
+        let mut accumulator = Accumulator::new();
+        let message = Message::new(None, Some(vec![1u8])).ok().unwrap();
+        accumulator.append(&TopicPartition::new("events", 0), message.clone(), channel().0, 1000, 100);
+        accumulator.append(&TopicPartition::new("events", 1), message.clone(), channel().0, 1000, 150);
+
+        assert!(accumulator.drain_expired(140, 50).is_empty());
+        let expired = accumulator.drain_expired(160, 50);
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0], TopicPartition::new("events", 0));
+        assert_eq!(accumulator.drain_expired(200, 50)[0], TopicPartition::new("events", 1));
+        assert_eq!(accumulator.sending, 2);
+    }
+
+    #[test]
+    fn test_accumulator_drain_all() {
+        let mut accumulator = Accumulator::new();
+        let message = Message::new(None, Some(vec![1u8])).ok().unwrap();
+        accumulator.append(&TopicPartition::new("events", 0), message.clone(), channel().0, 1000, 100);
+        accumulator.append(&TopicPartition::new("events", 1), message.clone(), channel().0, 1000, 150);
+
+        let mut drained = accumulator.drain_all();
+        drained.sort();
+        assert_eq!(drained, vec![TopicPartition::new("events", 0), TopicPartition::new("events", 1)]);
+        assert_eq!(accumulator.sending, 2);
+        assert!(accumulator.drain_all().is_empty());
+    }
+
+    #[test]
+    fn test_accumulator_by_topic() {
+        let mut accumulator = Accumulator::new();
+        let message = Message::new(None, Some(vec![1u8])).ok().unwrap();
+        accumulator.append(&TopicPartition::new("orders", 0), message.clone(), channel().0, 1000, 100);
+        accumulator.append(&TopicPartition::new("orders", 1), message.clone(), channel().0, 1000, 100);
+        accumulator.append(&TopicPartition::new("audit", 0), message.clone(), channel().0, 1000, 100);
+
+        assert_eq!(accumulator.drain_topics(&["orders"]).len(), 2);
+        assert_eq!(accumulator.sending_for(&["orders"]), 2);
+        assert_eq!(accumulator.sending_for(&["audit"]), 0);
+        assert_eq!(accumulator.batches.len(), 1);
+
+        accumulator.finish(&TopicPartition::new("orders", 1));
+        accumulator.finish(&TopicPartition::new("orders", 0));
+        assert_eq!(accumulator.sending_for(&["orders", "audit"]), 0);
+        assert_eq!(accumulator.sending, 0);
+    }
+
+    #[test]
+    fn test_accumulator_in_flight() {
+        let mut accumulator = Accumulator::new();
+        let (first, second) = (TopicPartition::new("events", 0), TopicPartition::new("events", 1));
+        assert!(accumulator.try_acquire(&first, 1));
+        assert!(!accumulator.try_acquire(&first, 1));
+        assert!(accumulator.try_acquire(&second, 1));
+
+        accumulator.release(&first);
+        assert!(accumulator.try_acquire(&first, 1));
+        assert!(accumulator.try_acquire(&first, 2));
+        assert!(!accumulator.try_acquire(&first, 2));
+    }
+
+    #[test]
+    fn test_accumulator_ready_order() {
+        let mut accumulator = Accumulator::new();
+        let partition = TopicPartition::new("events", 0);
+        for value in range(0u8, 3) {
+            let message = Message::new(None, Some(vec![value])).ok().unwrap();
+            assert!(accumulator.append(&partition, message, channel().0, 1, value as u64));
+        }
+        assert!(accumulator.has_ready(&partition));
+
+        let values: Vec<Option<Vec<u8>>> = range(0u, 3).map(|_| {
+            accumulator.pop_ready(&partition).unwrap().messages[0].value.clone()
+        }).collect();
+        assert_eq!(values, vec![Some(vec![0u8]), Some(vec![1u8]), Some(vec![2u8])]);
+        assert!(!accumulator.has_ready(&partition));
+        assert_eq!(accumulator.sending, 3);
+    }
+
+    #[test]
+    fn test_accumulator_discard_ready() {
+        let mut accumulator = Accumulator::new();
+        let partition = TopicPartition::new("events", 0);
+        let (tx, rx) = channel();
+        accumulator.append(&partition, Message::new(None, Some(vec![1u8])).ok().unwrap(), tx, 1000, 0);
+        accumulator.drain_all();
+
+        accumulator.discard_ready(&partition);
+        assert!(!accumulator.has_ready(&partition));
+        assert_eq!(accumulator.sending, 0);
+        assert!(DeliveryHandle { receiver: rx }.wait().is_err());
+    }
+
+    #[test]
+    fn test_delivery() {
+        let partition = TopicPartition::new("events", 2);
+        let (first_tx, first_rx) = channel();
+        let (second_tx, second_rx) = channel();
+        let (first, second) = (DeliveryHandle { receiver: first_rx }, DeliveryHandle { receiver: second_rx });
+        assert!(first.poll().is_none());
+
+        let batch = Batch { messages: vec![], deliveries: vec![first_tx, second_tx], size: 0, started_at: 0 };
+        batch.deliver(&partition, Ok(40));
+        assert_eq!(first.wait().ok().unwrap(), (partition.clone(), 40));
+        assert_eq!(second.poll().unwrap().ok().unwrap(), (partition.clone(), 41));
+
+        let (tx, rx) = channel();
+        let batch = Batch { messages: vec![], deliveries: vec![tx], size: 0, started_at: 0 };
+        batch.deliver(&partition, Err(FromError::from_error(RequestTimedOut)));
+        assert_eq!(DeliveryHandle { receiver: rx }.wait().unwrap_err().kind, BrokerError(RequestTimedOut));
+
+        let (tx, rx) = channel::<Delivery>();
+        drop(tx);
+        assert_eq!(DeliveryHandle { receiver: rx }.wait().unwrap_err().kind, CancelledError);
+    }
+}
@@ -0,0 +1,63 @@
+//! AlterConfigs requests, for changing topic and broker configuration.
+
+use types::*;
+use versions;
+use super::{KafkaSerializable, HeapSize, BufferEncoder, Request, Response, add_size};
+
+kafka_datastructures! (
+    struct AlterConfigsEntry {
+        config_name: String,
+        config_value: Option<String>
+    }
+
+    struct AlterConfigsResource {
+        resource_type: i8,
+        resource_name: String,
+        config_entries: Vec<AlterConfigsEntry>
+    }
+
+    struct AlterConfigsRequest {
+        resources: Vec<AlterConfigsResource>,
+        validate_only: i8
+    }
+
+    struct AlterConfigsResult {
+        error_code: i16,
+        error_message: Option<String>,
+        resource_type: i8,
+        resource_name: String
+    }
+
+    struct AlterConfigsResponse {
+        throttle_time_ms: i32,
+        resources: Vec<AlterConfigsResult>
+    }
+)
+
+impl Request for AlterConfigsRequest {
+    fn api_key(_: Option<AlterConfigsRequest>) -> i16 { versions::ALTER_CONFIGS }
+
+    fn validate(&self) -> KafkaResult<()> {
+        if self.resources.is_empty() {
+            fail!((MalformedRequestError, "AlterConfigs request has no resources"));
+        }
+        Ok(())
+    }
+
+    fn topic_count(&self) -> uint {
+        self.resources.iter().filter(|resource| resource.resource_type == super::TOPIC_RESOURCE).count()
+    }
+}
+
+impl Response for AlterConfigsResponse {}
+
+/// The messages as of API version 0.
+pub mod v0 {
+    pub use super::{AlterConfigsEntry, AlterConfigsResource, AlterConfigsRequest, AlterConfigsResult,
+                    AlterConfigsResponse};
+}
+
+/// The newest version of these messages supported by this crate.
+pub mod latest {
+    pub use super::v0::*;
+}
@@ -0,0 +1,70 @@
+//! ApiVersions requests, for finding out which API versions a broker supports.
+
+use std::io;
+
+use types::*;
+use versions;
+use versions::ApiVersionRange;
+use super::{KafkaSerializable, HeapSize, BufferEncoder, Request, Response, add_size};
+
+/// Has no fields, so it can't be declared with `kafka_datastructures!`.
+#[deriving(Show, PartialEq, Eq, Clone)]
+pub struct ApiVersionsRequest;
+
+impl KafkaSerializable for ApiVersionsRequest {
+    fn encode(&self, _: &mut io::Writer) -> KafkaResult<()> {
+        Ok(())
+    }
+
+    fn decode(_: &mut io::Reader) -> KafkaResult<ApiVersionsRequest> {
+        Ok(ApiVersionsRequest)
+    }
+
+    #[inline]
+    fn size(&self) -> KafkaResult<i32> {
+        Ok(0)
+    }
+}
+
+impl HeapSize for ApiVersionsRequest {
+    fn heap_size(&self) -> uint { 0 }
+}
+
+kafka_datastructures! (
+    struct ApiVersionsResponseKey {
+        api_key: i16,
+        min_version: i16,
+        max_version: i16
+    }
+
+    struct ApiVersionsResponse {
+        error_code: i16,
+        api_versions: Vec<ApiVersionsResponseKey>
+    }
+)
+
+impl Request for ApiVersionsRequest {
+    fn api_key(_: Option<ApiVersionsRequest>) -> i16 { versions::API_VERSIONS }
+}
+
+impl Response for ApiVersionsResponse {}
+
+impl ApiVersionsResponse {
+    pub fn version_ranges(&self) -> Vec<ApiVersionRange> {
+        self.api_versions.iter().map(|key| ApiVersionRange {
+            api_key: key.api_key,
+            min_version: key.min_version,
+            max_version: key.max_version
+        }).collect()
+    }
+}
+
+/// The messages as of API version 0.
+pub mod v0 {
+    pub use super::{ApiVersionsRequest, ApiVersionsResponseKey, ApiVersionsResponse};
+}
+
+/// The newest version of these messages supported by this crate.
+pub mod latest {
+    pub use super::v0::*;
+}
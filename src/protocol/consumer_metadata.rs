@@ -0,0 +1,34 @@
+//! ConsumerMetadata requests, for finding a group's coordinator.
+
+use types::*;
+use versions;
+use super::{KafkaSerializable, HeapSize, BufferEncoder, Request, Response, add_size};
+
+kafka_datastructures! (
+    struct ConsumerMetadataRequest {
+        group: String
+    }
+
+    struct ConsumerMetadataResponse {
+        error_code: i16,
+        coordinator_id: i32,
+        coordinator_host: String,
+        coordinator_port: i32
+    }
+)
+
+impl Request for ConsumerMetadataRequest {
+    fn api_key(_: Option<ConsumerMetadataRequest>) -> i16 { versions::CONSUMER_METADATA }
+}
+
+impl Response for ConsumerMetadataResponse {}
+
+/// The messages as of API version 0.
+pub mod v0 {
+    pub use super::{ConsumerMetadataRequest, ConsumerMetadataResponse};
+}
+
+/// The newest version of these messages supported by this crate.
+pub mod latest {
+    pub use super::v0::*;
+}
@@ -0,0 +1,107 @@
+//! The embedded protocol consumers use within JoinGroup and SyncGroup. The
+//! group coordinator passes these around as opaque bytes, so every client in
+//! a group has to encode them the same way.
+
+use std::io;
+
+use types::*;
+use super::{KafkaSerializable, HeapSize, BufferEncoder, add_size, encode_to_vec};
+
+/// The protocol type consumers join groups with.
+pub const CONSUMER_PROTOCOL_TYPE: &'static str = "consumer";
+
+/// The version of the embedded protocol spoken here.
+pub const CONSUMER_PROTOCOL_VERSION: i16 = 0;
+
+kafka_datastructures! (
+    struct ConsumerProtocolSubscription {
+        version: i16,
+        topics: Vec<String>,
+        user_data: Option<Vec<u8>>
+    }
+
+    struct ConsumerProtocolTopicPartitions {
+        topic: String,
+        partitions: Vec<i32>
+    }
+
+    struct ConsumerProtocolAssignment {
+        version: i16,
+        assigned_partitions: Vec<ConsumerProtocolTopicPartitions>,
+        user_data: Option<Vec<u8>>
+    }
+)
+
+impl ConsumerProtocolSubscription {
+    pub fn new(topics: Vec<String>) -> ConsumerProtocolSubscription {
+        ConsumerProtocolSubscription { version: CONSUMER_PROTOCOL_VERSION, topics: topics, user_data: None }
+    }
+
+    /// The bytes sent as the member's metadata in JoinGroup.
+    pub fn to_bytes(&self) -> KafkaResult<Vec<u8>> {
+        encode_to_vec(self)
+    }
+
+    /// Reads a member's metadata, as the group leader gets it from JoinGroup.
+    /// Newer versions add fields at the end, which are ignored.
+    pub fn from_bytes(bytes: &[u8]) -> KafkaResult<ConsumerProtocolSubscription> {
+        KafkaSerializable::decode(&mut io::BufReader::new(bytes))
+    }
+}
+
+impl ConsumerProtocolAssignment {
+    /// Groups `partitions` by topic, in the order topics first appear.
+    pub fn new(partitions: &[TopicPartition]) -> ConsumerProtocolAssignment {
+        let mut assigned: Vec<ConsumerProtocolTopicPartitions> = vec![];
+        for partition in partitions.iter() {
+            match assigned.iter().position(|topic| topic.topic == partition.topic) {
+                Some(i) => assigned.as_mut_slice()[i].partitions.push(partition.partition),
+                None => assigned.push(ConsumerProtocolTopicPartitions {
+                    topic: partition.topic.clone(),
+                    partitions: vec![partition.partition]
+                })
+            }
+        }
+        ConsumerProtocolAssignment {
+            version: CONSUMER_PROTOCOL_VERSION,
+            assigned_partitions: assigned,
+            user_data: None
+        }
+    }
+
+    /// Every partition assigned, in the order encoded.
+    pub fn partitions(&self) -> Vec<TopicPartition> {
+        let mut partitions = vec![];
+        for topic in self.assigned_partitions.iter() {
+            for &partition in topic.partitions.iter() {
+                partitions.push(TopicPartition::new(topic.topic.as_slice(), partition));
+            }
+        }
+        partitions
+    }
+
+    /// The bytes the group leader sends for a member in SyncGroup.
+    pub fn to_bytes(&self) -> KafkaResult<Vec<u8>> {
+        encode_to_vec(self)
+    }
+
+    /// Reads the assignment a member gets back from SyncGroup. A member left
+    /// without partitions may get no bytes at all.
+    pub fn from_bytes(bytes: &[u8]) -> KafkaResult<ConsumerProtocolAssignment> {
+        if bytes.is_empty() {
+            return Ok(ConsumerProtocolAssignment::new(&[]));
+        }
+        KafkaSerializable::decode(&mut io::BufReader::new(bytes))
+    }
+}
+
+/// The messages as of version 0 of the embedded protocol.
+pub mod v0 {
+    pub use super::{ConsumerProtocolSubscription, ConsumerProtocolTopicPartitions, ConsumerProtocolAssignment,
+                    CONSUMER_PROTOCOL_TYPE, CONSUMER_PROTOCOL_VERSION};
+}
+
+/// The newest version of these messages supported by this crate.
+pub mod latest {
+    pub use super::v0::*;
+}
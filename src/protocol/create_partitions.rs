@@ -0,0 +1,59 @@
+//! CreatePartitions requests, which the controller broker answers.
+
+use types::*;
+use versions;
+use super::{KafkaSerializable, HeapSize, BufferEncoder, Request, Response, add_size};
+
+kafka_datastructures! (
+    struct CreatePartitionsTopic {
+        topic: String,
+        count: i32,
+        assignment: Option<Vec<Vec<i32>>>
+    }
+
+    struct CreatePartitionsRequest {
+        topic_partitions: Vec<CreatePartitionsTopic>,
+        timeout: i32,
+        validate_only: i8
+    }
+
+    struct CreatePartitionsTopicError {
+        topic: String,
+        error_code: i16,
+        error_message: Option<String>
+    }
+
+    struct CreatePartitionsResponse {
+        throttle_time_ms: i32,
+        topic_errors: Vec<CreatePartitionsTopicError>
+    }
+)
+
+impl Request for CreatePartitionsRequest {
+    fn api_key(_: Option<CreatePartitionsRequest>) -> i16 { versions::CREATE_PARTITIONS }
+
+    fn validate(&self) -> KafkaResult<()> {
+        if self.topic_partitions.is_empty() {
+            fail!((MalformedRequestError, "CreatePartitions request has no topics"));
+        }
+        if self.topic_partitions.iter().any(|topic| topic.count <= 0) {
+            fail!((MalformedRequestError, "CreatePartitions request has a topic without partitions"));
+        }
+        Ok(())
+    }
+
+    fn topic_count(&self) -> uint { self.topic_partitions.len() }
+}
+
+impl Response for CreatePartitionsResponse {}
+
+/// The messages as of API version 0.
+pub mod v0 {
+    pub use super::{CreatePartitionsTopic, CreatePartitionsRequest, CreatePartitionsTopicError,
+                    CreatePartitionsResponse};
+}
+
+/// The newest version of these messages supported by this crate.
+pub mod latest {
+    pub use super::v0::*;
+}
@@ -0,0 +1,84 @@
+//! CreateTopics requests, which the controller broker answers.
+
+use types::*;
+use versions;
+use super::{KafkaSerializable, HeapSize, BufferEncoder, Request, Response, add_size};
+
+kafka_datastructures! (
+    struct CreateTopicsReplicaAssignment {
+        partition: i32,
+        replicas: Vec<i32>
+    }
+
+    struct CreateTopicsConfigEntry {
+        config_name: String,
+        config_value: Option<String>
+    }
+
+    struct CreateTopicsTopic {
+        topic: String,
+        num_partitions: i32,
+        replication_factor: i16,
+        replica_assignment: Vec<CreateTopicsReplicaAssignment>,
+        config_entries: Vec<CreateTopicsConfigEntry>
+    }
+
+    struct CreateTopicsRequest {
+        create_topic_requests: Vec<CreateTopicsTopic>,
+        timeout: i32,
+        validate_only: i8
+    }
+
+    struct CreateTopicsTopicError {
+        topic: String,
+        error_code: i16,
+        error_message: Option<String>
+    }
+
+    struct CreateTopicsResponse {
+        topic_errors: Vec<CreateTopicsTopicError>
+    }
+)
+
+/// `num_partitions` and `replication_factor` when `replica_assignment` is
+/// given instead.
+pub const FROM_ASSIGNMENT: i32 = -1;
+
+impl Request for CreateTopicsRequest {
+    fn api_key(_: Option<CreateTopicsRequest>) -> i16 { versions::CREATE_TOPICS }
+
+    fn api_version(_: Option<CreateTopicsRequest>) -> i16 { 1 }
+
+    fn validate(&self) -> KafkaResult<()> {
+        if self.create_topic_requests.is_empty() {
+            fail!((MalformedRequestError, "CreateTopics request has no topics"));
+        }
+        for topic in self.create_topic_requests.iter() {
+            let assigned = !topic.replica_assignment.is_empty();
+            if assigned && (topic.num_partitions != FROM_ASSIGNMENT || topic.replication_factor as i32 != FROM_ASSIGNMENT) {
+                fail!((MalformedRequestError, "CreateTopics request gives both a replica assignment and counts"));
+            }
+            if !assigned && (topic.num_partitions <= 0 || topic.replication_factor <= 0) {
+                fail!((MalformedRequestError, "CreateTopics request has a topic without partitions or replicas"));
+            }
+        }
+        Ok(())
+    }
+
+    fn topic_count(&self) -> uint { self.create_topic_requests.len() }
+}
+
+impl Response for CreateTopicsResponse {}
+
+/// The messages as of API version 1, which added `validate_only` and
+/// `error_message`.
+pub mod v1 {
+    pub use super::{CreateTopicsReplicaAssignment, CreateTopicsConfigEntry, CreateTopicsTopic,
+                    CreateTopicsRequest, CreateTopicsTopicError, CreateTopicsResponse,
+                    FROM_ASSIGNMENT};
+}
+
+/// The newest version of these messages supported by this crate.
+pub mod latest {
+    pub use super::v1::*;
+}
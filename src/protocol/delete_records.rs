@@ -0,0 +1,82 @@
+//! DeleteRecords requests, which each partition's leader answers.
+
+use types::*;
+use versions;
+use super::{KafkaSerializable, HeapSize, BufferEncoder, Request, Response, add_size, check_error_code};
+
+kafka_datastructures! (
+    struct DeleteRecordsPartition {
+        partition: i32,
+        offset: i64
+    }
+
+    struct DeleteRecordsTopic {
+        topic: String,
+        partitions: Vec<DeleteRecordsPartition>
+    }
+
+    struct DeleteRecordsRequest {
+        topics: Vec<DeleteRecordsTopic>,
+        timeout: i32
+    }
+
+    struct DeleteRecordsPartitionResult {
+        partition: i32,
+        low_watermark: i64,
+        error_code: i16
+    }
+
+    struct DeleteRecordsTopicResult {
+        topic: String,
+        partitions: Vec<DeleteRecordsPartitionResult>
+    }
+
+    struct DeleteRecordsResponse {
+        throttle_time_ms: i32,
+        topics: Vec<DeleteRecordsTopicResult>
+    }
+)
+
+/// `offset` to delete every record up to the high watermark.
+pub const HIGH_WATERMARK: i64 = -1;
+
+impl Request for DeleteRecordsRequest {
+    fn api_key(_: Option<DeleteRecordsRequest>) -> i16 { versions::DELETE_RECORDS }
+
+    fn validate(&self) -> KafkaResult<()> {
+        require_non_empty!(self.topics, "DeleteRecords")
+    }
+
+    fn topic_count(&self) -> uint { self.topics.len() }
+}
+
+impl Response for DeleteRecordsResponse {}
+
+impl DeleteRecordsResponse {
+    /// The low watermark left in a partition, or the partition's error.
+    pub fn low_watermark(&self, topic: &str, partition: i32) -> KafkaResult<i64> {
+        let result = self.topics.iter()
+            .filter(|result| result.topic.as_slice() == topic)
+            .flat_map(|result| result.partitions.iter())
+            .find(|result| result.partition == partition);
+        match result {
+            Some(result) => {
+                try!(check_error_code(result.error_code));
+                Ok(result.low_watermark)
+            },
+            None => fail!((MalformedResponseError, "DeleteRecords response is missing the requested partition"))
+        }
+    }
+}
+
+/// The messages as of API version 0.
+pub mod v0 {
+    pub use super::{DeleteRecordsPartition, DeleteRecordsTopic, DeleteRecordsRequest,
+                    DeleteRecordsPartitionResult, DeleteRecordsTopicResult, DeleteRecordsResponse,
+                    HIGH_WATERMARK};
+}
+
+/// The newest version of these messages supported by this crate.
+pub mod latest {
+    pub use super::v0::*;
+}
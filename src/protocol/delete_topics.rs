@@ -0,0 +1,46 @@
+//! DeleteTopics requests, which the controller broker answers.
+
+use types::*;
+use versions;
+use super::{KafkaSerializable, HeapSize, BufferEncoder, Request, Response, add_size};
+
+kafka_datastructures! (
+    struct DeleteTopicsRequest {
+        topics: Vec<String>,
+        timeout: i32
+    }
+
+    struct DeleteTopicsTopicError {
+        topic: String,
+        error_code: i16
+    }
+
+    struct DeleteTopicsResponse {
+        topic_error_codes: Vec<DeleteTopicsTopicError>
+    }
+)
+
+impl Request for DeleteTopicsRequest {
+    fn api_key(_: Option<DeleteTopicsRequest>) -> i16 { versions::DELETE_TOPICS }
+
+    fn validate(&self) -> KafkaResult<()> {
+        if self.topics.is_empty() {
+            fail!((MalformedRequestError, "DeleteTopics request has no topics"));
+        }
+        Ok(())
+    }
+
+    fn topic_count(&self) -> uint { self.topics.len() }
+}
+
+impl Response for DeleteTopicsResponse {}
+
+/// The messages as of API version 0.
+pub mod v0 {
+    pub use super::{DeleteTopicsRequest, DeleteTopicsTopicError, DeleteTopicsResponse};
+}
+
+/// The newest version of these messages supported by this crate.
+pub mod latest {
+    pub use super::v0::*;
+}
@@ -0,0 +1,71 @@
+//! DescribeConfigs requests, for reading topic and broker configuration.
+
+use types::*;
+use versions;
+use super::{KafkaSerializable, HeapSize, BufferEncoder, Request, Response, add_size};
+
+kafka_datastructures! (
+    struct DescribeConfigsResource {
+        resource_type: i8,
+        resource_name: String,
+        config_names: Option<Vec<String>>
+    }
+
+    struct DescribeConfigsRequest {
+        resources: Vec<DescribeConfigsResource>
+    }
+
+    struct DescribeConfigsEntry {
+        config_name: String,
+        config_value: Option<String>,
+        read_only: i8,
+        is_default: i8,
+        is_sensitive: i8
+    }
+
+    struct DescribeConfigsResult {
+        error_code: i16,
+        error_message: Option<String>,
+        resource_type: i8,
+        resource_name: String,
+        config_entries: Vec<DescribeConfigsEntry>
+    }
+
+    struct DescribeConfigsResponse {
+        throttle_time_ms: i32,
+        resources: Vec<DescribeConfigsResult>
+    }
+)
+
+/// `resource_type` of a topic in DescribeConfigs and AlterConfigs.
+pub const TOPIC_RESOURCE: i8 = 2;
+/// `resource_type` of a broker in DescribeConfigs and AlterConfigs.
+pub const BROKER_RESOURCE: i8 = 4;
+
+impl Request for DescribeConfigsRequest {
+    fn api_key(_: Option<DescribeConfigsRequest>) -> i16 { versions::DESCRIBE_CONFIGS }
+    fn validate(&self) -> KafkaResult<()> {
+        if self.resources.is_empty() {
+            fail!((MalformedRequestError, "DescribeConfigs request has no resources"));
+        }
+        Ok(())
+    }
+
+    fn topic_count(&self) -> uint {
+        self.resources.iter().filter(|resource| resource.resource_type == TOPIC_RESOURCE).count()
+    }
+}
+
+impl Response for DescribeConfigsResponse {}
+
+/// The messages as of API version 0.
+pub mod v0 {
+    pub use super::{DescribeConfigsResource, DescribeConfigsRequest, DescribeConfigsEntry,
+                    DescribeConfigsResult, DescribeConfigsResponse, TOPIC_RESOURCE,
+                    BROKER_RESOURCE};
+}
+
+/// The newest version of these messages supported by this crate.
+pub mod latest {
+    pub use super::v0::*;
+}
@@ -0,0 +1,76 @@
+//! DescribeGroups requests, for inspecting a group's state and members.
+
+use types::*;
+use versions;
+use super::{KafkaSerializable, HeapSize, BufferEncoder, Request, Response, add_size};
+use super::consumer_protocol::{ConsumerProtocolAssignment, CONSUMER_PROTOCOL_TYPE};
+
+kafka_datastructures! (
+    struct DescribeGroupsRequest {
+        group_ids: Vec<String>
+    }
+
+    struct DescribeGroupsMember {
+        member_id: String,
+        client_id: String,
+        client_host: String,
+        member_metadata: Option<Vec<u8>>,
+        member_assignment: Option<Vec<u8>>
+    }
+
+    struct DescribeGroupsGroup {
+        error_code: i16,
+        group_id: String,
+        state: String,
+        protocol_type: String,
+        protocol: String,
+        members: Vec<DescribeGroupsMember>
+    }
+
+    struct DescribeGroupsResponse {
+        groups: Vec<DescribeGroupsGroup>
+    }
+)
+
+impl Request for DescribeGroupsRequest {
+    fn api_key(_: Option<DescribeGroupsRequest>) -> i16 { versions::DESCRIBE_GROUPS }
+
+    fn validate(&self) -> KafkaResult<()> {
+        if self.group_ids.is_empty() {
+            fail!((MalformedRequestError, "DescribeGroups request has no groups"));
+        }
+        Ok(())
+    }
+}
+
+impl Response for DescribeGroupsResponse {}
+
+impl DescribeGroupsGroup {
+    /// The partitions assigned to each member of a consumer group, by
+    /// member id. Empty for groups of other protocol types.
+    pub fn consumer_assignments(&self) -> KafkaResult<Vec<(String, Vec<TopicPartition>)>> {
+        let mut assignments = vec![];
+        if self.protocol_type.as_slice() != CONSUMER_PROTOCOL_TYPE {
+            return Ok(assignments);
+        }
+        for member in self.members.iter() {
+            let bytes: &[u8] = match member.member_assignment {
+                Some(ref bytes) => bytes.as_slice(),
+                None => &[]
+            };
+            let assignment = try!(ConsumerProtocolAssignment::from_bytes(bytes));
+            assignments.push((member.member_id.clone(), assignment.partitions()));
+        }
+        Ok(assignments)
+    }
+}
+
+/// The messages as of API version 0.
+pub mod v0 {
+    pub use super::{DescribeGroupsRequest, DescribeGroupsMember, DescribeGroupsGroup, DescribeGroupsResponse};
+}
+
+/// The newest version of these messages supported by this crate.
+pub mod latest {
+    pub use super::v0::*;
+}
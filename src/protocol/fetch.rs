@@ -0,0 +1,118 @@
+//! Fetch requests, for reading message sets from partitions.
+
+use std::vec;
+
+use types::*;
+use versions;
+use super::{KafkaSerializable, HeapSize, BufferEncoder, WithSize, Request, Response, add_size};
+use super::message::{MessageSet, NO_COMPRESSION};
+
+/// A message fetched from a partition: where it came from, its offset, key
+/// and value.
+pub type FetchedMessage = (TopicPartition, i64, Option<Vec<u8>>, Option<Vec<u8>>);
+
+kafka_datastructures! (
+    struct FetchRequestPartition {
+        partition: i32,
+        fetch_offset: i64,
+        max_bytes: i32
+    }
+
+    struct FetchRequestTopic {
+        name: String,
+        partitions: Vec<FetchRequestPartition>
+    }
+
+    struct FetchRequest {
+        replica_id: i32,
+        max_wait_time: i32,
+        min_bytes: i32,
+        elements: Vec<FetchRequestTopic>
+    }
+
+    struct FetchResponsePartition {
+        partition: i32,
+        error_code: i16,
+        highwater_mark_offset: i64,
+        messages: WithSize<MessageSet>
+    }
+
+    struct FetchResponseTopic {
+        name: String,
+        partitions: Vec<FetchResponsePartition>
+    }
+
+    struct FetchResponse {
+        topics: Vec<FetchResponseTopic>
+    }
+)
+
+impl Request for FetchRequest {
+    fn api_key(_: Option<FetchRequest>) -> i16 { versions::FETCH }
+
+    fn validate(&self) -> KafkaResult<()> {
+        require_non_empty!(self.elements, "Fetch")
+    }
+
+    fn topic_count(&self) -> uint { self.elements.len() }
+}
+
+impl Response for FetchResponse {}
+
+impl FetchResponse {
+    /// Heap bytes held by each topic's part of the response.
+    pub fn heap_size_by_topic(&self) -> Vec<(String, uint)> {
+        self.topics.iter().map(|topic| (topic.name.clone(), topic.heap_size())).collect()
+    }
+
+    /// Every message in the response, in order, with those wrapped up in
+    /// compressed messages decompressed.
+    pub fn messages(&self) -> KafkaResult<vec::MoveItems<FetchedMessage>> {
+        let mut messages = vec![];
+        for topic in self.topics.iter() {
+            for partition in topic.partitions.iter() {
+                let WithSize(ref message_set) = partition.messages;
+                let topic_partition = TopicPartition::new(topic.name.as_slice(), partition.partition);
+                try!(flatten(&topic_partition, message_set, false, &mut messages));
+            }
+        }
+        Ok(messages.into_iter())
+    }
+}
+
+/// Every message in a message set fetched from `partition`, in order, with
+/// those wrapped up in compressed messages decompressed.
+pub fn fetched_messages(partition: &TopicPartition,
+                        message_set: &MessageSet) -> KafkaResult<vec::MoveItems<FetchedMessage>> {
+    let mut messages = vec![];
+    try!(flatten(partition, message_set, false, &mut messages));
+    Ok(messages.into_iter())
+}
+
+// Kafka only ever wraps uncompressed messages in a compressed one, so a
+// compressed message inside a compressed set is rejected rather than followed
+fn flatten(partition: &TopicPartition, message_set: &MessageSet, compressed: bool,
+           messages: &mut Vec<FetchedMessage>) -> KafkaResult<()> {
+    for element in message_set.messages.iter() {
+        let WithSize(ref message) = element.message;
+        if message.codec() == NO_COMPRESSION {
+            messages.push((partition.clone(), element.offset, message.key.clone(), message.value.clone()));
+        } else if compressed {
+            fail!((MalformedResponseError, "Compressed message inside a compressed message set"));
+        } else {
+            try!(flatten(partition, &try!(message.decompress()), true, messages));
+        }
+    }
+    Ok(())
+}
+
+/// The messages as of API version 0.
+pub mod v0 {
+    pub use super::{fetched_messages, FetchedMessage, FetchRequestPartition, FetchRequestTopic, FetchRequest,
+                    FetchResponsePartition, FetchResponseTopic, FetchResponse};
+}
+
+/// The newest version of these messages supported by this crate.
+pub mod latest {
+    pub use super::v0::*;
+}
@@ -0,0 +1,60 @@
+//! Heartbeat requests, for a group member to show the coordinator it's alive.
+
+use types::*;
+use versions;
+use super::{KafkaSerializable, HeapSize, BufferEncoder, Request, Response, add_size};
+
+kafka_datastructures! (
+    struct HeartbeatRequest {
+        group_id: String,
+        group_generation_id: i32,
+        member_id: String
+    }
+
+    struct HeartbeatResponse {
+        error_code: i16
+    }
+
+    struct HeartbeatRequestV3 {
+        group_id: String,
+        group_generation_id: i32,
+        member_id: String,
+        group_instance_id: Option<String>
+    }
+
+    struct HeartbeatResponseV3 {
+        throttle_time_ms: i32,
+        error_code: i16
+    }
+)
+
+impl Request for HeartbeatRequest {
+    fn api_key(_: Option<HeartbeatRequest>) -> i16 { versions::HEARTBEAT }
+}
+
+impl Response for HeartbeatResponse {}
+
+impl Request for HeartbeatRequestV3 {
+    fn api_key(_: Option<HeartbeatRequestV3>) -> i16 { versions::HEARTBEAT }
+
+    fn api_version(_: Option<HeartbeatRequestV3>) -> i16 { 3 }
+}
+
+impl Response for HeartbeatResponseV3 {}
+
+/// The messages as of API version 0.
+pub mod v0 {
+    pub use super::{HeartbeatRequest, HeartbeatResponse};
+}
+
+/// The messages as of API version 3, which added `group_instance_id` for
+/// static membership (Kafka 2.3), so the coordinator can fence off another
+/// consumer using the same instance id.
+pub mod v3 {
+    pub use super::{HeartbeatRequestV3, HeartbeatResponseV3};
+}
+
+/// The newest version of these messages supported by this crate.
+pub mod latest {
+    pub use super::v3::*;
+}
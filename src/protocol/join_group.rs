@@ -0,0 +1,145 @@
+//! JoinGroup requests, with which a consumer becomes a member of a group and
+//! the coordinator picks one member to lead it.
+
+use types::*;
+use versions;
+use super::{KafkaSerializable, HeapSize, BufferEncoder, Request, Response, add_size};
+
+/// The member id to join with the first time, leaving the coordinator to
+/// pick one.
+pub const UNKNOWN_MEMBER_ID: &'static str = "";
+
+kafka_datastructures! (
+    struct JoinGroupProtocol {
+        name: String,
+        metadata: Vec<u8>
+    }
+
+    struct JoinGroupRequest {
+        group_id: String,
+        session_timeout_ms: i32,
+        rebalance_timeout_ms: i32,
+        member_id: String,
+        group_instance_id: Option<String>,
+        protocol_type: String,
+        protocols: Vec<JoinGroupProtocol>
+    }
+
+    struct JoinGroupMember {
+        member_id: String,
+        group_instance_id: Option<String>,
+        metadata: Vec<u8>
+    }
+
+    struct JoinGroupResponse {
+        throttle_time_ms: i32,
+        error_code: i16,
+        generation_id: i32,
+        protocol_name: String,
+        leader: String,
+        member_id: String,
+        members: Vec<JoinGroupMember>
+    }
+
+    struct JoinGroupRequestV0 {
+        group_id: String,
+        session_timeout_ms: i32,
+        member_id: String,
+        protocol_type: String,
+        protocols: Vec<JoinGroupProtocol>
+    }
+
+    struct JoinGroupRequestV1 {
+        group_id: String,
+        session_timeout_ms: i32,
+        rebalance_timeout_ms: i32,
+        member_id: String,
+        protocol_type: String,
+        protocols: Vec<JoinGroupProtocol>
+    }
+
+    struct JoinGroupMemberV0 {
+        member_id: String,
+        metadata: Vec<u8>
+    }
+
+    struct JoinGroupResponseV0 {
+        error_code: i16,
+        generation_id: i32,
+        protocol_name: String,
+        leader: String,
+        member_id: String,
+        members: Vec<JoinGroupMemberV0>
+    }
+)
+
+impl Request for JoinGroupRequest {
+    fn api_key(_: Option<JoinGroupRequest>) -> i16 { versions::JOIN_GROUP }
+
+    fn api_version(_: Option<JoinGroupRequest>) -> i16 { 5 }
+
+    fn validate(&self) -> KafkaResult<()> {
+        if self.protocols.is_empty() {
+            fail!((MalformedRequestError, "JoinGroup request has no protocols"));
+        }
+        Ok(())
+    }
+}
+
+impl Response for JoinGroupResponse {}
+
+impl Request for JoinGroupRequestV0 {
+    fn api_key(_: Option<JoinGroupRequestV0>) -> i16 { versions::JOIN_GROUP }
+
+    fn validate(&self) -> KafkaResult<()> {
+        if self.protocols.is_empty() {
+            fail!((MalformedRequestError, "JoinGroup request has no protocols"));
+        }
+        Ok(())
+    }
+}
+
+impl Request for JoinGroupRequestV1 {
+    fn api_key(_: Option<JoinGroupRequestV1>) -> i16 { versions::JOIN_GROUP }
+
+    fn api_version(_: Option<JoinGroupRequestV1>) -> i16 { 1 }
+
+    fn validate(&self) -> KafkaResult<()> {
+        if self.protocols.is_empty() {
+            fail!((MalformedRequestError, "JoinGroup request has no protocols"));
+        }
+        Ok(())
+    }
+}
+
+impl Response for JoinGroupResponseV0 {}
+
+impl JoinGroupResponse {
+    /// Whether the member this answers is the group's leader, which has to
+    /// assign partitions to every member. Only the leader is sent `members`.
+    pub fn is_leader(&self) -> bool {
+        self.leader == self.member_id
+    }
+}
+
+/// The messages as of API version 0, from Kafka 0.9.
+pub mod v0 {
+    pub use super::{JoinGroupProtocol, JoinGroupRequestV0, JoinGroupMemberV0, JoinGroupResponseV0, UNKNOWN_MEMBER_ID};
+}
+
+/// The messages as of API version 1, which added `rebalance_timeout_ms`
+/// (Kafka 0.10.1). Responses are as in version 0.
+pub mod v1 {
+    pub use super::{JoinGroupProtocol, JoinGroupRequestV1, JoinGroupMemberV0, JoinGroupResponseV0, UNKNOWN_MEMBER_ID};
+}
+
+/// The messages as of API version 5, which added `group_instance_id` for
+/// static membership (Kafka 2.3).
+pub mod v5 {
+    pub use super::{JoinGroupProtocol, JoinGroupRequest, JoinGroupMember, JoinGroupResponse, UNKNOWN_MEMBER_ID};
+}
+
+/// The newest version of these messages supported by this crate.
+pub mod latest {
+    pub use super::v5::*;
+}
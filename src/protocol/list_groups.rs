@@ -0,0 +1,58 @@
+//! ListGroups requests, for finding the groups a broker coordinates.
+
+use std::io;
+
+use types::*;
+use versions;
+use super::{KafkaSerializable, HeapSize, BufferEncoder, Request, Response, add_size};
+
+/// Has no fields, so it can't be declared with `kafka_datastructures!`.
+#[deriving(Show, PartialEq, Eq, Clone)]
+pub struct ListGroupsRequest;
+
+impl KafkaSerializable for ListGroupsRequest {
+    fn encode(&self, _: &mut io::Writer) -> KafkaResult<()> {
+        Ok(())
+    }
+
+    fn decode(_: &mut io::Reader) -> KafkaResult<ListGroupsRequest> {
+        Ok(ListGroupsRequest)
+    }
+
+    #[inline]
+    fn size(&self) -> KafkaResult<i32> {
+        Ok(0)
+    }
+}
+
+impl HeapSize for ListGroupsRequest {
+    fn heap_size(&self) -> uint { 0 }
+}
+
+kafka_datastructures! (
+    struct ListGroupsGroup {
+        group_id: String,
+        protocol_type: String
+    }
+
+    struct ListGroupsResponse {
+        error_code: i16,
+        groups: Vec<ListGroupsGroup>
+    }
+)
+
+impl Request for ListGroupsRequest {
+    fn api_key(_: Option<ListGroupsRequest>) -> i16 { versions::LIST_GROUPS }
+}
+
+impl Response for ListGroupsResponse {}
+
+/// The messages as of API version 0.
+pub mod v0 {
+    pub use super::{ListGroupsRequest, ListGroupsGroup, ListGroupsResponse};
+}
+
+/// The newest version of these messages supported by this crate.
+pub mod latest {
+    pub use super::v0::*;
+}
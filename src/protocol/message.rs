@@ -0,0 +1,339 @@
+//! Messages and the sets they're sent in, shared by produce and fetch.
+
+use std::cmp;
+use std::i32;
+use std::io;
+
+use flate;
+
+use types::*;
+use super::{KafkaSerializable, HeapSize, BufferEncoder, WithSize, CountingReader};
+use super::{add_size, array_length, read_bytes, locate_error};
+
+kafka_datastructures! (
+    struct Message {
+        crc: i32,
+        magic_byte: i8,
+        attributes: i8,
+        key: Option<Vec<u8>>,
+        value: Option<Vec<u8>>
+    }
+
+    struct MessageSetElement {
+        offset: i64,
+        message: WithSize<Message>
+    }
+)
+
+// A MessageSet isn't an ordinary array: its elements run back to back without a
+// count, filling whatever size the enclosing field declares.
+#[deriving(Show, PartialEq, Eq, Clone)]
+pub struct MessageSet {
+    pub messages: Vec<MessageSetElement>
+}
+
+impl MessageSet {
+    /// Decodes the elements contained in the next `size` bytes. A fetch may cut
+    /// the last message short; that fragment is skipped, and the returned count
+    /// covers only the bytes of the complete messages.
+    pub fn decode_sized(reader: &mut io::Reader, size: i32) -> KafkaResult<(MessageSet, i32)> {
+        if size < 0 {
+            fail!((MalformedResponseError, "Negative message set size"));
+        }
+        let mut iter = MessageSetIter::new(reader, size);
+        let mut messages = Vec::new();
+        for element in iter.by_ref() {
+            messages.push(try!(element));
+        }
+        Ok((MessageSet { messages: messages }, iter.consumed()))
+    }
+}
+
+/// Decodes a MessageSet one element at a time, so large fetches never have to
+/// be held in memory all at once.
+pub struct MessageSetIter<R> {
+    reader: CountingReader<R>,
+    consumed: i32,
+    done: bool
+}
+
+impl <R:Reader> MessageSetIter<R> {
+    pub fn new(reader: R, size: i32) -> MessageSetIter<R> {
+        MessageSetIter {
+            reader: CountingReader::new(reader, cmp::max(size, 0) as uint),
+            consumed: 0,
+            done: size < 0
+        }
+    }
+
+    /// Bytes taken up by the complete messages decoded so far.
+    pub fn consumed(&self) -> i32 {
+        self.consumed
+    }
+
+    fn read_element(&mut self) -> KafkaResult<Option<MessageSetElement>> {
+        // Offset and MessageSize come before every message
+        if self.reader.limit() < 12 {
+            return self.skip_truncated();
+        }
+
+        let offset: i64 = try!(KafkaSerializable::decode(&mut self.reader));
+        let message_size: i32 = try!(KafkaSerializable::decode(&mut self.reader));
+        if message_size < 0 {
+            fail!((MalformedResponseError, "Negative message size"));
+        }
+        if message_size as uint > self.reader.limit() {
+            return self.skip_truncated();
+        }
+
+        let mut message_reader = CountingReader::new(&mut self.reader as &mut io::Reader, message_size as uint);
+        let result: KafkaResult<Message> = KafkaSerializable::decode(&mut message_reader);
+        let message = try!(locate_error(result, message_reader.position(), message_size as uint));
+        if message_reader.limit() != 0 {
+            fail!((MalformedResponseError, "Less data read than specified"));
+        }
+
+        self.consumed += 12 + message_size;
+        Ok(Some(MessageSetElement {
+            offset: offset,
+            message: WithSize(message)
+        }))
+    }
+
+    // Whatever is left is a message the broker cut off at the fetch size limit
+    fn skip_truncated(&mut self) -> KafkaResult<Option<MessageSetElement>> {
+        let truncated = self.reader.limit();
+        try!(read_bytes(&mut self.reader, truncated));
+        Ok(None)
+    }
+}
+
+impl <'a> MessageSetIter<io::BufReader<'a>> {
+    pub fn from_slice(bytes: &'a [u8]) -> MessageSetIter<io::BufReader<'a>> {
+        MessageSetIter::new(io::BufReader::new(bytes), bytes.len() as i32)
+    }
+}
+
+impl <R:Reader> Iterator<KafkaResult<MessageSetElement>> for MessageSetIter<R> {
+    fn next(&mut self) -> Option<KafkaResult<MessageSetElement>> {
+        if self.done {
+            return None;
+        }
+
+        match self.read_element() {
+            Ok(Some(element)) => Some(Ok(element)),
+            Ok(None) => {
+                self.done = true;
+                None
+            },
+            Err(err) => {
+                self.done = true;
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+impl HeapSize for MessageSet {
+    fn heap_size(&self) -> uint {
+        self.messages.heap_size()
+    }
+}
+
+impl KafkaSerializable for MessageSet {
+    fn encode(&self, writer: &mut io::Writer) -> KafkaResult<()> {
+        for element in self.messages.iter() {
+            try!(element.encode(writer));
+        }
+        Ok(())
+    }
+
+    fn encode_buffered(&self, encoder: &mut BufferEncoder) -> KafkaResult<()> {
+        for element in self.messages.iter() {
+            try!(element.encode_buffered(encoder));
+        }
+        Ok(())
+    }
+
+    // Only meaningful on a reader limited to the set, such as within WithSize
+    fn decode(reader: &mut io::Reader) -> KafkaResult<MessageSet> {
+        let buffer = try!(reader.read_to_end());
+        let (message_set, _) = try!(MessageSet::decode_sized(&mut io::BufReader::new(buffer.as_slice()),
+                                                             try!(array_length(buffer.len()))));
+        Ok(message_set)
+    }
+
+    #[inline]
+    fn size(&self) -> KafkaResult<i32> {
+        let mut size = 0i32;
+        for element in self.messages.iter() {
+            size = try!(add_size(size, try!(element.size())));
+        }
+        Ok(size)
+    }
+
+    fn type_name(_: Option<MessageSet>) -> &'static str { "MessageSet" }
+}
+
+/// Assembles a MessageSet to produce, building each message with its CRC and
+/// refusing any that would take the set beyond `max_bytes`.
+pub struct MessageSetBuilder {
+    messages: Vec<MessageSetElement>,
+    size: i32,
+    max_bytes: i32
+}
+
+impl MessageSetBuilder {
+    pub fn new() -> MessageSetBuilder {
+        MessageSetBuilder::with_max_bytes(i32::MAX)
+    }
+
+    pub fn with_max_bytes(max_bytes: i32) -> MessageSetBuilder {
+        MessageSetBuilder { messages: vec![], size: 0, max_bytes: max_bytes }
+    }
+
+    pub fn append(&mut self, key: Option<Vec<u8>>, value: Option<Vec<u8>>) -> KafkaResult<()> {
+        self.append_message(try!(Message::new(key, value)))
+    }
+
+    /// Appends a message that has already been built. Its offset is left at
+    /// 0, as the broker assigns offsets to the messages it's sent.
+    pub fn append_message(&mut self, message: Message) -> KafkaResult<()> {
+        let element = MessageSetElement { offset: 0, message: WithSize(message) };
+        let size = try!(add_size(self.size, try!(element.size())));
+        if size > self.max_bytes {
+            fail!((MalformedRequestError, "Message set would exceed its size limit"));
+        }
+        self.messages.push(element);
+        self.size = size;
+        Ok(())
+    }
+
+    /// Encoded size of the set so far.
+    pub fn size(&self) -> i32 {
+        self.size
+    }
+
+    pub fn len(&self) -> uint {
+        self.messages.len()
+    }
+
+    pub fn build(self) -> MessageSet {
+        MessageSet { messages: self.messages }
+    }
+}
+
+/// The low bits of a message's attributes give the codec its value is
+/// compressed with. A compressed message's value is a whole message set.
+pub const COMPRESSION_CODEC_MASK: i8 = 0x07;
+pub const NO_COMPRESSION: i8 = 0;
+pub const GZIP_COMPRESSION: i8 = 1;
+pub const SNAPPY_COMPRESSION: i8 = 2;
+
+/// The CRC-32 (IEEE) checksum Kafka stores with every message.
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = 0xffffffffu32;
+    for &byte in bytes.iter() {
+        crc ^= byte as u32;
+        for _ in range(0u, 8) {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xedb88320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+impl Message {
+    /// Builds an uncompressed message with its CRC filled in. A `None` value
+    /// makes the message a tombstone for its key.
+    pub fn new(key: Option<Vec<u8>>, value: Option<Vec<u8>>) -> KafkaResult<Message> {
+        let mut message = Message {
+            crc: 0,
+            magic_byte: 0,
+            attributes: 0,
+            key: key,
+            value: value
+        };
+        message.crc = try!(message.compute_crc());
+        Ok(message)
+    }
+
+    pub fn codec(&self) -> i8 {
+        self.attributes & COMPRESSION_CODEC_MASK
+    }
+
+    /// The message set wrapped up in a compressed message. Only gzip is
+    /// supported.
+    pub fn decompress(&self) -> KafkaResult<MessageSet> {
+        let value = match self.value {
+            Some(ref value) => value.as_slice(),
+            None => fail!((MalformedResponseError, "Compressed message has no value"))
+        };
+        let bytes = match self.codec() {
+            GZIP_COMPRESSION => try!(gunzip(value)),
+            SNAPPY_COMPRESSION => fail!((MalformedResponseError, "Snappy compression isn't supported")),
+            _ => fail!((MalformedResponseError, "Unknown compression codec"))
+        };
+        let (message_set, _) = try!(MessageSet::decode_sized(&mut io::BufReader::new(bytes.as_slice()),
+                                                             try!(array_length(bytes.len()))));
+        Ok(message_set)
+    }
+
+    /// The CRC of everything following the crc field, as the broker will
+    /// compute it.
+    pub fn compute_crc(&self) -> KafkaResult<i32> {
+        let mut encoder = BufferEncoder::new();
+        try!(self.magic_byte.encode(&mut encoder));
+        try!(self.attributes.encode(&mut encoder));
+        try!(self.key.encode(&mut encoder));
+        try!(self.value.encode(&mut encoder));
+        Ok(crc32(encoder.get_ref()) as i32)
+    }
+}
+
+// Unwraps gzip's framing (RFC 1952) around the deflate stream flate inflates,
+// checking the inflated data against the trailer's CRC and size. flate
+// inflates in one go, so how much a set inflates to isn't bounded.
+fn gunzip(bytes: &[u8]) -> KafkaResult<Vec<u8>> {
+    if bytes.len() < 18 || bytes[0] != 0x1f || bytes[1] != 0x8b || bytes[2] != 8 {
+        fail!((MalformedResponseError, "Invalid gzip header"));
+    }
+
+    let flags = bytes[3];
+    let mut start = 10u;
+    if flags & 0x04 != 0 && start + 2 <= bytes.len() {
+        start += 2 + (bytes[start] as uint | bytes[start + 1] as uint << 8);
+    }
+    // The file name and comment end with a NUL byte
+    for &flag in [0x08u8, 0x10].iter() {
+        if flags & flag != 0 && start < bytes.len() {
+            match bytes.slice_from(start).iter().position(|&byte| byte == 0) {
+                Some(end) => start += end + 1,
+                None => start = bytes.len()
+            }
+        }
+    }
+    if flags & 0x02 != 0 {
+        start += 2;
+    }
+    // Followed by an 8 byte trailer
+    if start + 8 > bytes.len() {
+        fail!((MalformedResponseError, "Invalid gzip header"));
+    }
+
+    let trailer = bytes.slice_from(bytes.len() - 8);
+    let little_endian = |bytes: &[u8]| bytes.iter().rev().fold(0u32, |value, &byte| value << 8 | byte as u32);
+    let (crc, size) = (little_endian(trailer.slice_to(4)), little_endian(trailer.slice_from(4)));
+
+    let inflated = match flate::inflate_bytes(bytes.slice(start, bytes.len() - 8)) {
+        Some(inflated) => inflated.as_slice().to_vec(),
+        None => fail!((MalformedResponseError, "Invalid gzip data"))
+    };
+    // The trailer holds the size modulo 2^32
+    if inflated.len() as u32 != size {
+        fail!((MalformedResponseError, "Gzip data doesn't match its declared size"));
+    }
+    if crc32(inflated.as_slice()) != crc {
+        fail!((MalformedResponseError, "Gzip data doesn't match its CRC"));
+    }
+    Ok(inflated)
+}
@@ -0,0 +1,103 @@
+//! Metadata requests, for discovering brokers and partition leaders.
+
+use types::*;
+use versions;
+use super::{KafkaSerializable, HeapSize, BufferEncoder, Request, Response, add_size};
+
+kafka_datastructures! (
+    struct MetadataRequest {
+        topic_names: Vec<String>
+    }
+
+    struct Broker {
+        node_id: i32,
+        host: String,
+        port: i32
+    }
+
+    struct PartitionMetadata {
+        error_code: i16,
+        partition: i32,
+        leader: i32,
+        replicas: Vec<i32>,
+        isr: Vec<i32>
+    }
+
+    struct TopicMetadata {
+        error_code: i16,
+        name: String,
+        partitions: Vec<PartitionMetadata>
+    }
+
+    struct MetadataResponse {
+        brokers: Vec<Broker>,
+        topics: Vec<TopicMetadata>
+    }
+
+    struct MetadataRequestV1 {
+        topic_names: Option<Vec<String>>
+    }
+
+    struct BrokerV1 {
+        node_id: i32,
+        host: String,
+        port: i32,
+        rack: Option<String>
+    }
+
+    struct TopicMetadataV1 {
+        error_code: i16,
+        name: String,
+        is_internal: i8,
+        partitions: Vec<PartitionMetadata>
+    }
+
+    struct MetadataResponseV1 {
+        brokers: Vec<BrokerV1>,
+        controller_id: i32,
+        topics: Vec<TopicMetadataV1>
+    }
+)
+
+/// `controller_id` while no broker has been elected controller.
+pub const NO_CONTROLLER: i32 = -1;
+
+impl Request for MetadataRequest {
+    fn api_key(_: Option<MetadataRequest>) -> i16 { versions::METADATA }
+
+    fn topic_count(&self) -> uint { self.topic_names.len() }
+}
+
+impl Response for MetadataResponse {}
+
+impl Request for MetadataRequestV1 {
+    fn api_key(_: Option<MetadataRequestV1>) -> i16 { versions::METADATA }
+
+    fn api_version(_: Option<MetadataRequestV1>) -> i16 { 1 }
+
+    fn topic_count(&self) -> uint { self.topic_names.as_ref().map_or(0, |names| names.len()) }
+}
+
+impl Response for MetadataResponseV1 {}
+
+impl MetadataResponseV1 {
+    /// The broker acting as controller, unless none has been elected.
+    pub fn controller(&self) -> Option<&BrokerV1> {
+        if self.controller_id == NO_CONTROLLER {
+            return None;
+        }
+        self.brokers.iter().find(|broker| broker.node_id == self.controller_id)
+    }
+}
+
+/// The messages as of API version 0.
+pub mod v0 {
+    pub use super::{MetadataRequest, Broker, PartitionMetadata, TopicMetadata, MetadataResponse};
+}
+
+/// The messages as of API version 1, which added racks, the controller and
+/// internal topics. `topic_names` of `None` asks about every topic, and an
+/// empty list about none. Partitions are described as in version 0.
+pub mod v1 {
+    pub use super::{MetadataRequestV1, BrokerV1, TopicMetadataV1, MetadataResponseV1, NO_CONTROLLER};
+}
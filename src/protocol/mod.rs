@@ -0,0 +1,1687 @@
+use std::cmp;
+use std::i16;
+use std::i32;
+use std::io;
+use std::mem;
+
+use types::*;
+
+pub use self::message::*;
+pub use self::metadata::v0::*;
+pub use self::produce::latest::*;
+pub use self::offset::v0::*;
+pub use self::fetch::latest::*;
+pub use self::consumer_metadata::latest::*;
+pub use self::offset_commit::latest::*;
+pub use self::offset_fetch::latest::*;
+pub use self::describe_configs::latest::*;
+pub use self::api_versions::latest::*;
+pub use self::consumer_protocol::latest::*;
+pub use self::heartbeat::latest::*;
+pub use self::join_group::latest::*;
+pub use self::sync_group::latest::*;
+pub use self::describe_groups::latest::*;
+pub use self::list_groups::latest::*;
+pub use self::create_topics::latest::*;
+pub use self::delete_topics::latest::*;
+pub use self::delete_records::latest::*;
+pub use self::create_partitions::latest::*;
+pub use self::alter_configs::latest::*;
+
+// Length prefixes come straight off the wire, so never trust them for more than
+// this much up-front allocation; anything bigger grows as data actually arrives.
+static MAX_PREALLOCATED_ELEMENTS: uint = 1024;
+static MAX_PREALLOCATED_BYTES: uint = 64 * 1024;
+
+fn read_bytes(reader: &mut io::Reader, size: uint) -> KafkaResult<Vec<u8>> {
+    let mut buffer = Vec::with_capacity(cmp::min(size, MAX_PREALLOCATED_BYTES));
+    while buffer.len() < size {
+        let chunk = cmp::min(size - buffer.len(), MAX_PREALLOCATED_BYTES);
+        try!(reader.push_at_least(chunk, chunk, &mut buffer));
+    }
+    Ok(buffer)
+}
+
+// Sizes end up in i32 length prefixes, so refuse to wrap around rather than
+// emitting a frame whose prefix disagrees with its contents.
+fn add_size(a: i32, b: i32) -> KafkaResult<i32> {
+    let sum = a as i64 + b as i64;
+    if sum > i32::MAX as i64 {
+        fail!((MalformedRequestError, "Encoded size exceeds the maximum frame size"));
+    }
+    Ok(sum as i32)
+}
+
+fn string_length(string: &str) -> KafkaResult<i16> {
+    if string.len() > i16::MAX as uint {
+        fail!((MalformedRequestError, "String too long to encode"));
+    }
+    Ok(string.len() as i16)
+}
+
+fn array_length(length: uint) -> KafkaResult<i32> {
+    if length > i32::MAX as uint {
+        fail!((MalformedRequestError, "Array too long to encode"));
+    }
+    Ok(length as i32)
+}
+
+/// Reads at most `limit` bytes from the inner reader, like `LimitReader`, while
+/// keeping track of how many bytes have been read so far.
+pub struct CountingReader<R> {
+    inner: R,
+    limit: uint,
+    position: uint
+}
+
+impl <R:Reader> CountingReader<R> {
+    pub fn new(inner: R, limit: uint) -> CountingReader<R> {
+        CountingReader {
+            inner: inner,
+            limit: limit,
+            position: 0
+        }
+    }
+
+    /// Bytes left before the limit is reached.
+    pub fn limit(&self) -> uint {
+        self.limit
+    }
+
+    /// Bytes read so far.
+    pub fn position(&self) -> uint {
+        self.position
+    }
+
+    pub fn unwrap(self) -> R {
+        self.inner
+    }
+}
+
+impl <R:Reader> Reader for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::IoResult<uint> {
+        if self.limit == 0 {
+            return Err(io::standard_error(io::EndOfFile));
+        }
+
+        let length = cmp::min(self.limit, buf.len());
+        let read = try!(self.inner.read(buf.slice_to_mut(length)));
+        self.limit -= read;
+        self.position += read;
+        Ok(read)
+    }
+}
+
+// Adds where in a sized block a decode error happened, and the type the block
+// holds, to its detail. Errors from nested blocks get the position in each
+// enclosing block appended in turn.
+fn locate_error<T:KafkaSerializable>(result: KafkaResult<T>, position: uint, size: uint) -> KafkaResult<T> {
+    result.map_err(|mut err| {
+        let location = format!("byte {} of {} in {}", position, size, KafkaSerializable::type_name(None::<T>));
+        err.detail = Some(match err.detail.take() {
+            Some(detail) => format!("{}, within {}", detail, location),
+            None => format!("at {}", location)
+        });
+        err
+    })
+}
+
+// Decodes a value preceded by its size, which it has to take up exactly
+fn decode_with_size<T:KafkaSerializable>(reader: &mut io::Reader) -> KafkaResult<T> {
+    let size: i32 = try!(KafkaSerializable::decode(reader));
+    if size < 0 {
+        fail!((MalformedResponseError, "Negative size"));
+    }
+
+    let mut counting_reader = CountingReader::new(reader, size as uint);
+    let result = KafkaSerializable::decode(&mut counting_reader);
+    let value = try!(locate_error(result, counting_reader.position(), size as uint));
+
+    if counting_reader.limit() != 0 {
+        fail!((MalformedResponseError, "Less data read than specified"));
+    }
+    Ok(value)
+}
+
+pub trait KafkaSerializable {
+    fn encode(&self, writer: &mut io::Writer) -> KafkaResult<()>;
+    fn decode(reader: &mut io::Reader) -> KafkaResult<Self>;
+    fn size(&self) -> KafkaResult<i32>;
+
+    /// What decode errors call the type.
+    fn type_name(_: Option<Self>) -> &'static str { "value" }
+
+    /// Like `encode`, but size prefixes are backpatched once their contents
+    /// have been written instead of being computed up front with `size()`.
+    /// Containers override this to pass the encoder down to their fields.
+    fn encode_buffered(&self, encoder: &mut BufferEncoder) -> KafkaResult<()> {
+        self.encode(encoder)
+    }
+}
+
+/// A growable output buffer for single-pass encoding with `encode_buffered`.
+pub struct BufferEncoder {
+    buffer: Vec<u8>
+}
+
+impl BufferEncoder {
+    pub fn new() -> BufferEncoder {
+        BufferEncoder::with_capacity(128)
+    }
+
+    pub fn with_capacity(capacity: uint) -> BufferEncoder {
+        BufferEncoder { buffer: Vec::with_capacity(capacity) }
+    }
+
+    /// Encodes into an existing buffer, such as one checked out of a `BufferPool`.
+    pub fn from_buffer(mut buffer: Vec<u8>) -> BufferEncoder {
+        buffer.clear();
+        BufferEncoder { buffer: buffer }
+    }
+
+    /// Writes a placeholder for an i32 size prefix, returning its position.
+    pub fn reserve_size(&mut self) -> uint {
+        let position = self.buffer.len();
+        self.buffer.push_all(&[0, 0, 0, 0]);
+        position
+    }
+
+    /// Fills in a placeholder with the number of bytes written after it.
+    pub fn patch_size(&mut self, position: uint) -> KafkaResult<()> {
+        let size = try!(array_length(self.buffer.len() - position - 4));
+        let bytes = [(size >> 24) as u8, (size >> 16) as u8, (size >> 8) as u8, size as u8];
+        let slot = self.buffer.as_mut_slice();
+        for i in range(0, 4) {
+            slot[position + i] = bytes[i];
+        }
+        Ok(())
+    }
+
+    pub fn get_ref(&self) -> &[u8] {
+        self.buffer.as_slice()
+    }
+
+    pub fn unwrap(self) -> Vec<u8> {
+        self.buffer
+    }
+}
+
+impl Writer for BufferEncoder {
+    fn write(&mut self, buf: &[u8]) -> io::IoResult<()> {
+        self.buffer.push_all(buf);
+        Ok(())
+    }
+}
+
+pub fn encode_to_vec<T:KafkaSerializable>(value: &T) -> KafkaResult<Vec<u8>> {
+    let mut encoder = BufferEncoder::new();
+    try!(value.encode_buffered(&mut encoder));
+    Ok(encoder.unwrap())
+}
+
+/// Decodes a value which must take up the whole of `bytes`. Running out of
+/// bytes part way through is reported as a malformed response.
+pub fn decode_from_slice<T:KafkaSerializable>(bytes: &[u8]) -> KafkaResult<T> {
+    let mut reader = io::BufReader::new(bytes);
+    let result = try!(KafkaSerializable::decode(&mut reader).map_err(|err| match err.kind {
+        TransportError(io::IoError { kind: io::EndOfFile, .. }) => KafkaError {
+            kind: ProtocolError(MalformedResponseError),
+            desc: "Truncated data",
+            ..err
+        },
+        _ => err
+    }));
+    if !reader.eof() {
+        fail!((MalformedResponseError, "Trailing data after decoded value"));
+    }
+    Ok(result)
+}
+
+#[deriving(Show, PartialEq, Eq, Clone)]
+pub struct WithSize<T:KafkaSerializable>(pub T);
+
+impl KafkaSerializable for i8 {
+    fn encode(&self, writer: &mut io::Writer) -> KafkaResult<()> {
+        Ok(try!(writer.write_i8(*self)))
+    }
+
+    fn decode(reader: &mut io::Reader) -> KafkaResult<i8> {
+        Ok(try!(reader.read_i8()))
+    }
+
+    #[inline]
+    fn size(&self) -> KafkaResult<i32> {
+        Ok(1)
+    }
+}
+
+impl KafkaSerializable for i16 {
+    fn encode(&self, writer: &mut io::Writer) -> KafkaResult<()> {
+        Ok(try!(writer.write_be_i16(*self)))
+    }
+
+    fn decode(reader: &mut io::Reader) -> KafkaResult<i16> {
+        Ok(try!(reader.read_be_i16()))
+    }
+
+    #[inline]
+    fn size(&self) -> KafkaResult<i32> {
+        Ok(2)
+    }
+}
+
+impl KafkaSerializable for i32 {
+    fn encode(&self, writer: &mut io::Writer) -> KafkaResult<()> {
+        Ok(try!(writer.write_be_i32(*self)))
+    }
+
+    fn decode(reader: &mut io::Reader) -> KafkaResult<i32> {
+        Ok(try!(reader.read_be_i32()))
+    }
+
+    #[inline]
+    fn size(&self) -> KafkaResult<i32> {
+        Ok(4)
+    }
+}
+
+impl KafkaSerializable for i64 {
+    fn encode(&self, writer: &mut io::Writer) -> KafkaResult<()> {
+        Ok(try!(writer.write_be_i64(*self)))
+    }
+
+    fn decode(reader: &mut io::Reader) -> KafkaResult<i64> {
+        Ok(try!(reader.read_be_i64()))
+    }
+
+    #[inline]
+    fn size(&self) -> KafkaResult<i32> {
+        Ok(8)
+    }
+}
+
+impl KafkaSerializable for String {
+    fn encode(&self, writer: &mut io::Writer) -> KafkaResult<()> {
+        try!(try!(string_length(self.as_slice())).encode(writer));
+        Ok(try!(writer.write_str(self.as_slice())))
+    }
+
+    fn decode(reader: &mut io::Reader) -> KafkaResult<String> {
+        let size: i16 = try!(KafkaSerializable::decode(reader));
+        if size < 0 {
+            fail!((MalformedResponseError, "Negative string length"));
+        }
+        let buffer = try!(read_bytes(reader, size as uint));
+
+        Ok(try!(match String::from_utf8(buffer) {
+            Ok(string) => Ok(string),
+            Err(_) => Err((MalformedResponseError, "Malformed UTF8 response"))
+        }))
+    }
+
+    #[inline]
+    fn size(&self) -> KafkaResult<i32> {
+        Ok(2 + try!(string_length(self.as_slice())) as i32)
+    }
+}
+
+impl KafkaSerializable for Option<String> {
+    fn encode(&self, writer: &mut io::Writer) -> KafkaResult<()> {
+        match *self {
+            Some(ref string) => {
+                try!(try!(string_length(string.as_slice())).encode(writer));
+                Ok(try!(writer.write_str(string.as_slice())))
+            },
+            None => (-1i16).encode(writer)
+        }
+    }
+
+    fn decode(reader: &mut io::Reader) -> KafkaResult<Option<String>> {
+        let size: i16 = try!(KafkaSerializable::decode(reader));
+
+        if size < -1 {
+            fail!((MalformedResponseError, "Negative string length"));
+        }
+        if size == -1 {
+            Ok(None)
+        } else {
+            let buffer = try!(read_bytes(reader, size as uint));
+
+            Ok(try!(match String::from_utf8(buffer) {
+                Ok(string) => Ok(Some(string)),
+                Err(_) => Err((MalformedResponseError, "Malformed UTF8 response"))
+            }))
+        }
+    }
+
+    #[inline]
+    fn size(&self) -> KafkaResult<i32> {
+        Ok(2 + match *self {
+            Some(ref string) => {
+                try!(string_length(string.as_slice())) as i32
+            },
+            None => 0
+        })
+    }
+}
+
+impl <T:KafkaSerializable> KafkaSerializable for Vec<T> {
+    fn encode(&self, writer: &mut io::Writer) -> KafkaResult<()> {
+        try!(try!(array_length(self.len())).encode(writer));
+        for element in self.iter() {
+            try!(element.encode(writer))
+        }
+        Ok(())
+    }
+
+    fn encode_buffered(&self, encoder: &mut BufferEncoder) -> KafkaResult<()> {
+        try!(try!(array_length(self.len())).encode(encoder));
+        for element in self.iter() {
+            try!(element.encode_buffered(encoder))
+        }
+        Ok(())
+    }
+
+    fn decode(reader: &mut io::Reader) -> KafkaResult<Vec<T>> {
+        let size: i32 = try!(KafkaSerializable::decode(reader));
+        if size < 0 {
+            fail!((MalformedResponseError, "Negative array size"));
+        }
+
+        let mut result = Vec::with_capacity(cmp::min(size as uint, MAX_PREALLOCATED_ELEMENTS));
+        for _ in range(0, size) {
+            result.push(try!(KafkaSerializable::decode(reader)))
+        }
+        Ok(result)
+    }
+
+    #[inline]
+    fn size(&self) -> KafkaResult<i32> {
+        let mut size = 4i32;
+        for element in self.iter() {
+            size = try!(add_size(size, try!(element.size())));
+        }
+        Ok(size)
+    }
+}
+
+impl KafkaSerializable for Vec<u8> {
+    fn encode(&self, writer: &mut io::Writer) -> KafkaResult<()> {
+        try!(try!(array_length(self.len())).encode(writer));
+        Ok(try!(writer.write(self.as_slice())))
+    }
+
+    fn decode(reader: &mut io::Reader) -> KafkaResult<Vec<u8>> {
+        let size: i32 = try!(KafkaSerializable::decode(reader));
+        if size < 0 {
+            fail!((MalformedResponseError, "Negative array size"));
+        }
+        read_bytes(reader, size as uint)
+    }
+
+    #[inline]
+    fn size(&self) -> KafkaResult<i32> {
+        add_size(4, try!(array_length(self.len())))
+    }
+}
+
+impl KafkaSerializable for Option<Vec<u8>> {
+    fn encode(&self, writer: &mut io::Writer) -> KafkaResult<()> {
+        match *self {
+            Some(ref vector) => {
+                try!(try!(array_length(vector.len())).encode(writer));
+                Ok(try!(writer.write(vector.as_slice())))
+            },
+            None => (-1i32).encode(writer)
+        }
+    }
+
+    fn decode(reader: &mut io::Reader) -> KafkaResult<Option<Vec<u8>>> {
+        let size: i32 = try!(KafkaSerializable::decode(reader));
+
+        if size < -1 {
+            fail!((MalformedResponseError, "Negative array size"));
+        }
+
+        if size == -1 {
+            Ok(None)
+        } else {
+            let vec = try!(read_bytes(reader, size as uint));
+            Ok(Some(vec))
+        }
+    }
+
+    #[inline]
+    fn size(&self) -> KafkaResult<i32> {
+        add_size(4, match *self {
+            Some(ref vector) => {
+                try!(array_length(vector.len()))
+            },
+            None => 0
+        })
+    }
+}
+
+/// A nullable array of replica lists, as CreatePartitions takes for the
+/// placement of new partitions.
+impl KafkaSerializable for Option<Vec<Vec<i32>>> {
+    fn encode(&self, writer: &mut io::Writer) -> KafkaResult<()> {
+        match *self {
+            Some(ref replicas) => replicas.encode(writer),
+            None => (-1i32).encode(writer)
+        }
+    }
+
+    fn decode(reader: &mut io::Reader) -> KafkaResult<Option<Vec<Vec<i32>>>> {
+        decode_nullable_array(reader)
+    }
+
+    #[inline]
+    fn size(&self) -> KafkaResult<i32> {
+        match *self {
+            Some(ref replicas) => replicas.size(),
+            None => Ok(4)
+        }
+    }
+}
+
+/// A nullable array of names, as DescribeConfigs takes to mean all of them.
+impl KafkaSerializable for Option<Vec<String>> {
+    fn encode(&self, writer: &mut io::Writer) -> KafkaResult<()> {
+        match *self {
+            Some(ref names) => names.encode(writer),
+            None => (-1i32).encode(writer)
+        }
+    }
+
+    fn decode(reader: &mut io::Reader) -> KafkaResult<Option<Vec<String>>> {
+        decode_nullable_array(reader)
+    }
+
+    #[inline]
+    fn size(&self) -> KafkaResult<i32> {
+        match *self {
+            Some(ref names) => names.size(),
+            None => Ok(4)
+        }
+    }
+}
+
+fn decode_nullable_array<T:KafkaSerializable>(reader: &mut io::Reader) -> KafkaResult<Option<Vec<T>>> {
+    let size: i32 = try!(KafkaSerializable::decode(reader));
+
+    if size < -1 {
+        fail!((MalformedResponseError, "Negative array size"));
+    }
+
+    if size == -1 {
+        Ok(None)
+    } else {
+        let mut result = Vec::with_capacity(cmp::min(size as uint, MAX_PREALLOCATED_ELEMENTS));
+        for _ in range(0, size) {
+            result.push(try!(KafkaSerializable::decode(reader)))
+        }
+        Ok(Some(result))
+    }
+}
+
+impl <T:KafkaSerializable> KafkaSerializable for WithSize<T>  {
+    fn encode(&self, writer: &mut io::Writer) -> KafkaResult<()> {
+        try!(try!(self.0.size()).encode(writer));
+        self.0.encode(writer)
+    }
+
+    fn encode_buffered(&self, encoder: &mut BufferEncoder) -> KafkaResult<()> {
+        let position = encoder.reserve_size();
+        try!(self.0.encode_buffered(encoder));
+        encoder.patch_size(position)
+    }
+
+    fn decode(reader: &mut io::Reader) -> KafkaResult<WithSize<T>> {
+        Ok(WithSize(try!(decode_with_size(reader))))
+    }
+
+    #[inline]
+    fn size(&self) -> KafkaResult<i32> {
+        add_size(4, try!(self.0.size()))
+    }
+
+    fn type_name(_: Option<WithSize<T>>) -> &'static str { KafkaSerializable::type_name(None::<T>) }
+}
+
+/// Memory held by a decoded message, for finding out which topics a
+/// consumer's memory goes to.
+pub trait HeapSize {
+    /// Bytes allocated on the heap for the strings, vectors and payloads
+    /// this value owns, not counting the value itself.
+    fn heap_size(&self) -> uint;
+}
+
+impl HeapSize for i8 { fn heap_size(&self) -> uint { 0 } }
+impl HeapSize for i16 { fn heap_size(&self) -> uint { 0 } }
+impl HeapSize for i32 { fn heap_size(&self) -> uint { 0 } }
+impl HeapSize for i64 { fn heap_size(&self) -> uint { 0 } }
+
+impl HeapSize for String {
+    fn heap_size(&self) -> uint { self.capacity() }
+}
+
+impl HeapSize for Vec<u8> {
+    fn heap_size(&self) -> uint { self.capacity() }
+}
+
+impl <T:HeapSize> HeapSize for Vec<T> {
+    fn heap_size(&self) -> uint {
+        self.iter().fold(self.capacity() * mem::size_of::<T>(), |size, element| size + element.heap_size())
+    }
+}
+
+impl <T:HeapSize> HeapSize for Option<T> {
+    fn heap_size(&self) -> uint {
+        self.as_ref().map_or(0, |value| value.heap_size())
+    }
+}
+
+impl <T:HeapSize> HeapSize for WithSize<T> {
+    fn heap_size(&self) -> uint { self.0.heap_size() }
+}
+
+
+
+#[deriving(Show, PartialEq, Eq, Clone)]
+pub enum Error {
+    Unknown = -1,
+    NoError = 0,
+    OffsetOutOfRange = 1,
+    InvalidMessage = 2,
+    UnknownTopicOrPartition = 3,
+    InvalidMessageSize = 4,
+    LeaderNotAvailable = 5,
+    NotLeaderForPartition = 6,
+    RequestTimedOut = 7,
+    BrokerNotAvailable = 8,
+    ReplicaNotAvailable = 9,
+    MessageSizeTooLarge = 10,
+    StaleControllerEpochCode = 11,
+    OffsetMetadataTooLargeCode = 12,
+    OffsetsLoadInProgressCode = 14,
+    ConsumerCoordinatorNotAvailableCode = 15,
+    NotCoordinatorForConsumerCode = 16,
+    IllegalGenerationCode = 22,
+    InconsistentGroupProtocolCode = 23,
+    UnknownMemberIdCode = 25,
+    RebalanceInProgressCode = 27,
+    TopicAlreadyExistsCode = 36,
+    InvalidPartitionsCode = 37,
+    InvalidReplicationFactorCode = 38,
+    InvalidReplicaAssignmentCode = 39,
+    InvalidConfigCode = 40,
+    NotControllerCode = 41,
+    PolicyViolationCode = 44,
+    MemberIdRequiredCode = 79,
+    FencedInstanceIdCode = 82
+}
+
+impl FromPrimitive for Error {
+    fn from_i64(n: i64) -> Option<Error> {
+        match n {
+            0 => Some(NoError),
+            1 => Some(OffsetOutOfRange),
+            2 => Some(InvalidMessage),
+            3 => Some(UnknownTopicOrPartition),
+            4 => Some(InvalidMessageSize),
+            5 => Some(LeaderNotAvailable),
+            6 => Some(NotLeaderForPartition),
+            7 => Some(RequestTimedOut),
+            8 => Some(BrokerNotAvailable),
+            9 => Some(ReplicaNotAvailable),
+            10 => Some(MessageSizeTooLarge),
+            11 => Some(StaleControllerEpochCode),
+            12 => Some(OffsetMetadataTooLargeCode),
+            14 => Some(OffsetsLoadInProgressCode),
+            15 => Some(ConsumerCoordinatorNotAvailableCode),
+            16 => Some(NotCoordinatorForConsumerCode),
+            22 => Some(IllegalGenerationCode),
+            23 => Some(InconsistentGroupProtocolCode),
+            25 => Some(UnknownMemberIdCode),
+            27 => Some(RebalanceInProgressCode),
+            36 => Some(TopicAlreadyExistsCode),
+            37 => Some(InvalidPartitionsCode),
+            38 => Some(InvalidReplicationFactorCode),
+            39 => Some(InvalidReplicaAssignmentCode),
+            40 => Some(InvalidConfigCode),
+            41 => Some(NotControllerCode),
+            44 => Some(PolicyViolationCode),
+            79 => Some(MemberIdRequiredCode),
+            82 => Some(FencedInstanceIdCode),
+            -1 => Some(Unknown),
+            _ => None
+        }
+    }
+
+    fn from_u64(_: u64) -> Option<Error> {
+        panic!("Can't convert unsigned integer to Error")
+    }
+}
+
+/// Turns a response's error code into a `BrokerError`, unless it's `NoError`.
+pub fn check_error_code(code: i16) -> KafkaResult<()> {
+    let error: Error = FromPrimitive::from_i16(code).unwrap_or(Unknown);
+    if error != NoError {
+        fail!(error);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_fromprimitive() {
+    for &(n, expected) in [(0, Some(NoError)), (-1, Some(Unknown)), (20, None)].iter() {
+        let error: Option<Error> = FromPrimitive::from_i64(n);
+        assert_eq!(error, expected);
+    }
+}
+
+#[test]
+fn test_check_error_code() {
+    assert!(check_error_code(0).is_ok());
+    assert_eq!(check_error_code(6).unwrap_err().kind, BrokerError(NotLeaderForPartition));
+    assert_eq!(check_error_code(100).unwrap_err().kind, BrokerError(Unknown));
+}
+
+macro_rules! kafka_datastructures {
+    (
+        $(
+            struct $Name:ident {
+                $($name:ident: $t:ty),+
+            }
+        )+) => {
+        $(
+            #[deriving(Show, PartialEq, Eq, Clone)]
+            pub struct $Name {
+                $(pub $name: $t),+
+            }
+
+            impl KafkaSerializable for $Name {
+                fn encode(&self, writer: &mut Writer) -> KafkaResult<()> {
+                    $(try!(self.$name.encode(writer)));+
+                    Ok(())
+                }
+
+                fn encode_buffered(&self, encoder: &mut BufferEncoder) -> KafkaResult<()> {
+                    $(try!(self.$name.encode_buffered(encoder)));+
+                    Ok(())
+                }
+
+                fn decode(reader: &mut Reader) -> KafkaResult<$Name> {
+                    Ok($Name {
+                        $($name: try!(KafkaSerializable::decode(reader)),)+
+                    })
+                }
+
+                #[inline]
+                fn size(&self) -> KafkaResult<i32> {
+                    let mut size = 0i32;
+                    $(size = try!(add_size(size, try!(self.$name.size())));)+
+                    Ok(size)
+                }
+
+                fn type_name(_: Option<$Name>) -> &'static str { stringify!($Name) }
+            }
+
+            impl HeapSize for $Name {
+                fn heap_size(&self) -> uint {
+                    0 $(+ self.$name.heap_size())+
+                }
+            }
+        )+
+    };
+}
+
+macro_rules! require_non_empty {
+    ($topics:expr, $request:expr) => ({
+        if $topics.is_empty() {
+            fail!((MalformedRequestError, concat!($request, " request has no topics")));
+        }
+        if $topics.iter().any(|topic| topic.partitions.is_empty()) {
+            fail!((MalformedRequestError, concat!($request, " request has a topic without partitions")));
+        }
+        Ok(())
+    })
+}
+
+// The macros above must be defined before the modules using them
+pub mod message;
+pub mod metadata;
+pub mod produce;
+pub mod offset;
+pub mod fetch;
+pub mod consumer_metadata;
+pub mod offset_commit;
+pub mod offset_fetch;
+pub mod describe_configs;
+pub mod api_versions;
+pub mod consumer_protocol;
+pub mod heartbeat;
+pub mod join_group;
+pub mod sync_group;
+pub mod describe_groups;
+pub mod list_groups;
+pub mod create_topics;
+pub mod delete_topics;
+pub mod delete_records;
+pub mod create_partitions;
+pub mod alter_configs;
+
+pub trait Request: KafkaSerializable {
+    fn api_key(_: Option<Self>) -> i16;
+
+    fn api_version(_: Option<Self>) -> i16 { 0 }
+
+    /// Rejects requests which can't mean anything to the broker. Called
+    /// before every request is encoded.
+    fn validate(&self) -> KafkaResult<()> { Ok(()) }
+
+    /// How many topics the request covers, for tracing.
+    fn topic_count(&self) -> uint { 0 }
+
+    /// Whether the broker answers this request at all. When it doesn't, the
+    /// connection returns `Response::unanswered` instead of reading one.
+    fn expects_response(&self) -> bool { true }
+}
+
+#[deriving(Show, PartialEq, Eq)]
+pub struct RequestMessage<T:Request> {
+    // api_key: i16,
+    // api_version: i16,
+    pub correlation_id: i32,
+    pub client_id: String,
+    pub request_message: T
+}
+
+impl <T:Request> KafkaSerializable for RequestMessage<T> {
+    fn encode(&self, writer: &mut io::Writer) -> KafkaResult<()> {
+        try!(self.request_message.validate());
+        try!(Request::api_key(None::<T>).encode(writer));
+        try!(Request::api_version(None::<T>).encode(writer));
+        try!(self.correlation_id.encode(writer));
+        try!(self.client_id.encode(writer));
+        self.request_message.encode(writer)
+    }
+
+    fn encode_buffered(&self, encoder: &mut BufferEncoder) -> KafkaResult<()> {
+        try!(self.request_message.validate());
+        try!(Request::api_key(None::<T>).encode(encoder));
+        try!(Request::api_version(None::<T>).encode(encoder));
+        try!(self.correlation_id.encode(encoder));
+        try!(self.client_id.encode(encoder));
+        self.request_message.encode_buffered(encoder)
+    }
+
+    fn decode(reader: &mut io::Reader) -> KafkaResult<RequestMessage<T>> {
+        let expected_api_key = Request::api_key(None::<T>);
+        let api_key: i16 = try!(KafkaSerializable::decode(reader));
+        if api_key != expected_api_key {
+            fail!((ProtocolMismatchError(expected_api_key as i32, api_key as i32), "Unexpected ApiKey"));
+        }
+
+        let expected_api_version = Request::api_version(None::<T>);
+        let api_version: i16 = try!(KafkaSerializable::decode(reader));
+        if api_version != expected_api_version {
+            fail!((ProtocolMismatchError(expected_api_version as i32, api_version as i32), "Unexpected API version"));
+        }
+
+        Ok(
+            RequestMessage{
+                correlation_id: try!(KafkaSerializable::decode(reader)),
+                client_id: try!(KafkaSerializable::decode(reader)),
+                request_message: try!(KafkaSerializable::decode(reader))
+            }
+        )
+    }
+
+    #[inline]
+    fn size(&self) -> KafkaResult<i32> {
+        let header = 2 + 2 + 4 + try!(self.client_id.size());
+        add_size(header, try!(self.request_message.size()))
+    }
+}
+
+pub trait Response: KafkaSerializable {
+    /// Stands in for the response to a request which gets none.
+    fn unanswered(_: Option<Self>) -> KafkaResult<Self> {
+        fail!((MalformedRequestError, "A response was expected for this request"))
+    }
+}
+
+
+#[deriving(Show, PartialEq, Eq)]
+pub struct ResponseMessage<T:Response> {
+    pub correlation_id: i32,
+    pub response: T
+}
+
+impl <T:Response> ResponseMessage<T> {
+    pub fn check_correlation_id(&self, expected: i32) -> KafkaResult<()> {
+        if self.correlation_id != expected {
+            fail!((ProtocolMismatchError(expected, self.correlation_id), "Unexpected correlation ID"));
+        }
+        Ok(())
+    }
+}
+
+impl <T:Response> KafkaSerializable for ResponseMessage<T> {
+    fn encode(&self, writer: &mut io::Writer) -> KafkaResult<()> {
+        try!(self.correlation_id.encode(writer));
+        self.response.encode(writer)
+    }
+
+    fn encode_buffered(&self, encoder: &mut BufferEncoder) -> KafkaResult<()> {
+        try!(self.correlation_id.encode(encoder));
+        self.response.encode_buffered(encoder)
+    }
+
+    fn decode(reader: &mut io::Reader) -> KafkaResult<ResponseMessage<T>> {
+        Ok(
+            ResponseMessage{
+                correlation_id: try!(KafkaSerializable::decode(reader)),
+                response: try!(KafkaSerializable::decode(reader))
+            }
+        )
+    }
+
+    #[inline]
+    fn size(&self) -> KafkaResult<i32> {
+        add_size(4, try!(self.response.size()))
+    }
+
+    fn type_name(_: Option<ResponseMessage<T>>) -> &'static str { KafkaSerializable::type_name(None::<T>) }
+}
+
+pub trait IsRequestOrResponse: KafkaSerializable {}
+impl <T:Response> IsRequestOrResponse for ResponseMessage<T> {}
+impl <T:Request> IsRequestOrResponse for RequestMessage<T> {}
+
+#[deriving(Show, PartialEq, Eq)]
+pub struct RequestOrResponse<T:IsRequestOrResponse>(pub T);
+
+impl <T:IsRequestOrResponse> KafkaSerializable for RequestOrResponse<T>  {
+    fn encode(&self, writer: &mut io::Writer) -> KafkaResult<()> {
+        try!(try!(self.0.size()).encode(writer));
+        self.0.encode(writer)
+    }
+
+    fn encode_buffered(&self, encoder: &mut BufferEncoder) -> KafkaResult<()> {
+        let position = encoder.reserve_size();
+        try!(self.0.encode_buffered(encoder));
+        encoder.patch_size(position)
+    }
+
+    fn decode(reader: &mut io::Reader) -> KafkaResult<RequestOrResponse<T>> {
+        Ok(RequestOrResponse(try!(decode_with_size(reader))))
+    }
+
+    #[inline]
+    fn size(&self) -> KafkaResult<i32> {
+        add_size(4, try!(self.0.size()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate test;
+    extern crate core;
+    use std::fmt;
+    use std::io::{MemWriter, MemReader};
+    use std::mem;
+    use flate;
+    use super::*;
+    use super::offset::v1::{OffsetRequestPartitionV1, OffsetRequestTopicV1, OffsetRequestV1, PartitionOffsetV1,
+                            OffsetResponseTopicV1, OffsetResponseV1};
+    use super::metadata::v1::{BrokerV1, MetadataRequestV1, MetadataResponseV1, NO_CONTROLLER};
+    use super::heartbeat::v0::{HeartbeatRequest, HeartbeatResponse};
+    use super::join_group::v0::{JoinGroupRequestV0, JoinGroupMemberV0, JoinGroupResponseV0};
+    use super::join_group::v1::JoinGroupRequestV1;
+    use super::sync_group::v0::{SyncGroupRequestV0, SyncGroupResponseV0};
+    use types::*;
+    use versions;
+
+    #[test]
+    fn test_full_metadata_request() {
+        let mut writer = MemWriter::new();
+
+        let request = RequestOrResponse(RequestMessage {
+                correlation_id: 0,
+                client_id: String::from_str("Client"),
+                request_message: MetadataRequest{
+                    topic_names: vec![String::from_str("test")]
+                }
+            }
+        );
+
+        request.encode(&mut writer).ok().unwrap();
+
+        let expected = [
+            0x00, 0x00, 0x00, 26,
+            0x00,    3, // ApiKey
+            0x00, 0x00, // Api Version
+            0x00, 0x00, 0x00, 0x00, // Correlation ID
+            0x00,    6,  'C' as u8,  'l' as u8, 'i' as u8, 'e' as u8, 'n' as u8, 't' as u8,
+            0x00, 0x00, 0x00,    1,
+            0x00,    4,  't' as u8,  'e' as u8, 's' as u8, 't' as u8
+        ];
+
+        assert_eq!(expected.as_slice(), writer.get_ref());
+    }
+
+    #[test]
+    fn test_encode_to_vec() {
+        let request = RequestOrResponse(RequestMessage {
+            correlation_id: 0,
+            client_id: String::from_str("Client"),
+            request_message: MetadataRequest { topic_names: vec![String::from_str("test")] }
+        });
+
+        let mut writer = MemWriter::new();
+        request.encode(&mut writer).ok().unwrap();
+        assert_eq!(encode_to_vec(&request).ok().unwrap().as_slice(), writer.get_ref());
+    }
+
+    #[test]
+    fn test_request_api_key_mismatch() {
+        let mut writer = MemWriter::new();
+        RequestMessage {
+            correlation_id: 0,
+            client_id: String::from_str("Client"),
+            request_message: ConsumerMetadataRequest{ group: String::from_str("group") }
+        }.encode(&mut writer).ok().unwrap();
+
+        let mut reader = MemReader::new(writer.unwrap());
+        let result: KafkaResult<RequestMessage<MetadataRequest>> = KafkaSerializable::decode(&mut reader);
+        assert_eq!(result.unwrap_err().kind, ProtocolError(ProtocolMismatchError(3, 10)));
+    }
+
+    #[test]
+    fn test_request_validation() {
+        let fetch = RequestMessage {
+            correlation_id: 0,
+            client_id: String::from_str("Client"),
+            request_message: FetchRequest {
+                replica_id: -1,
+                max_wait_time: 100,
+                min_bytes: 1,
+                elements: vec![FetchRequestTopic { name: String::from_str("test"), partitions: vec![] }]
+            }
+        };
+        assert_eq!(encode_to_vec(&fetch).unwrap_err().desc, "Fetch request has a topic without partitions");
+
+        let metadata = RequestMessage {
+            correlation_id: 0,
+            client_id: String::from_str("Client"),
+            request_message: MetadataRequest { topic_names: vec![] }
+        };
+        assert!(encode_to_vec(&metadata).is_ok());
+    }
+
+    #[test]
+    fn test_correlation_id_mismatch() {
+        let response = ResponseMessage {
+            correlation_id: 7,
+            response: ConsumerMetadataResponse {
+                error_code: 0,
+                coordinator_id: 1,
+                coordinator_host: String::from_str("localhost"),
+                coordinator_port: 9092
+            }
+        };
+
+        assert!(response.check_correlation_id(7).is_ok());
+        assert_eq!(response.check_correlation_id(8).unwrap_err().kind, ProtocolError(ProtocolMismatchError(8, 7)));
+    }
+
+    fn write_read_test<T:KafkaSerializable + Eq + fmt::Show>(input: T) {
+        let mut writer = MemWriter::new();
+        input.encode(&mut writer).ok().unwrap();
+        assert_eq!(writer.get_ref().len() as i32, input.size().ok().unwrap());
+
+        let mut encoder = BufferEncoder::new();
+        input.encode_buffered(&mut encoder).ok().unwrap();
+        assert_eq!(encoder.get_ref(), writer.get_ref());
+        assert_eq!(decode_from_slice::<T>(writer.get_ref()).ok().unwrap(), input);
+
+        let mut reader = MemReader::new(writer.unwrap());
+        let result = KafkaSerializable::decode(&mut reader).ok().unwrap();
+        assert!(reader.eof());
+        assert_eq!(input, result);
+    }
+
+    #[test]
+    fn test_decode_trailing_data() {
+        let result: KafkaResult<i16> = decode_from_slice(&[0x00u8, 0x01, 0x02]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_i8() {
+        for i in range(core::i8::MIN, core::i8::MAX) {
+            write_read_test(i);
+        }
+    }
+
+    #[test]
+    fn test_i16() {
+        for i in range(-10, 10i16) {
+            write_read_test(i);
+        }
+    }
+
+    #[test]
+    fn test_i32() {
+        for i in range(-10, 10i32) {
+            write_read_test(i);
+        }
+    }
+
+    #[test]
+    fn test_i64() {
+        for i in range(-10, 10i64) {
+            write_read_test(i);
+        }
+    }
+
+    #[test]
+    fn test_string() {
+        write_read_test(String::from_str("Interesting"));
+    }
+
+    #[test]
+    fn test_option_string() {
+        write_read_test(Some(String::from_str("Interesting")));
+        let none_test: Option<String> = None;
+        write_read_test(none_test);
+    }
+
+    #[test]
+    fn test_string_too_long() {
+        let input = String::from_char(40000, 'a');
+        assert!(input.size().is_err());
+        assert!(input.encode(&mut MemWriter::new()).is_err());
+        assert!(Some(input).encode(&mut MemWriter::new()).is_err());
+    }
+
+    #[test]
+    fn test_vec() {
+        write_read_test(vec![-1, 0, 1, 2, 3, 4, 5i16]);
+    }
+
+    #[test]
+    fn test_vec_u8() {
+        write_read_test(vec![0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10u8]);
+    }
+
+    #[test]
+    fn test_option_vec_u8() {
+        write_read_test(Some(vec![0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10u8]));
+        let none_test: Option<Vec<u8>> = None;
+        write_read_test(none_test);
+    }
+
+    #[test]
+    fn test_oversized_length_prefix() {
+        let input = vec![0x7f, 0xff, 0xff, 0xff, 0x00, 0x01];
+
+        let result: KafkaResult<Vec<i16>> = KafkaSerializable::decode(&mut MemReader::new(input.clone()));
+        assert!(result.is_err());
+
+        let result: KafkaResult<Vec<u8>> = KafkaSerializable::decode(&mut MemReader::new(input));
+        assert!(result.is_err());
+    }
+
+    fn test_message(value: &str) -> MessageSetElement {
+        MessageSetElement {
+            offset: 0,
+            message: WithSize(Message {
+                crc: 0,
+                magic_byte: 0,
+                attributes: 0,
+                key: None,
+                value: Some(value.as_bytes().to_vec())
+            })
+        }
+    }
+
+    #[test]
+    fn test_message_set() {
+        write_read_test(WithSize(MessageSet {
+            messages: vec![test_message("first"), test_message("second")]
+        }));
+    }
+
+    #[test]
+    fn test_message_set_truncated() {
+        let message_set = MessageSet { messages: vec![test_message("first"), test_message("second")] };
+        let complete_size = message_set.size().ok().unwrap();
+
+        let mut writer = MemWriter::new();
+        message_set.encode(&mut writer).ok().unwrap();
+        test_message("cut short").encode(&mut writer).ok().unwrap();
+        let mut buffer = writer.unwrap();
+        buffer.truncate(complete_size as uint + 20);
+
+        let mut reader = MemReader::new(buffer.clone());
+        let (result, consumed) = MessageSet::decode_sized(&mut reader, buffer.len() as i32).ok().unwrap();
+        assert!(reader.eof());
+        assert_eq!(result, message_set);
+        assert_eq!(consumed, complete_size);
+    }
+
+    #[test]
+    fn test_message_set_negative_size() {
+        let mut reader = MemReader::new(vec![]);
+        let err = MessageSet::decode_sized(&mut reader, -1).unwrap_err();
+        assert_eq!(err.kind, ProtocolError(MalformedResponseError));
+    }
+
+    #[test]
+    fn test_message_set_builder() {
+        let mut builder = MessageSetBuilder::with_max_bytes(70);
+        builder.append(Some(b"key".to_vec()), Some(b"value".to_vec())).ok().unwrap();
+        // 12 bytes of offset and size, 14 of message header, 3 of key and 5 of value
+        assert_eq!(builder.size(), 34);
+        assert!(builder.append(None, Some(b"too long to fit".to_vec())).is_err());
+        builder.append(None, None).ok().unwrap();
+        assert_eq!(builder.len(), 2);
+
+        let message_set = builder.build();
+        assert_eq!(message_set.size().ok().unwrap(), 34 + 26);
+        let WithSize(ref message) = message_set.messages[0].message;
+        assert_eq!(message_set.messages[0].offset, 0);
+        assert_eq!(message.crc, message.compute_crc().ok().unwrap());
+    }
+
+    #[test]
+    fn test_message_set_iter() {
+        let message_set = MessageSet { messages: vec![test_message("first"), test_message("second")] };
+        let mut writer = MemWriter::new();
+        message_set.encode(&mut writer).ok().unwrap();
+        let buffer = writer.unwrap();
+
+        let mut iter = MessageSetIter::from_slice(buffer.as_slice());
+        assert_eq!(iter.next().unwrap().ok().unwrap(), test_message("first"));
+        assert_eq!(iter.next().unwrap().ok().unwrap(), test_message("second"));
+        assert!(iter.next().is_none());
+        assert_eq!(iter.consumed() as uint, buffer.len());
+    }
+
+    #[test]
+    fn test_decode_error_position() {
+        // A Broker whose host claims 10 bytes but only has 2, wrapped in a 9 byte WithSize
+        let input = [0x00u8, 0x00, 0x00, 0x09, 0x00, 0x00, 0x00, 0x01, 0x00, 0x0a, 'a' as u8, 'b' as u8, 0x00];
+        let result: KafkaResult<WithSize<Broker>> = decode_from_slice(&input);
+        assert_eq!(result.unwrap_err().detail, Some(String::from_str("at byte 9 of 9 in Broker")));
+
+        // A 17 byte message set holding a 5 byte message which stops after its magic byte
+        let input = [0x00u8, 0x00, 0x00, 0x11, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+                     0x00, 0x00, 0x00, 0x05, 0x00, 0x00, 0x00, 0x00, 0x00];
+        let result: KafkaResult<WithSize<MessageSet>> = decode_from_slice(&input);
+        assert_eq!(result.unwrap_err().detail,
+                   Some(String::from_str("at byte 5 of 5 in Message, within byte 17 of 17 in MessageSet")));
+    }
+
+    // Frames which a broker should never send, paired with the error each has to
+    // produce. Decoding any of them must fail cleanly rather than panic.
+    fn assert_malformed<T:KafkaSerializable>(name: &str, input: &[u8], kind: ErrorKind) {
+        match decode_from_slice::<T>(input) {
+            Ok(_) => panic!("{} decoded successfully", name),
+            Err(err) => assert!(err.kind == kind, "{}: expected {}, got {}", name, kind, err.kind)
+        }
+    }
+
+    #[test]
+    fn test_malformed_frames() {
+        let malformed = ProtocolError(MalformedResponseError);
+
+        assert_malformed::<String>("negative string length", &[0xff, 0xfe], malformed.clone());
+        assert_malformed::<Option<String>>("string length below -1", &[0xff, 0xfe], malformed.clone());
+        assert_malformed::<String>("truncated string", &[0x00, 0x05, 'a' as u8, 'b' as u8], malformed.clone());
+        assert_malformed::<String>("bad UTF-8", &[0x00, 0x02, 0xc3, 0x28], malformed.clone());
+        assert_malformed::<Option<String>>("bad UTF-8 in nullable string", &[0x00, 0x01, 0xff], malformed.clone());
+        assert_malformed::<i32>("truncated integer", &[0x00, 0x01], malformed.clone());
+
+        assert_malformed::<Vec<i32>>("negative array length", &[0xff, 0xff, 0xff, 0xfe], malformed.clone());
+        assert_malformed::<Vec<i32>>("over-long array", &[0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x01], malformed.clone());
+        assert_malformed::<Vec<u8>>("over-long bytes", &[0x7f, 0xff, 0xff, 0xff, 0x00], malformed.clone());
+        assert_malformed::<Option<Vec<u8>>>("bytes length below -1", &[0xff, 0xff, 0xff, 0xfe], malformed.clone());
+
+        assert_malformed::<WithSize<i16>>("negative WithSize", &[0xff, 0xff, 0xff, 0xff, 0x00, 0x01], malformed.clone());
+        assert_malformed::<WithSize<i16>>("WithSize smaller than contents", &[0x00, 0x00, 0x00, 0x01, 0x00, 0x01], malformed.clone());
+        assert_malformed::<WithSize<i16>>("WithSize larger than contents", &[0x00, 0x00, 0x00, 0x03, 0x00, 0x01, 0x00], malformed.clone());
+        assert_malformed::<WithSize<MessageSet>>("WithSize beyond the frame", &[0x00, 0x00, 0x01, 0x00, 0x00], malformed.clone());
+
+        assert_malformed::<RequestOrResponse<ResponseMessage<MetadataResponse>>>(
+            "empty response", &[0x00, 0x00, 0x00, 0x00], malformed.clone());
+        assert_malformed::<ResponseMessage<MetadataResponse>>(
+            "metadata with truncated broker", &[0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00], malformed);
+    }
+
+    #[test]
+    fn test_option_withsize() {
+        write_read_test(WithSize(vec![0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10u8]));
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b"123456789"), 0xcbf43926);
+        assert_eq!(crc32(&[]), 0);
+    }
+
+    #[test]
+    fn test_message_crc() {
+        let message = Message::new(Some(b"key".to_vec()), None).ok().unwrap();
+        assert_eq!(message.crc, message.compute_crc().ok().unwrap());
+        assert!(message.crc != Message::new(Some(b"key".to_vec()), Some(vec![])).ok().unwrap().crc);
+    }
+
+    #[test]
+    fn test_produce_results() {
+        let partition = |partition: i32, error_code: i16, offset: i64| ProduceResponsePartition {
+            partition: partition, error_code: error_code, offset: offset
+        };
+        let response = ProduceResponse { topics: vec![
+            ProduceResponseTopic { name: String::from_str("a"), partitions: vec![partition(0, 0, 12), partition(1, 6, -1)] },
+            ProduceResponseTopic { name: String::from_str("b"), partitions: vec![partition(2, 99, -1)] }
+        ]};
+
+        assert_eq!(response.results().collect::<Vec<(TopicPartition, Result<i64, Error>)>>(), vec![
+            (TopicPartition::new("a", 0), Ok(12)),
+            (TopicPartition::new("a", 1), Err(NotLeaderForPartition)),
+            (TopicPartition::new("b", 2), Err(Unknown))]);
+    }
+
+    #[test]
+    fn test_fetch_messages() {
+        // A gzipped message set holding "hi" at offset 5 and "you" at offset 6
+        let gzipped = vec![
+            0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x03, 0x63, 0x60, 0x00, 0x03, 0x56, 0x20,
+            0x16, 0x80, 0x30, 0xff, 0x03, 0x01, 0x90, 0x62, 0xca, 0xc8, 0x84, 0xf0, 0x19, 0xd8, 0x80, 0x58,
+            0x10, 0x45, 0x8e, 0xb9, 0x32, 0xbf, 0x14, 0x00, 0xfb, 0xed, 0x61, 0xdc, 0x39, 0x00, 0x00, 0x00];
+        let mut wrapper = Message { crc: 0, magic_byte: 0, attributes: GZIP_COMPRESSION, key: None, value: Some(gzipped) };
+        wrapper.crc = wrapper.compute_crc().ok().unwrap();
+
+        let response = FetchResponse { topics: vec![FetchResponseTopic {
+            name: String::from_str("events"),
+            partitions: vec![FetchResponsePartition {
+                partition: 1,
+                error_code: 0,
+                highwater_mark_offset: 7,
+                messages: WithSize(MessageSet { messages: vec![
+                    test_message("first"),
+                    MessageSetElement { offset: 6, message: WithSize(wrapper) }
+                ]})
+            }]
+        }]};
+
+        let partition = TopicPartition::new("events", 1);
+        assert_eq!(response.messages().ok().unwrap().collect::<Vec<FetchedMessage>>(), vec![
+            (partition.clone(), 0, None, Some(b"first".to_vec())),
+            (partition.clone(), 5, None, Some(b"hi".to_vec())),
+            (partition.clone(), 6, None, Some(b"you".to_vec()))]);
+    }
+
+    fn gzip_wrapper(gzipped: Vec<u8>) -> Message {
+        let mut wrapper = Message { crc: 0, magic_byte: 0, attributes: GZIP_COMPRESSION, key: None, value: Some(gzipped) };
+        wrapper.crc = wrapper.compute_crc().ok().unwrap();
+        wrapper
+    }
+
+    // Frames a deflate stream as gzip, with no optional header fields
+    fn gzip(bytes: &[u8]) -> Vec<u8> {
+        let mut gzipped = vec![0x1fu8, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03];
+        gzipped.push_all(flate::deflate_bytes(bytes).unwrap().as_slice());
+        for &value in [crc32(bytes), bytes.len() as u32].iter() {
+            gzipped.push_all(&[value as u8, (value >> 8) as u8, (value >> 16) as u8, (value >> 24) as u8]);
+        }
+        gzipped
+    }
+
+    #[test]
+    fn test_gzip_checks() {
+        let inner = MessageSet { messages: vec![test_message("first")] };
+        let bytes = encode_to_vec(&inner).ok().unwrap();
+        assert_eq!(gzip_wrapper(gzip(bytes.as_slice())).decompress().ok().unwrap(), inner);
+
+        let mut corrupt = gzip(bytes.as_slice());
+        let crc_at = corrupt.len() - 8;
+        corrupt.as_mut_slice()[crc_at] ^= 0xff;
+        assert!(gzip_wrapper(corrupt).decompress().is_err());
+
+        let mut wrong_size = gzip(bytes.as_slice());
+        let size_at = wrong_size.len() - 4;
+        wrong_size.as_mut_slice()[size_at] ^= 0x01;
+        assert!(gzip_wrapper(wrong_size).decompress().is_err());
+    }
+
+    #[test]
+    fn test_fetch_nested_compression() {
+        let inner = MessageSet { messages: vec![test_message("first")] };
+        let wrapper = gzip_wrapper(gzip(encode_to_vec(&inner).ok().unwrap().as_slice()));
+        let nested = MessageSet { messages: vec![MessageSetElement { offset: 0, message: WithSize(wrapper) }] };
+        let outer = gzip_wrapper(gzip(encode_to_vec(&nested).ok().unwrap().as_slice()));
+
+        let response = FetchResponse { topics: vec![FetchResponseTopic {
+            name: String::from_str("events"),
+            partitions: vec![FetchResponsePartition {
+                partition: 0,
+                error_code: 0,
+                highwater_mark_offset: 1,
+                messages: WithSize(MessageSet { messages: vec![MessageSetElement { offset: 0, message: WithSize(outer) }] })
+            }]
+        }]};
+        assert_eq!(response.messages().err().unwrap().kind, ProtocolError(MalformedResponseError));
+    }
+
+    #[test]
+    fn test_snappy_unsupported() {
+        let message = Message { crc: 0, magic_byte: 0, attributes: SNAPPY_COMPRESSION, key: None, value: Some(vec![0]) };
+        assert!(message.decompress().is_err());
+    }
+
+    #[test]
+    fn test_api_versions() {
+        let mut writer = MemWriter::new();
+        RequestOrResponse(RequestMessage {
+            correlation_id: 1,
+            client_id: String::from_str("c"),
+            request_message: ApiVersionsRequest
+        }).encode(&mut writer).ok().unwrap();
+        assert_eq!(writer.get_ref(), [0x00u8, 0x00, 0x00, 0x0b, 0x00, 0x12, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01,
+                                      0x00, 0x01, 'c' as u8].as_slice());
+
+        let input = [0x00u8, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x03, 0x00, 0x00, 0x00, 0x05];
+        let response: ApiVersionsResponse = decode_from_slice(&input).ok().unwrap();
+        assert_eq!(response.version_ranges(), vec![versions::ApiVersionRange { api_key: 3, min_version: 0, max_version: 5 }]);
+    }
+
+    #[test]
+    fn test_heap_size() {
+        let mut name = String::with_capacity(16);
+        name.push_str("events");
+        assert_eq!(name.heap_size(), 16);
+        assert_eq!(Some(Vec::from_elem(8, 0u8)).heap_size(), 8);
+        assert_eq!(None::<Vec<u8>>.heap_size(), 0);
+
+        let topic = OffsetResponseTopic {
+            name: String::from_str("t"),
+            partitions: vec![PartitionOffset { partition: 0, error_code: 0, offsets: Vec::with_capacity(4) }]
+        };
+        let partitions = topic.partitions.capacity() * mem::size_of::<PartitionOffset>();
+        assert_eq!(topic.heap_size(), topic.name.capacity() + partitions + 4 * mem::size_of::<i64>());
+    }
+
+    #[test]
+    fn test_consumer_protocol() {
+        let subscription = ConsumerProtocolSubscription::new(vec![String::from_str("t")]);
+        let bytes = subscription.to_bytes().ok().unwrap();
+        assert_eq!(bytes.as_slice(), [0x00u8, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x01, 't' as u8,
+                                      0xff, 0xff, 0xff, 0xff].as_slice());
+        assert_eq!(ConsumerProtocolSubscription::from_bytes(bytes.as_slice()).ok().unwrap(), subscription);
+
+        let partitions = vec![TopicPartition::new("a", 0), TopicPartition::new("b", 1), TopicPartition::new("a", 2)];
+        let assignment = ConsumerProtocolAssignment::new(partitions.as_slice());
+        assert_eq!(assignment.assigned_partitions.len(), 2);
+        let bytes = assignment.to_bytes().ok().unwrap();
+        let decoded = ConsumerProtocolAssignment::from_bytes(bytes.as_slice()).ok().unwrap();
+        assert_eq!(decoded.partitions(), vec![TopicPartition::new("a", 0), TopicPartition::new("a", 2),
+                                              TopicPartition::new("b", 1)]);
+        assert!(ConsumerProtocolAssignment::from_bytes(&[]).ok().unwrap().partitions().is_empty());
+    }
+
+    #[test]
+    fn test_join_and_sync_group() {
+        let subscription = ConsumerProtocolSubscription::new(vec![String::from_str("t")]).to_bytes().ok().unwrap();
+        let request = JoinGroupRequest {
+            group_id: String::from_str("g"),
+            session_timeout_ms: 10000,
+            rebalance_timeout_ms: 20000,
+            member_id: String::from_str(UNKNOWN_MEMBER_ID),
+            group_instance_id: Some(String::from_str("host-1")),
+            protocol_type: String::from_str(CONSUMER_PROTOCOL_TYPE),
+            protocols: vec![JoinGroupProtocol { name: String::from_str("range"), metadata: subscription.clone() }]
+        };
+        write_read_test(request.clone());
+        assert!(JoinGroupRequest { protocols: vec![], ..request }.validate().is_err());
+
+        let response = JoinGroupResponse {
+            throttle_time_ms: 0,
+            error_code: 0,
+            generation_id: 3,
+            protocol_name: String::from_str("range"),
+            leader: String::from_str("m-1"),
+            member_id: String::from_str("m-1"),
+            members: vec![JoinGroupMember { member_id: String::from_str("m-1"), group_instance_id: None,
+                                            metadata: subscription }]
+        };
+        write_read_test(response.clone());
+        assert!(response.is_leader());
+        assert!(!JoinGroupResponse { member_id: String::from_str("m-2"), ..response }.is_leader());
+
+        let assignment = ConsumerProtocolAssignment::new(&[TopicPartition::new("t", 0)]).to_bytes().ok().unwrap();
+        write_read_test(SyncGroupRequest {
+            group_id: String::from_str("g"),
+            generation_id: 3,
+            member_id: String::from_str("m-1"),
+            group_instance_id: Some(String::from_str("host-1")),
+            assignments: vec![SyncGroupAssignment { member_id: String::from_str("m-1"), assignment: assignment.clone() }]
+        });
+        write_read_test(SyncGroupResponse { throttle_time_ms: 0, error_code: 0, assignment: assignment });
+    }
+
+    #[test]
+    fn test_join_and_sync_group_before_static_membership() {
+        let subscription = ConsumerProtocolSubscription::new(vec![String::from_str("t")]).to_bytes().ok().unwrap();
+        let protocols = vec![JoinGroupProtocol { name: String::from_str("range"), metadata: subscription.clone() }];
+        let request = JoinGroupRequestV0 {
+            group_id: String::from_str("g"),
+            session_timeout_ms: 10000,
+            member_id: String::from_str(UNKNOWN_MEMBER_ID),
+            protocol_type: String::from_str(CONSUMER_PROTOCOL_TYPE),
+            protocols: protocols.clone()
+        };
+        write_read_test(request.clone());
+        assert!(JoinGroupRequestV0 { protocols: vec![], ..request }.validate().is_err());
+        let request = JoinGroupRequestV1 {
+            group_id: String::from_str("g"),
+            session_timeout_ms: 10000,
+            rebalance_timeout_ms: 20000,
+            member_id: String::from_str(UNKNOWN_MEMBER_ID),
+            protocol_type: String::from_str(CONSUMER_PROTOCOL_TYPE),
+            protocols: protocols
+        };
+        write_read_test(request.clone());
+        assert!(JoinGroupRequestV1 { protocols: vec![], ..request }.validate().is_err());
+        write_read_test(JoinGroupResponseV0 {
+            error_code: 0,
+            generation_id: 3,
+            protocol_name: String::from_str("range"),
+            leader: String::from_str("m-1"),
+            member_id: String::from_str("m-1"),
+            members: vec![JoinGroupMemberV0 { member_id: String::from_str("m-1"), metadata: subscription }]
+        });
+
+        let assignment = ConsumerProtocolAssignment::new(&[TopicPartition::new("t", 0)]).to_bytes().ok().unwrap();
+        write_read_test(SyncGroupRequestV0 {
+            group_id: String::from_str("g"),
+            generation_id: 3,
+            member_id: String::from_str("m-1"),
+            assignments: vec![SyncGroupAssignment { member_id: String::from_str("m-1"), assignment: assignment.clone() }]
+        });
+        write_read_test(SyncGroupResponseV0 { error_code: 0, assignment: assignment });
+    }
+
+    #[test]
+    fn test_heartbeat() {
+        write_read_test(HeartbeatRequest {
+            group_id: String::from_str("g"),
+            group_generation_id: 3,
+            member_id: String::from_str("m-1")
+        });
+        write_read_test(HeartbeatResponse { error_code: 0 });
+        write_read_test(HeartbeatRequestV3 {
+            group_id: String::from_str("g"),
+            group_generation_id: 3,
+            member_id: String::from_str("m-1"),
+            group_instance_id: Some(String::from_str("host-1"))
+        });
+        write_read_test(HeartbeatResponseV3 { throttle_time_ms: 0, error_code: FencedInstanceIdCode as i16 });
+    }
+
+    #[test]
+    fn test_list_groups() {
+        let input = [0x00u8, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x01, 'g' as u8, 0x00, 0x08,
+                     'c' as u8, 'o' as u8, 'n' as u8, 's' as u8, 'u' as u8, 'm' as u8, 'e' as u8, 'r' as u8];
+        let response: ListGroupsResponse = decode_from_slice(&input).ok().unwrap();
+        assert_eq!(response.groups, vec![ListGroupsGroup {
+            group_id: String::from_str("g"),
+            protocol_type: String::from_str(CONSUMER_PROTOCOL_TYPE)
+        }]);
+    }
+
+    #[test]
+    fn test_describe_groups() {
+        let assignment = ConsumerProtocolAssignment::new(&[TopicPartition::new("t", 3)]);
+        let group = DescribeGroupsGroup {
+            error_code: 0,
+            group_id: String::from_str("g"),
+            state: String::from_str("Stable"),
+            protocol_type: String::from_str(CONSUMER_PROTOCOL_TYPE),
+            protocol: String::from_str("range"),
+            members: vec![DescribeGroupsMember {
+                member_id: String::from_str("m"),
+                client_id: String::from_str("c"),
+                client_host: String::from_str("/10.0.0.1"),
+                member_metadata: None,
+                member_assignment: Some(assignment.to_bytes().ok().unwrap())
+            }]
+        };
+        write_read_test(DescribeGroupsResponse { groups: vec![group.clone()] });
+        assert_eq!(group.consumer_assignments().ok().unwrap(),
+                   vec![(String::from_str("m"), vec![TopicPartition::new("t", 3)])]);
+    }
+
+    #[test]
+    fn test_create_topics() {
+        write_read_test(CreateTopicsRequest {
+            create_topic_requests: vec![CreateTopicsTopic {
+                topic: String::from_str("t"),
+                num_partitions: FROM_ASSIGNMENT,
+                replication_factor: FROM_ASSIGNMENT as i16,
+                replica_assignment: vec![CreateTopicsReplicaAssignment { partition: 0, replicas: vec![1, 2] }],
+                config_entries: vec![CreateTopicsConfigEntry {
+                    config_name: String::from_str("retention.ms"),
+                    config_value: None
+                }]
+            }],
+            timeout: 5000,
+            validate_only: 1
+        });
+
+        let input = [0x00u8, 0x00, 0x00, 0x01, 0x00, 0x01, 't' as u8, 0x00, 0x24, 0xff, 0xff];
+        let response: CreateTopicsResponse = decode_from_slice(&input).ok().unwrap();
+        assert_eq!(response.topic_errors, vec![CreateTopicsTopicError {
+            topic: String::from_str("t"),
+            error_code: TopicAlreadyExistsCode as i16,
+            error_message: None
+        }]);
+    }
+
+    #[test]
+    fn test_delete_records() {
+        let input = [0x00u8, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x01, 't' as u8,
+                     0x00, 0x00, 0x00, 0x02,
+                     0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x2a, 0x00, 0x00,
+                     0x00, 0x00, 0x00, 0x01, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x00, 0x01];
+        let response: DeleteRecordsResponse = decode_from_slice(&input).ok().unwrap();
+        assert_eq!(response.low_watermark("t", 0).ok().unwrap(), 42);
+        assert_eq!(response.low_watermark("t", 1).unwrap_err().kind, BrokerError(OffsetOutOfRange));
+        assert!(response.low_watermark("t", 2).is_err());
+
+        write_read_test(DeleteTopicsResponse {
+            topic_error_codes: vec![DeleteTopicsTopicError { topic: String::from_str("t"), error_code: 0 }]
+        });
+    }
+
+    #[test]
+    fn test_create_partitions() {
+        fn topic(assignment: Option<Vec<Vec<i32>>>) -> CreatePartitionsTopic {
+            CreatePartitionsTopic { topic: String::from_str("t"), count: 2, assignment: assignment }
+        }
+        write_read_test(CreatePartitionsRequest {
+            topic_partitions: vec![topic(None), topic(Some(vec![vec![1, 2]]))],
+            timeout: 5000,
+            validate_only: 0
+        });
+
+        let null = [0x00u8, 0x01, 't' as u8, 0x00, 0x00, 0x00, 0x02, 0xff, 0xff, 0xff, 0xff];
+        assert_eq!(decode_from_slice::<CreatePartitionsTopic>(&null).ok().unwrap(), topic(None));
+    }
+
+    #[test]
+    fn test_metadata_v1() {
+        fn broker(node_id: i32) -> BrokerV1 {
+            BrokerV1 { node_id: node_id, host: String::from_str("h"), port: 9092, rack: None }
+        }
+        let response = MetadataResponseV1 { brokers: vec![broker(1), broker(2)], controller_id: 2, topics: vec![] };
+        write_read_test(response.clone());
+        assert_eq!(response.controller(), Some(&broker(2)));
+        assert_eq!(MetadataResponseV1 { controller_id: NO_CONTROLLER, ..response }.controller(), None);
+
+        write_read_test(MetadataRequestV1 { topic_names: None });
+        write_read_test(MetadataRequestV1 { topic_names: Some(vec![]) });
+    }
+
+    #[test]
+    fn test_offset_v1() {
+        let mut writer = MemWriter::new();
+        RequestOrResponse(RequestMessage {
+            correlation_id: 1,
+            client_id: String::from_str("c"),
+            request_message: OffsetRequestV1 {
+                replica_id: -1,
+                requests: vec![OffsetRequestTopicV1 {
+                    name: String::from_str("t"),
+                    partitions: vec![OffsetRequestPartitionV1 { partition: 0, time: -1 }]
+                }]
+            }
+        }).encode(&mut writer).ok().unwrap();
+        assert_eq!(writer.get_ref(), [0x00u8, 0x00, 0x00, 0x26, 0x00, 0x02, 0x00, 0x01, 0x00, 0x00, 0x00, 0x01,
+                                      0x00, 0x01, 'c' as u8, 0xff, 0xff, 0xff, 0xff, 0x00, 0x00, 0x00, 0x01,
+                                      0x00, 0x01, 't' as u8, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00,
+                                      0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff].as_slice());
+
+        write_read_test(OffsetResponseV1 {
+            responses: vec![OffsetResponseTopicV1 {
+                name: String::from_str("t"),
+                partitions: vec![PartitionOffsetV1 { partition: 0, error_code: 0, timestamp: 1000, offset: 42 }]
+            }]
+        });
+    }
+
+    #[test]
+    fn test_describe_configs_response() {
+        write_read_test(DescribeConfigsResponse {
+            throttle_time_ms: 0,
+            resources: vec![DescribeConfigsResult {
+                error_code: 0,
+                error_message: None,
+                resource_type: TOPIC_RESOURCE,
+                resource_name: String::from_str("events"),
+                config_entries: vec![DescribeConfigsEntry {
+                    config_name: String::from_str("cleanup.policy"),
+                    config_value: Some(String::from_str("compact")),
+                    read_only: 0,
+                    is_default: 0,
+                    is_sensitive: 0
+                }]
+            }]
+        });
+    }
+}
@@ -0,0 +1,108 @@
+//! Offset requests, for looking up the offsets held by a partition.
+
+use types::*;
+use versions;
+use super::{KafkaSerializable, HeapSize, BufferEncoder, Request, Response, add_size};
+
+kafka_datastructures! (
+    struct OffsetRequestPartition {
+        partition: i32,
+        time: i64,
+        max_number_of_offsets: i32
+    }
+
+    struct OffsetRequestTopic {
+        name: String,
+        partitions: Vec<OffsetRequestPartition>
+    }
+
+    struct OffsetRequest {
+        replica_id: i32,
+        requests: Vec<OffsetRequestTopic>
+    }
+
+    struct PartitionOffset {
+        partition: i32,
+        error_code: i16,
+        offsets: Vec<i64>
+    }
+
+    struct OffsetResponseTopic {
+        name: String,
+        partitions: Vec<PartitionOffset>
+    }
+
+    struct OffsetResponse {
+        responses: Vec<OffsetResponseTopic>
+    }
+
+    struct OffsetRequestPartitionV1 {
+        partition: i32,
+        time: i64
+    }
+
+    struct OffsetRequestTopicV1 {
+        name: String,
+        partitions: Vec<OffsetRequestPartitionV1>
+    }
+
+    struct OffsetRequestV1 {
+        replica_id: i32,
+        requests: Vec<OffsetRequestTopicV1>
+    }
+
+    struct PartitionOffsetV1 {
+        partition: i32,
+        error_code: i16,
+        timestamp: i64,
+        offset: i64
+    }
+
+    struct OffsetResponseTopicV1 {
+        name: String,
+        partitions: Vec<PartitionOffsetV1>
+    }
+
+    struct OffsetResponseV1 {
+        responses: Vec<OffsetResponseTopicV1>
+    }
+)
+
+impl Request for OffsetRequest {
+    fn api_key(_: Option<OffsetRequest>) -> i16 { versions::OFFSETS }
+
+    fn validate(&self) -> KafkaResult<()> {
+        require_non_empty!(self.requests, "Offset")
+    }
+
+    fn topic_count(&self) -> uint { self.requests.len() }
+}
+
+impl Response for OffsetResponse {}
+
+impl Request for OffsetRequestV1 {
+    fn api_key(_: Option<OffsetRequestV1>) -> i16 { versions::OFFSETS }
+
+    fn api_version(_: Option<OffsetRequestV1>) -> i16 { 1 }
+
+    fn validate(&self) -> KafkaResult<()> {
+        require_non_empty!(self.requests, "Offset")
+    }
+
+    fn topic_count(&self) -> uint { self.requests.len() }
+}
+
+impl Response for OffsetResponseV1 {}
+
+/// The messages as of API version 0.
+pub mod v0 {
+    pub use super::{OffsetRequestPartition, OffsetRequestTopic, OffsetRequest, PartitionOffset,
+                    OffsetResponseTopic, OffsetResponse};
+}
+
+/// The messages as of API version 1, which give a single offset per
+/// partition along with its timestamp. Brokers before 0.10.1 don't support it.
+pub mod v1 {
+    pub use super::{OffsetRequestPartitionV1, OffsetRequestTopicV1, OffsetRequestV1, PartitionOffsetV1,
+                    OffsetResponseTopicV1, OffsetResponseV1};
+}
@@ -0,0 +1,74 @@
+//! OffsetCommit requests, for storing a group's positions.
+
+use types::*;
+use versions;
+use super::{KafkaSerializable, HeapSize, BufferEncoder, Request, Response, add_size};
+
+/// The generation to commit with when not a member of a group, as when
+/// partitions are assigned explicitly.
+pub const NO_GENERATION_ID: i32 = -1;
+
+/// The timestamp to commit with for the broker to fill in its own time.
+pub const BROKER_TIMESTAMP: i64 = -1;
+
+kafka_datastructures! (
+    struct OffsetCommitRequestPartition {
+        partition: i32,
+        offset: i64,
+        timestamp: i64,
+        metadata: String
+    }
+
+    struct OffsetCommitRequestTopic {
+        name: String,
+        partitions: Vec<OffsetCommitRequestPartition>
+    }
+
+    struct OffsetCommitRequest {
+        consumer_group: String,
+        consumer_group_generation_id: i32,
+        consumer_id: String,
+        topics: Vec<OffsetCommitRequestTopic>
+    }
+
+    struct OffsetCommitResponsePartition {
+        partition: i32,
+        error_code: i16
+    }
+
+    struct OffsetCommitResponseTopic {
+        name: String,
+        partitions: Vec<OffsetCommitResponsePartition>
+    }
+
+    struct OffsetCommitResponse {
+        topics: Vec<OffsetCommitResponseTopic>
+    }
+)
+
+impl Request for OffsetCommitRequest {
+    fn api_key(_: Option<OffsetCommitRequest>) -> i16 { versions::OFFSET_COMMIT }
+
+    // Version 0 stores offsets in ZooKeeper, 1 in Kafka itself
+    fn api_version(_: Option<OffsetCommitRequest>) -> i16 { 1 }
+
+    fn validate(&self) -> KafkaResult<()> {
+        require_non_empty!(self.topics, "OffsetCommit")
+    }
+
+    fn topic_count(&self) -> uint { self.topics.len() }
+}
+
+impl Response for OffsetCommitResponse {}
+
+/// The messages as of API version 1.
+pub mod v1 {
+    pub use super::{OffsetCommitRequestPartition, OffsetCommitRequestTopic, OffsetCommitRequest,
+                    OffsetCommitResponsePartition, OffsetCommitResponseTopic, OffsetCommitResponse,
+                    NO_GENERATION_ID, BROKER_TIMESTAMP};
+}
+
+/// The newest version of these messages supported by this crate.
+pub mod latest {
+    pub use super::v1::*;
+}
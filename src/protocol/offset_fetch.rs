@@ -0,0 +1,59 @@
+//! OffsetFetch requests, for reading a group's stored positions.
+
+use types::*;
+use versions;
+use super::{KafkaSerializable, HeapSize, BufferEncoder, Request, Response, add_size};
+
+kafka_datastructures! (
+    struct OffsetFetchRequestTopic {
+        name: String,
+        partitions: Vec<i32>
+    }
+
+    struct OffsetFetchRequest {
+        consumer_group: String,
+        topics: Vec<OffsetFetchRequestTopic>
+    }
+
+    struct OffsetFetchResponsePartition {
+        partition: i32,
+        offset: i64,
+        metadata: String,
+        error_code: i16
+    }
+
+    struct OffsetFetchResponseTopic {
+        name: String,
+        partitions: Vec<OffsetFetchResponsePartition>
+    }
+
+    struct OffsetFetchResponse {
+        topics: Vec<OffsetFetchResponseTopic>
+    }
+)
+
+impl Request for OffsetFetchRequest {
+    fn api_key(_: Option<OffsetFetchRequest>) -> i16 { versions::OFFSET_FETCH }
+
+    // Version 1 reads the offsets OffsetCommit version 1 stores in Kafka
+    fn api_version(_: Option<OffsetFetchRequest>) -> i16 { 1 }
+
+    fn validate(&self) -> KafkaResult<()> {
+        require_non_empty!(self.topics, "OffsetFetch")
+    }
+
+    fn topic_count(&self) -> uint { self.topics.len() }
+}
+
+impl Response for OffsetFetchResponse {}
+
+/// The messages as of API version 1, which are laid out as in version 0.
+pub mod v1 {
+    pub use super::{OffsetFetchRequestTopic, OffsetFetchRequest, OffsetFetchResponsePartition,
+                    OffsetFetchResponseTopic, OffsetFetchResponse};
+}
+
+/// The newest version of these messages supported by this crate.
+pub mod latest {
+    pub use super::v1::*;
+}
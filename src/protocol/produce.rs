@@ -0,0 +1,88 @@
+//! Produce requests, for writing message sets to partitions.
+
+use std::vec;
+
+use types::*;
+use versions;
+use super::{KafkaSerializable, HeapSize, BufferEncoder, WithSize, Request, Response, add_size};
+use super::{Error, NoError, Unknown};
+use super::message::MessageSet;
+
+kafka_datastructures! (
+    struct ProduceRequestPartition {
+        partition: i32,
+        message_set: WithSize<MessageSet>
+    }
+
+    struct ProduceRequestTopic {
+        name: String,
+        partitions: Vec<ProduceRequestPartition>
+    }
+
+    struct ProduceRequest {
+        required_acks: i16,
+        timeout: i32,
+        topics: Vec<ProduceRequestTopic>
+    }
+
+    struct ProduceResponsePartition {
+        partition: i32,
+        error_code: i16,
+        offset: i64
+    }
+
+    struct ProduceResponseTopic {
+        name: String,
+        partitions: Vec<ProduceResponsePartition>
+    }
+
+    struct ProduceResponse {
+        topics: Vec<ProduceResponseTopic>
+    }
+)
+
+impl Request for ProduceRequest {
+    fn api_key(_: Option<ProduceRequest>) -> i16 { versions::PRODUCE }
+
+    fn validate(&self) -> KafkaResult<()> {
+        require_non_empty!(self.topics, "Produce")
+    }
+
+    fn topic_count(&self) -> uint { self.topics.len() }
+
+    // With acks=0 the broker writes the messages without replying
+    fn expects_response(&self) -> bool { self.required_acks != 0 }
+}
+
+impl Response for ProduceResponse {
+    fn unanswered(_: Option<ProduceResponse>) -> KafkaResult<ProduceResponse> {
+        Ok(ProduceResponse { topics: vec![] })
+    }
+}
+
+impl ProduceResponse {
+    /// Each partition's result: the offset given to its first message, or
+    /// the error the broker reported for it.
+    pub fn results(&self) -> vec::MoveItems<(TopicPartition, Result<i64, Error>)> {
+        let mut results = vec![];
+        for topic in self.topics.iter() {
+            for partition in topic.partitions.iter() {
+                let error: Error = FromPrimitive::from_i16(partition.error_code).unwrap_or(Unknown);
+                let result = if error == NoError { Ok(partition.offset) } else { Err(error) };
+                results.push((TopicPartition::new(topic.name.as_slice(), partition.partition), result));
+            }
+        }
+        results.into_iter()
+    }
+}
+
+/// The messages as of API version 0.
+pub mod v0 {
+    pub use super::{ProduceRequestPartition, ProduceRequestTopic, ProduceRequest,
+                    ProduceResponsePartition, ProduceResponseTopic, ProduceResponse};
+}
+
+/// The newest version of these messages supported by this crate.
+pub mod latest {
+    pub use super::v0::*;
+}
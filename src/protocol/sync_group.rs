@@ -0,0 +1,69 @@
+//! SyncGroup requests, with which the group leader hands out assignments and
+//! every member collects its own.
+
+use types::*;
+use versions;
+use super::{KafkaSerializable, HeapSize, BufferEncoder, Request, Response, add_size};
+
+kafka_datastructures! (
+    struct SyncGroupAssignment {
+        member_id: String,
+        assignment: Vec<u8>
+    }
+
+    struct SyncGroupRequest {
+        group_id: String,
+        generation_id: i32,
+        member_id: String,
+        group_instance_id: Option<String>,
+        assignments: Vec<SyncGroupAssignment>
+    }
+
+    struct SyncGroupResponse {
+        throttle_time_ms: i32,
+        error_code: i16,
+        assignment: Vec<u8>
+    }
+
+    struct SyncGroupRequestV0 {
+        group_id: String,
+        generation_id: i32,
+        member_id: String,
+        assignments: Vec<SyncGroupAssignment>
+    }
+
+    struct SyncGroupResponseV0 {
+        error_code: i16,
+        assignment: Vec<u8>
+    }
+)
+
+impl Request for SyncGroupRequest {
+    fn api_key(_: Option<SyncGroupRequest>) -> i16 { versions::SYNC_GROUP }
+
+    fn api_version(_: Option<SyncGroupRequest>) -> i16 { 3 }
+}
+
+impl Response for SyncGroupResponse {}
+
+impl Request for SyncGroupRequestV0 {
+    fn api_key(_: Option<SyncGroupRequestV0>) -> i16 { versions::SYNC_GROUP }
+}
+
+impl Response for SyncGroupResponseV0 {}
+
+/// The messages as of API version 0, from Kafka 0.9.
+pub mod v0 {
+    pub use super::{SyncGroupAssignment, SyncGroupRequestV0, SyncGroupResponseV0};
+}
+
+/// The messages as of API version 3, which added `group_instance_id` for
+/// static membership (Kafka 2.3). Only the leader sends `assignments`.
+pub mod v3 {
+    pub use super::{SyncGroupAssignment, SyncGroupRequest, SyncGroupResponse};
+}
+
+/// The newest version of these messages supported by this crate.
+pub mod latest {
+    pub use super::v3::*;
+}
@@ -0,0 +1,121 @@
+use producer::ProducerRecord;
+
+/// Where a routing rule sends a record. Without a partition, the producer's
+/// `Partitioner` chooses one.
+#[deriving(Show, PartialEq, Eq, Clone)]
+pub struct Route {
+    pub topic: String,
+    pub partition: Option<i32>
+}
+
+impl Route {
+    pub fn to_topic(topic: &str) -> Route {
+        Route { topic: String::from_str(topic), partition: None }
+    }
+}
+
+/// Picks a destination for a record from its headers, or leaves it to the
+/// next rule by returning `None`.
+pub trait RoutingRule {
+    fn route(&self, record: &ProducerRecord) -> Option<Route>;
+}
+
+/// Sends records whose header `name` is exactly `value` to `route`.
+pub struct HeaderEquals {
+    pub name: String,
+    pub value: Vec<u8>,
+    pub route: Route
+}
+
+impl RoutingRule for HeaderEquals {
+    fn route(&self, record: &ProducerRecord) -> Option<Route> {
+        match record.header(self.name.as_slice()) {
+            Some(value) if value == self.value.as_slice() => Some(self.route.clone()),
+            _ => None
+        }
+    }
+}
+
+/// Sends records to a topic named after one of their headers, such as
+/// "orders-" followed by a tenant header for per-tenant topics. Headers which
+/// aren't UTF-8 don't match.
+pub struct TopicFromHeader {
+    pub name: String,
+    pub prefix: String,
+    pub suffix: String
+}
+
+impl RoutingRule for TopicFromHeader {
+    fn route(&self, record: &ProducerRecord) -> Option<Route> {
+        match record.header(self.name.as_slice()).and_then(|value| ::std::str::from_utf8(value)) {
+            Some(value) if !value.is_empty() => {
+                Some(Route::to_topic(format!("{}{}{}", self.prefix, value, self.suffix).as_slice()))
+            },
+            _ => None
+        }
+    }
+}
+
+/// Tries each rule in turn, routing a record by the first that matches.
+/// Records no rule matches keep the topic and partition they were given.
+pub struct Router {
+    rules: Vec<Box<RoutingRule + Send + Sync>>
+}
+
+impl Router {
+    pub fn new() -> Router {
+        Router { rules: vec![] }
+    }
+
+    pub fn add_rule(&mut self, rule: Box<RoutingRule + Send + Sync>) {
+        self.rules.push(rule);
+    }
+
+    pub fn route(&self, mut record: ProducerRecord) -> ProducerRecord {
+        match self.rules.iter().filter_map(|rule| rule.route(&record)).next() {
+            Some(route) => {
+                record.topic = route.topic;
+                record.partition = route.partition;
+            },
+            None => ()
+        }
+        record
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use producer::ProducerRecord;
+    use super::*;
+
+    fn record() -> ProducerRecord {
+        ProducerRecord::new("events", None, Some(b"payload".to_vec()))
+    }
+
+    #[test]
+    fn test_first_matching_rule() {
+        let mut router = Router::new();
+        router.add_rule(box HeaderEquals {
+            name: String::from_str("priority"),
+            value: b"high".to_vec(),
+            route: Route { topic: String::from_str("urgent"), partition: Some(0) }
+        });
+        router.add_rule(box TopicFromHeader {
+            name: String::from_str("tenant"),
+            prefix: String::from_str("events-"),
+            suffix: String::new()
+        });
+
+        let routed = router.route(record().with_header("tenant", b"acme".to_vec()));
+        assert_eq!(routed.topic.as_slice(), "events-acme");
+        assert_eq!(routed.partition, None);
+
+        let routed = router.route(record().with_header("tenant", b"acme".to_vec())
+                                          .with_header("priority", b"high".to_vec()));
+        assert_eq!(routed.topic.as_slice(), "urgent");
+        assert_eq!(routed.partition, Some(0));
+
+        let routed = router.route(record().with_header("priority", b"low".to_vec()));
+        assert_eq!(routed.topic.as_slice(), "events");
+    }
+}
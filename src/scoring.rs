@@ -0,0 +1,122 @@
+use std::collections::HashMap;
+
+/// Weight given to each new sample in the moving averages.
+const SMOOTHING: f64 = 0.2;
+
+/// Brokers failing more often than this are tried after untried ones.
+const UNHEALTHY_ERROR_RATE: f64 = 0.5;
+
+/// Exponentially weighted averages of how a broker has been answering.
+#[deriving(Show, PartialEq, Clone)]
+pub struct BrokerHealth {
+    pub latency_ms: f64,
+    /// The fraction of recent requests that failed, from 0 to 1.
+    pub error_rate: f64,
+    pub samples: u64
+}
+
+impl BrokerHealth {
+    fn new() -> BrokerHealth {
+        BrokerHealth { latency_ms: 0.0, error_rate: 0.0, samples: 0 }
+    }
+
+    fn record(&mut self, latency_ms: f64, failed: bool) {
+        let failure = if failed { 1.0 } else { 0.0 };
+        if self.samples == 0 {
+            self.latency_ms = latency_ms;
+            self.error_rate = failure;
+        } else {
+            self.latency_ms += SMOOTHING * (latency_ms - self.latency_ms);
+            self.error_rate += SMOOTHING * (failure - self.error_rate);
+        }
+        self.samples += 1;
+    }
+
+    /// Lower is better: latency, penalized heavily for errors.
+    pub fn score(&self) -> f64 {
+        self.latency_ms * (1.0 + 10.0 * self.error_rate)
+    }
+
+    pub fn is_healthy(&self) -> bool {
+        self.error_rate < UNHEALTHY_ERROR_RATE
+    }
+}
+
+/// Health of each broker the client has talked to, by "host:port".
+pub struct BrokerScores {
+    brokers: HashMap<String, BrokerHealth>
+}
+
+impl BrokerScores {
+    pub fn new() -> BrokerScores {
+        BrokerScores { brokers: HashMap::new() }
+    }
+
+    pub fn record(&mut self, broker: &str, latency_ms: f64, failed: bool) {
+        if !self.brokers.contains_key(broker) {
+            self.brokers.insert(String::from_str(broker), BrokerHealth::new());
+        }
+        self.brokers.get_mut(broker).unwrap().record(latency_ms, failed);
+    }
+
+    pub fn get(&self, broker: &str) -> Option<&BrokerHealth> {
+        self.brokers.get(broker)
+    }
+
+    /// Orders hosts for requests any broker can answer: healthy brokers best
+    /// first, so the same one keeps being used while it does well, then
+    /// those not tried yet in the order given, then unhealthy ones.
+    pub fn order(&self, hosts: Vec<(String, u16)>) -> Vec<(String, u16)> {
+        let mut healthy = vec![];
+        let mut untried = vec![];
+        let mut unhealthy = vec![];
+        for (host, port) in hosts.into_iter() {
+            match self.brokers.get(&format!("{}:{}", host, port)) {
+                Some(health) if health.is_healthy() => healthy.push((health.score(), (host, port))),
+                Some(health) => unhealthy.push((health.score(), (host, port))),
+                None => untried.push((host, port))
+            }
+        }
+
+        let by_score = |a: &(f64, (String, u16)), b: &(f64, (String, u16))| a.0.partial_cmp(&b.0).unwrap_or(Equal);
+        healthy.sort_by(|a, b| by_score(a, b));
+        unhealthy.sort_by(|a, b| by_score(a, b));
+
+        let mut ordered: Vec<(String, u16)> = healthy.into_iter().map(|(_, host)| host).collect();
+        ordered.extend(untried.into_iter());
+        ordered.extend(unhealthy.into_iter().map(|(_, host)| host));
+        ordered
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn host(name: &str) -> (String, u16) {
+        (String::from_str(name), 9092)
+    }
+
+    #[test]
+    fn test_ewma() {
+        let mut scores = BrokerScores::new();
+        scores.record("a:9092", 10.0, false);
+        scores.record("a:9092", 20.0, true);
+        let health = scores.get("a:9092").unwrap();
+        assert_eq!(health.latency_ms, 12.0);
+        assert_eq!(health.error_rate, 0.2);
+        assert_eq!(health.samples, 2);
+        assert!(health.is_healthy());
+    }
+
+    #[test]
+    fn test_order() {
+        let mut scores = BrokerScores::new();
+        scores.record("slow:9092", 50.0, false);
+        scores.record("fast:9092", 5.0, false);
+        scores.record("failing:9092", 1.0, true);
+
+        let hosts = vec![host("failing"), host("new"), host("slow"), host("fast")];
+        assert_eq!(scores.order(hosts), vec![host("fast"), host("slow"), host("new"), host("failing")]);
+    }
+}
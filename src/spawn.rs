@@ -0,0 +1,17 @@
+use std::task;
+
+/// Starts the client's background work, such as lingering batches and
+/// heartbeats. Replace it to run that work on a pool or an embedding
+/// runtime's threads instead of a new native thread per task.
+pub trait Spawner {
+    fn spawn(&self, task: proc(): Send);
+}
+
+/// Runs each task on a thread of its own.
+pub struct NativeSpawner;
+
+impl Spawner for NativeSpawner {
+    fn spawn(&self, task: proc(): Send) {
+        task::spawn(task);
+    }
+}
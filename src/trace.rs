@@ -0,0 +1,31 @@
+use types::KafkaError;
+
+/// What is known about a request when its span starts.
+#[deriving(Show, PartialEq, Eq, Clone)]
+pub struct SpanInfo {
+    pub api_key: i16,
+    /// The `host:port` the request is sent to.
+    pub broker: String,
+    pub topic_count: uint,
+    /// Encoded size of the request, excluding the header.
+    pub request_bytes: uint
+}
+
+/// Receives a span for every request the client sends, so Kafka operations
+/// can show up in an application's distributed traces.
+pub trait Tracer {
+    /// Called before a request is sent. The returned id is passed back to
+    /// `end_span` once the request completes.
+    fn start_span(&self, info: &SpanInfo) -> u64;
+
+    fn end_span(&self, span: u64, info: &SpanInfo, error: Option<&KafkaError>);
+}
+
+/// The default tracer, which records nothing.
+pub struct NoopTracer;
+
+impl Tracer for NoopTracer {
+    fn start_span(&self, _: &SpanInfo) -> u64 { 0 }
+
+    fn end_span(&self, _: u64, _: &SpanInfo, _: Option<&KafkaError>) {}
+}
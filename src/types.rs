@@ -1,26 +1,147 @@
+use std::fmt;
+use std::io;
 use std::io::IoError;
 use std::error;
 
+use protocol;
+
 #[deriving(PartialEq, Eq, Clone, Show)]
 pub enum ErrorKind {
+    /// Talking to the broker failed at the network level.
+    TransportError(IoError),
+    /// A broker took longer than the configured timeout to respond.
+    TimeoutError,
+    /// Data couldn't be encoded or decoded, or didn't match the request.
+    ProtocolError(ProtocolErrorKind),
+    /// The broker answered with an error code.
+    BrokerError(protocol::Error),
+    /// The client was asked to do something its settings or state don't
+    /// allow.
+    ConfigError(ConfigErrorKind),
+    /// The client's `Spawner` dropped a task without running it.
+    SpawnError,
+    /// The operation was stopped through its `CancellationToken`, or its
+    /// record discarded before being sent, as when a producer is dropped.
+    CancelledError,
+}
+
+#[deriving(PartialEq, Eq, Clone, Show)]
+pub enum ProtocolErrorKind {
     MalformedRequestError,
     MalformedResponseError,
-    InternalIoError(IoError),
+    /// A header field didn't match what was sent, holding (expected, actual).
+    ProtocolMismatchError(i32, i32),
 }
 
 #[deriving(PartialEq, Eq, Clone, Show)]
+pub enum ConfigErrorKind {
+    /// Metadata is needed but `load_metadata` hasn't succeeded yet.
+    NoMetadata,
+    /// No bootstrap hosts or known brokers to send a request to.
+    NoHosts,
+    /// A broker address isn't a valid "host:port".
+    InvalidHost,
+    /// Offsets are committed or fetched without a `group_id`.
+    NoGroupId,
+    /// The partition isn't one the consumer is assigned.
+    UnassignedPartition,
+    /// A message is larger than the biggest fetch the client will make.
+    FetchSizeLimit,
+    /// A tombstone was sent to a topic without `cleanup.policy=compact`.
+    NotCompacted,
+    /// The partitioner chose a partition the topic doesn't have.
+    PartitionerOutOfRange,
+    /// The `partition_count_pin` is above the topic's partition count.
+    PartitionCountPinExceeded,
+}
+
+#[deriving(PartialEq, Eq, Clone)]
 pub struct KafkaError {
     pub kind: ErrorKind,
     pub desc: &'static str,
     pub detail: Option<String>,
+    /// What was being attempted when the error occurred, e.g. "Fetch".
+    pub operation: Option<&'static str>,
+    /// The `host:port` of the broker involved, if any.
+    pub broker: Option<String>,
+}
+
+impl KafkaError {
+    pub fn with_context(mut self, operation: &'static str, broker: Option<String>) -> KafkaError {
+        self.operation = Some(operation);
+        self.broker = broker;
+        self
+    }
+
+    /// Whether the broker rejected the request because the client's view of
+    /// partition leadership is stale, so refreshing metadata may fix it.
+    pub fn is_routing_error(&self) -> bool {
+        match self.kind {
+            BrokerError(protocol::NotLeaderForPartition) |
+            BrokerError(protocol::LeaderNotAvailable) |
+            BrokerError(protocol::UnknownTopicOrPartition) => true,
+            _ => false,
+        }
+    }
+
+    /// Whether the same request may succeed if sent again, perhaps after
+    /// refreshing metadata.
+    pub fn is_retriable(&self) -> bool {
+        match self.kind {
+            BrokerError(protocol::RequestTimedOut) => true,
+            _ => self.is_routing_error(),
+        }
+    }
+
+    /// A suggestion for what to look at when this error shows up.
+    pub fn remediation(&self) -> &'static str {
+        match self.kind {
+            TransportError(..) => "Check that the broker is running and reachable from this host.",
+            TimeoutError => "Retry, or raise the timeout if the broker is expected to be this slow.",
+            ProtocolError(MalformedRequestError) => "Check that the request's strings, arrays and messages are within protocol limits.",
+            ProtocolError(MalformedResponseError) => "Check that the broker speaks a protocol version supported by this client.",
+            ProtocolError(ProtocolMismatchError(..)) => "The connection is out of step with the broker and should be reopened.",
+            BrokerError(protocol::NotLeaderForPartition) |
+            BrokerError(protocol::LeaderNotAvailable) |
+            BrokerError(protocol::UnknownTopicOrPartition) => "Refresh metadata and retry against the current leader.",
+            BrokerError(protocol::RequestTimedOut) => "Retry the request, possibly with a longer timeout.",
+            BrokerError(protocol::NotControllerCode) => "Look up the current controller and retry against it.",
+            BrokerError(protocol::OffsetOutOfRange) => "Reset the offset to one the broker still has.",
+            BrokerError(protocol::MessageSizeTooLarge) => "Send smaller messages or raise the topic's message size limit.",
+            BrokerError(protocol::FencedInstanceIdCode) => "Give each consumer instance in the group its own group_instance_id.",
+            BrokerError(..) => "See the Kafka protocol documentation for this error code.",
+            ConfigError(NoMetadata) => "Call load_metadata first.",
+            ConfigError(NoHosts) | ConfigError(InvalidHost) => "Check the bootstrap hosts given to the client.",
+            ConfigError(NoGroupId) => "Set the consumer's group_id.",
+            ConfigError(UnassignedPartition) => "Assign the partition to the consumer first.",
+            ConfigError(FetchSizeLimit) => "Raise the fetch size limit above the topic's largest message.",
+            ConfigError(NotCompacted) => "Only send tombstones to compacted topics.",
+            ConfigError(PartitionerOutOfRange) => "Check that the partitioner only chooses the topic's partitions.",
+            ConfigError(PartitionCountPinExceeded) => "Lower the partition count pin to at most the topic's partition count.",
+            SpawnError => "Check that the client's Spawner runs every task it is given.",
+            CancelledError => "The application asked for the operation to stop, or dropped the producer before flushing it.",
+        }
+    }
 }
 
 impl error::FromError<IoError> for KafkaError {
     fn from_error(err: IoError) -> KafkaError {
+        if err.kind == io::TimedOut {
+            return KafkaError {
+                kind: TimeoutError,
+                desc: "The operation timed out.",
+                detail: err.detail,
+                operation: None,
+                broker: None,
+            };
+        }
+
         KafkaError {
-            kind: InternalIoError(err),
+            kind: TransportError(err),
             desc: "An internal IO error ocurred.",
-            detail: None
+            detail: None,
+            operation: None,
+            broker: None,
         }
     }
 }
@@ -31,31 +152,154 @@ impl error::FromError<(ErrorKind, &'static str)> for KafkaError {
             kind: kind,
             desc: desc,
             detail: None,
+            operation: None,
+            broker: None,
         }
     }
 }
 
+impl error::FromError<(ProtocolErrorKind, &'static str)> for KafkaError {
+    fn from_error((kind, desc): (ProtocolErrorKind, &'static str)) -> KafkaError {
+        error::FromError::from_error((ProtocolError(kind), desc))
+    }
+}
+
+impl error::FromError<(ConfigErrorKind, &'static str)> for KafkaError {
+    fn from_error((kind, desc): (ConfigErrorKind, &'static str)) -> KafkaError {
+        error::FromError::from_error((ConfigError(kind), desc))
+    }
+}
+
+impl error::FromError<protocol::Error> for KafkaError {
+    fn from_error(err: protocol::Error) -> KafkaError {
+        error::FromError::from_error((BrokerError(err), "The broker returned an error"))
+    }
+}
+
 impl error::Error for KafkaError {
     fn description(&self) -> &str {
         match self.kind {
-            InternalIoError(ref err) => err.desc,
+            TransportError(ref err) => err.desc,
             _ => self.desc,
         }
     }
 
     fn detail(&self) -> Option<String> {
         match self.kind {
-            InternalIoError(ref err) => err.detail.clone(),
+            TransportError(ref err) => err.detail.clone(),
             _ => self.detail.clone(),
         }
     }
 
     fn cause(&self) -> Option<&error::Error> {
         match self.kind {
-            InternalIoError(ref err) => Some(err as &error::Error),
+            TransportError(ref err) => Some(err as &error::Error),
             _ => None,
         }
     }
 }
 
+impl fmt::Show for KafkaError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.operation {
+            Some(operation) => match self.broker {
+                Some(ref broker) => try!(write!(f, "{} on broker {} failed: ", operation, broker)),
+                None => try!(write!(f, "{} failed: ", operation)),
+            },
+            None => (),
+        }
+
+        try!(write!(f, "{}", self.desc.trim_right_chars('.')));
+        match self.kind {
+            ProtocolError(ProtocolMismatchError(expected, actual)) => {
+                try!(write!(f, " (expected {}, got {})", expected, actual))
+            },
+            BrokerError(ref err) => try!(write!(f, " ({})", err)),
+            _ => (),
+        }
+        match self.detail {
+            Some(ref detail) => try!(write!(f, " ({})", detail)),
+            None => (),
+        }
+        try!(write!(f, "."));
+        match self.kind {
+            TransportError(ref err) => try!(write!(f, " Caused by: {}.", err)),
+            _ => (),
+        }
+
+        write!(f, " {}", self.remediation())
+    }
+}
+
 pub type KafkaResult<T> = Result<T, KafkaError>;
+
+/// Identifies a partition of a topic.
+#[deriving(PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Show)]
+pub struct TopicPartition {
+    pub topic: String,
+    pub partition: i32,
+}
+
+impl TopicPartition {
+    pub fn new(topic: &str, partition: i32) -> TopicPartition {
+        TopicPartition { topic: String::from_str(topic), partition: partition }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::error::FromError;
+    use std::io;
+    use protocol;
+    use super::*;
+
+    #[test]
+    fn test_retriable() {
+        let timed_out: KafkaError = FromError::from_error(protocol::RequestTimedOut);
+        let not_leader: KafkaError = FromError::from_error(protocol::NotLeaderForPartition);
+        let too_large: KafkaError = FromError::from_error(protocol::MessageSizeTooLarge);
+        assert!(timed_out.is_retriable() && !timed_out.is_routing_error());
+        assert!(not_leader.is_retriable() && not_leader.is_routing_error());
+        assert!(!too_large.is_retriable());
+    }
+
+    #[test]
+    fn test_config_error() {
+        let err: KafkaError = FromError::from_error((NoGroupId, "Offsets can't be committed without a group_id"));
+        assert_eq!(err.kind, ConfigError(NoGroupId));
+        assert_eq!(format!("{}", err).as_slice(),
+                   "Offsets can't be committed without a group_id. Set the consumer's group_id.");
+    }
+
+    #[test]
+    fn test_topic_partition_order() {
+        let mut partitions = vec![TopicPartition::new("b", 0), TopicPartition::new("a", 2), TopicPartition::new("a", 1)];
+        partitions.sort();
+        assert_eq!(partitions, vec![TopicPartition::new("a", 1), TopicPartition::new("a", 2), TopicPartition::new("b", 0)]);
+    }
+
+    #[test]
+    fn test_show_with_context() {
+        let err: KafkaError = FromError::from_error((ProtocolMismatchError(3, 4), "Unexpected correlation ID"));
+        let err = err.with_context("Metadata", Some(String::from_str("localhost:9092")));
+
+        assert_eq!(format!("{}", err).as_slice(),
+                   "Metadata on broker localhost:9092 failed: Unexpected correlation ID (expected 3, got 4). \
+                    The connection is out of step with the broker and should be reopened.");
+    }
+
+    #[test]
+    fn test_timeout() {
+        let err: KafkaError = FromError::from_error(io::standard_error(io::TimedOut));
+        assert_eq!(err.kind, TimeoutError);
+    }
+
+    #[test]
+    fn test_broker_error() {
+        let err: KafkaError = FromError::from_error(protocol::NotLeaderForPartition);
+        assert_eq!(err.kind, BrokerError(protocol::NotLeaderForPartition));
+        assert_eq!(format!("{}", err).as_slice(),
+                   "The broker returned an error (NotLeaderForPartition). \
+                    Refresh metadata and retry against the current leader.");
+    }
+}
@@ -0,0 +1,219 @@
+pub const PRODUCE: i16 = 0;
+pub const FETCH: i16 = 1;
+pub const OFFSETS: i16 = 2;
+pub const METADATA: i16 = 3;
+pub const OFFSET_COMMIT: i16 = 8;
+pub const OFFSET_FETCH: i16 = 9;
+pub const CONSUMER_METADATA: i16 = 10;
+pub const JOIN_GROUP: i16 = 11;
+pub const HEARTBEAT: i16 = 12;
+pub const LEAVE_GROUP: i16 = 13;
+pub const SYNC_GROUP: i16 = 14;
+pub const DESCRIBE_GROUPS: i16 = 15;
+pub const LIST_GROUPS: i16 = 16;
+pub const API_VERSIONS: i16 = 18;
+pub const CREATE_TOPICS: i16 = 19;
+pub const DELETE_TOPICS: i16 = 20;
+pub const DELETE_RECORDS: i16 = 21;
+pub const DESCRIBE_CONFIGS: i16 = 32;
+pub const ALTER_CONFIGS: i16 = 33;
+pub const CREATE_PARTITIONS: i16 = 37;
+
+/// The versions of one API, as supported by this crate or reported by a broker.
+#[deriving(Show, PartialEq, Eq, Clone)]
+pub struct ApiVersionRange {
+    pub api_key: i16,
+    pub min_version: i16,
+    pub max_version: i16
+}
+
+impl ApiVersionRange {
+    pub fn contains(&self, version: i16) -> bool {
+        self.min_version <= version && version <= self.max_version
+    }
+}
+
+pub static SUPPORTED_VERSIONS: &'static [ApiVersionRange] = &[
+    ApiVersionRange { api_key: PRODUCE, min_version: 0, max_version: 0 },
+    ApiVersionRange { api_key: FETCH, min_version: 0, max_version: 0 },
+    ApiVersionRange { api_key: OFFSETS, min_version: 0, max_version: 1 },
+    ApiVersionRange { api_key: METADATA, min_version: 0, max_version: 1 },
+    ApiVersionRange { api_key: OFFSET_COMMIT, min_version: 1, max_version: 1 },
+    ApiVersionRange { api_key: OFFSET_FETCH, min_version: 1, max_version: 1 },
+    ApiVersionRange { api_key: CONSUMER_METADATA, min_version: 0, max_version: 0 },
+    ApiVersionRange { api_key: JOIN_GROUP, min_version: 0, max_version: 1 },
+    ApiVersionRange { api_key: HEARTBEAT, min_version: 0, max_version: 0 },
+    ApiVersionRange { api_key: SYNC_GROUP, min_version: 0, max_version: 0 },
+    ApiVersionRange { api_key: DESCRIBE_GROUPS, min_version: 0, max_version: 0 },
+    ApiVersionRange { api_key: LIST_GROUPS, min_version: 0, max_version: 0 },
+    ApiVersionRange { api_key: API_VERSIONS, min_version: 0, max_version: 0 },
+    ApiVersionRange { api_key: CREATE_TOPICS, min_version: 1, max_version: 1 },
+    ApiVersionRange { api_key: DELETE_TOPICS, min_version: 0, max_version: 0 },
+    ApiVersionRange { api_key: DELETE_RECORDS, min_version: 0, max_version: 0 },
+    ApiVersionRange { api_key: DESCRIBE_CONFIGS, min_version: 0, max_version: 0 },
+    ApiVersionRange { api_key: ALTER_CONFIGS, min_version: 0, max_version: 0 },
+    ApiVersionRange { api_key: CREATE_PARTITIONS, min_version: 0, max_version: 0 },
+];
+
+/// What a 0.8.2 broker supports. Brokers before 0.10 don't answer
+/// ApiVersions, closing the connection instead, so this has to be assumed.
+pub static KAFKA_0_8_2: &'static [ApiVersionRange] = &[
+    ApiVersionRange { api_key: PRODUCE, min_version: 0, max_version: 0 },
+    ApiVersionRange { api_key: FETCH, min_version: 0, max_version: 0 },
+    ApiVersionRange { api_key: OFFSETS, min_version: 0, max_version: 0 },
+    ApiVersionRange { api_key: METADATA, min_version: 0, max_version: 0 },
+    ApiVersionRange { api_key: OFFSET_COMMIT, min_version: 0, max_version: 1 },
+    ApiVersionRange { api_key: OFFSET_FETCH, min_version: 0, max_version: 1 },
+    ApiVersionRange { api_key: CONSUMER_METADATA, min_version: 0, max_version: 0 },
+];
+
+/// What a 0.9 broker supports, group membership APIs included.
+pub static KAFKA_0_9: &'static [ApiVersionRange] = &[
+    ApiVersionRange { api_key: PRODUCE, min_version: 0, max_version: 1 },
+    ApiVersionRange { api_key: FETCH, min_version: 0, max_version: 1 },
+    ApiVersionRange { api_key: OFFSETS, min_version: 0, max_version: 0 },
+    ApiVersionRange { api_key: METADATA, min_version: 0, max_version: 0 },
+    ApiVersionRange { api_key: OFFSET_COMMIT, min_version: 0, max_version: 2 },
+    ApiVersionRange { api_key: OFFSET_FETCH, min_version: 0, max_version: 1 },
+    ApiVersionRange { api_key: CONSUMER_METADATA, min_version: 0, max_version: 0 },
+    ApiVersionRange { api_key: JOIN_GROUP, min_version: 0, max_version: 0 },
+    ApiVersionRange { api_key: HEARTBEAT, min_version: 0, max_version: 0 },
+    ApiVersionRange { api_key: LEAVE_GROUP, min_version: 0, max_version: 0 },
+    ApiVersionRange { api_key: SYNC_GROUP, min_version: 0, max_version: 0 },
+    ApiVersionRange { api_key: DESCRIBE_GROUPS, min_version: 0, max_version: 0 },
+    ApiVersionRange { api_key: LIST_GROUPS, min_version: 0, max_version: 0 },
+];
+
+/// How the client learns which API versions a broker supports.
+#[deriving(Show, Clone)]
+pub enum VersionProbe {
+    /// Ask with ApiVersions, and if the broker drops the connection rather
+    /// than answer, take it to support the versions given.
+    ProbeVersions(&'static [ApiVersionRange]),
+    /// Never ask, for clusters known to predate ApiVersions.
+    AssumeVersions(&'static [ApiVersionRange])
+}
+
+fn find(versions: &[ApiVersionRange], api_key: i16) -> Option<&ApiVersionRange> {
+    versions.iter().find(|range| range.api_key == api_key)
+}
+
+/// The name Kafka gives `api_key`, e.g. "Fetch", as errors name the request
+/// that failed.
+pub fn api_name(api_key: i16) -> &'static str {
+    match api_key {
+        PRODUCE => "Produce",
+        FETCH => "Fetch",
+        OFFSETS => "ListOffsets",
+        METADATA => "Metadata",
+        OFFSET_COMMIT => "OffsetCommit",
+        OFFSET_FETCH => "OffsetFetch",
+        CONSUMER_METADATA => "FindCoordinator",
+        JOIN_GROUP => "JoinGroup",
+        HEARTBEAT => "Heartbeat",
+        LEAVE_GROUP => "LeaveGroup",
+        SYNC_GROUP => "SyncGroup",
+        DESCRIBE_GROUPS => "DescribeGroups",
+        LIST_GROUPS => "ListGroups",
+        API_VERSIONS => "ApiVersions",
+        CREATE_TOPICS => "CreateTopics",
+        DELETE_TOPICS => "DeleteTopics",
+        DELETE_RECORDS => "DeleteRecords",
+        DESCRIBE_CONFIGS => "DescribeConfigs",
+        ALTER_CONFIGS => "AlterConfigs",
+        CREATE_PARTITIONS => "CreatePartitions",
+        _ => "Request"
+    }
+}
+
+/// The versions of `api_key` this crate can encode and decode.
+pub fn supported_versions(api_key: i16) -> Option<ApiVersionRange> {
+    find(SUPPORTED_VERSIONS, api_key).map(|range| range.clone())
+}
+
+/// The highest version of `api_key` supported by both this crate and a
+/// broker advertising `broker_versions`.
+pub fn negotiate(api_key: i16, broker_versions: &[ApiVersionRange]) -> Option<i16> {
+    match (find(SUPPORTED_VERSIONS, api_key), find(broker_versions, api_key)) {
+        (Some(ours), Some(theirs)) => {
+            let version = if ours.max_version < theirs.max_version { ours.max_version } else { theirs.max_version };
+            if ours.contains(version) && theirs.contains(version) {
+                Some(version)
+            } else {
+                None
+            }
+        },
+        _ => None
+    }
+}
+
+/// Whether the broker understands record batches (the v2 message format),
+/// which arrived with Produce v3 and Fetch v4.
+pub fn supports_record_batches(broker_versions: &[ApiVersionRange]) -> bool {
+    supports(broker_versions, PRODUCE, 3) && supports(broker_versions, FETCH, 4)
+}
+
+/// Whether the broker understands static group membership, which arrived
+/// with JoinGroup v5, SyncGroup v3 and Heartbeat v3 (Kafka 2.3). These are
+/// only spoken to such brokers, for members with a `group_instance_id`.
+pub fn supports_static_membership(broker_versions: &[ApiVersionRange]) -> bool {
+    supports(broker_versions, JOIN_GROUP, 5) && supports(broker_versions, SYNC_GROUP, 3) &&
+        supports(broker_versions, HEARTBEAT, 3)
+}
+
+fn supports(broker_versions: &[ApiVersionRange], api_key: i16, version: i16) -> bool {
+    match find(broker_versions, api_key) {
+        Some(range) => range.max_version >= version,
+        None => false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_negotiate() {
+        let broker = [
+            ApiVersionRange { api_key: METADATA, min_version: 0, max_version: 5 },
+            ApiVersionRange { api_key: FETCH, min_version: 1, max_version: 6 },
+        ];
+        assert_eq!(negotiate(METADATA, broker.as_slice()), Some(1));
+        assert_eq!(negotiate(FETCH, broker.as_slice()), None);
+        assert_eq!(negotiate(PRODUCE, broker.as_slice()), None);
+    }
+
+    #[test]
+    fn test_api_name() {
+        assert_eq!(api_name(FETCH), "Fetch");
+        assert_eq!(api_name(CONSUMER_METADATA), "FindCoordinator");
+        assert_eq!(api_name(99), "Request");
+    }
+
+    #[test]
+    fn test_supports_record_batches() {
+        let old = [
+            ApiVersionRange { api_key: PRODUCE, min_version: 0, max_version: 2 },
+            ApiVersionRange { api_key: FETCH, min_version: 0, max_version: 3 },
+        ];
+        let new = [
+            ApiVersionRange { api_key: PRODUCE, min_version: 0, max_version: 7 },
+            ApiVersionRange { api_key: FETCH, min_version: 0, max_version: 10 },
+        ];
+        assert!(!supports_record_batches(old.as_slice()));
+        assert!(supports_record_batches(new.as_slice()));
+    }
+
+    #[test]
+    fn test_supports_static_membership() {
+        let new = [
+            ApiVersionRange { api_key: JOIN_GROUP, min_version: 0, max_version: 5 },
+            ApiVersionRange { api_key: HEARTBEAT, min_version: 0, max_version: 3 },
+            ApiVersionRange { api_key: SYNC_GROUP, min_version: 0, max_version: 3 },
+        ];
+        assert!(supports_static_membership(new.as_slice()));
+        assert!(!supports_static_membership(KAFKA_0_9));
+        assert_eq!(negotiate(JOIN_GROUP, new.as_slice()), Some(1));
+        assert_eq!(negotiate(JOIN_GROUP, KAFKA_0_9), Some(0));
+    }
+}